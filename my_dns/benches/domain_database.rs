@@ -0,0 +1,83 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use my_dns::dns_structs::{
+    dns_domain_name::Domain,
+    dns_message::{DNSEntry, QueryType},
+    dns_resource_data::RData,
+    domain_database_struct::DomainDatabase,
+};
+
+// Numero de records da zona usada nos benchmarks
+const ZONE_SIZE: u32 = 100_000;
+
+fn name(name: &str) -> Domain {
+    Domain::new(name.to_string())
+}
+
+// Zona example.com com ZONE_SIZE records: um A por nome, metade dos nomes com um TXT e alguns
+// CNAMEs, repartidos por subdominios para haver empty non-terminals
+fn zone() -> DomainDatabase {
+    let mut db = DomainDatabase::new();
+    db.authority = true;
+    db.soa_entries.domain_name = name("example.com.");
+    db.add_entry(DNSEntry::new(
+        name("example.com."),
+        3600,
+        RData::NS(name("ns1.example.com.")),
+    ));
+    let mut added = 1;
+    let mut host = 0;
+    while added < ZONE_SIZE {
+        let owner = name(&format!("host{}.zone{}.example.com.", host, host % 100));
+        let address = [10, (host >> 16) as u8, (host >> 8) as u8, host as u8];
+        db.add_entry(DNSEntry::new(
+            owner.to_owned(),
+            3600,
+            RData::A(address.into()),
+        ));
+        added += 1;
+        if host % 2 == 0 && added < ZONE_SIZE {
+            let text = RData::TXT(vec![format!("host={}", host).into_bytes()]);
+            db.add_entry(DNSEntry::new(owner.to_owned(), 3600, text));
+            added += 1;
+        }
+        if host % 10 == 0 && added < ZONE_SIZE {
+            let alias = name(&format!("alias{}.example.com.", host));
+            db.add_entry(DNSEntry::new(alias, 3600, RData::CNAME(owner)));
+            added += 1;
+        }
+        host += 1;
+    }
+    db
+}
+
+fn bench_lookups(c: &mut Criterion) {
+    let db = zone();
+    let existing = name("host4242.zone42.example.com.");
+    let missing = name("missing.zone42.example.com.");
+
+    c.bench_function("get_domain_query A hit", |b| {
+        b.iter(|| db.get_domain_query(QueryType::A, black_box(&existing)))
+    });
+    c.bench_function("get_domain_query A miss", |b| {
+        b.iter(|| db.get_domain_query(QueryType::A, black_box(&missing)))
+    });
+    c.bench_function("get_rrset TXT", |b| {
+        b.iter(|| db.get_rrset(black_box(&existing), QueryType::TXT))
+    });
+    c.bench_function("has_domain empty non-terminal", |b| {
+        b.iter(|| db.has_domain(black_box(&name("zone42.example.com."))))
+    });
+    c.bench_function("get_wildcard_for", |b| {
+        b.iter(|| db.get_wildcard_for(black_box(&missing)))
+    });
+}
+
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zone");
+    group.sample_size(10);
+    group.bench_function("build 100k records", |b| b.iter(zone));
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookups, bench_build);
+criterion_main!(benches);
@@ -1,3 +1,4 @@
 pub mod sp;
 pub mod ss;
 pub mod sr;
+pub mod validator;
@@ -1,18 +1,24 @@
 use log::{debug, error, info};
 use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use std::{
-    collections::HashMap,
     io::{Read, Write},
     net::{TcpListener, TcpStream},
 };
 
+use crate::dns_make::dns_dnssec::{self, ZoneKey};
+use crate::dns_make::{dns_recv, dns_send, dns_tsig, dns_wire};
+use crate::dns_parse::domain_database_parse;
 use crate::dns_structs::dns_domain_name::Domain;
 use crate::dns_structs::server_config::ServerConfig;
-use crate::dns_structs::{dns_message::DNSEntry, domain_database_struct::DomainDatabase};
+use crate::dns_structs::{dns_message::QueryType, zone_tree::ZoneTree};
 
-pub fn db_sync_listener(db: HashMap<Domain, DomainDatabase>, config: ServerConfig) {
+// Intervalo entre verificacoes do ficheiro de uma zona assinada
+const ZONE_SIGNER_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn db_sync_listener(db: Arc<Mutex<ZoneTree>>, config: ServerConfig) {
     let default_listener = "0.0.0.0:8000";
     let listener = match TcpListener::bind(default_listener) {
         Ok(lst) => lst,
@@ -35,8 +41,9 @@ pub fn db_sync_listener(db: HashMap<Domain, DomainDatabase>, config: ServerConfi
                     .collect::<Vec<IpAddr>>()
                     .contains(&incoming_addr.ip())
                 {
-                    let new_db = db.clone();
-                    thread::spawn(move || db_sync_handler(&mut stream, new_db));
+                    let new_db = Arc::clone(&db);
+                    let config_clone = config.clone();
+                    thread::spawn(move || db_sync_handler(&mut stream, new_db, &config_clone));
                 } else {
                     debug!("EZ denied-zone-transfer {} SP", incoming_addr.to_string(),);
                 }
@@ -47,21 +54,65 @@ pub fn db_sync_listener(db: HashMap<Domain, DomainDatabase>, config: ServerConfi
     }
 }
 
-fn db_sync_handler(stream: &mut TcpStream, db: HashMap<Domain, DomainDatabase>) {
-    // ler dominio pedido na stream
+fn db_sync_handler(stream: &mut TcpStream, db: Arc<Mutex<ZoneTree>>, config: &ServerConfig) {
+    // ler o pedido (pergunta SOA do dominio, possivelmente assinada com TSIG) na stream
     // enviar numero de entries da db desse dominio
     let now = Instant::now();
     let peer_addr = stream.peer_addr().unwrap();
-    let mut buf = [0u8; 1000];
     let mut total_bytes_transfered = 0;
-    let mut byte_num = stream.read(&mut buf).unwrap();
-    total_bytes_transfered += byte_num;
+    let request = match dns_recv::read_tcp_pdu(stream) {
+        Ok(request) => request,
+        Err(_) => {
+            debug!("EZ {} SP invalid-request", peer_addr);
+            return;
+        }
+    };
+    total_bytes_transfered += 2 + request.len();
 
-    let domain_name_bin = buf[0..byte_num].to_vec();
-    let domain_name = Domain::new(String::from_utf8(domain_name_bin).unwrap());
+    let (request, tsig) =
+        match dns_tsig::verify_message(&request, &config.get_tsig_keys(), None, dns_tsig::now()) {
+            Ok(Some((message, context))) => (message, Some(context)),
+            Ok(None) => (request, None),
+            Err(_) => {
+                debug!("EZ {} SP invalid-request", peer_addr);
+                return;
+            }
+        };
+    let domain_name = match dns_wire::decode(&request) {
+        Ok(message) => message.data.query_info.name,
+        Err(_) => {
+            debug!("EZ {} SP invalid-request", peer_addr);
+            return;
+        }
+    };
+    if let Some(err) = tsig.as_ref().and_then(|context| context.get_error()) {
+        debug!(
+            "EZ {} SP {} {}",
+            peer_addr,
+            err.event(),
+            domain_name.to_string()
+        );
+        return;
+    }
+    // com chave de transferencia configurada so se aceitam pedidos assinados com essa chave
+    let signing_key = tsig.as_ref().and_then(|context| context.verified_key());
+    if let Some(key_name) = config.get_transfer_key_name(&domain_name) {
+        if !signing_key.is_some_and(|key| key.is_named(&key_name)) {
+            debug!(
+                "EZ {} SP tsig-required {}",
+                peer_addr,
+                domain_name.to_string()
+            );
+            return;
+        }
+    }
+    // tudo o que se envia a partir daqui e coberto pelo MAC final
+    let mut signed_data: Vec<u8> = Vec::new();
 
-    let domain_db = match db.get(&domain_name) {
-        Some(ddb) => ddb,
+    // copia da zona, para que a transferencia nao bloqueie as respostas nem veja uma zona a meio
+    // de ser assinada de novo
+    let domain_db = match db.lock().unwrap().get(&domain_name) {
+        Some(ddb) => ddb.to_owned(),
         None => {
             debug!("EZ {} SP", peer_addr);
             return;
@@ -73,6 +124,7 @@ fn db_sync_handler(stream: &mut TcpStream, db: HashMap<Domain, DomainDatabase>)
     let serial: u32 = soas.get_serial_value();
     let serial_buf = serial.to_ne_bytes();
     stream.write(&serial_buf).unwrap();
+    signed_data.extend_from_slice(&serial_buf);
     // receber byte de confirmacao 0 para nao enviar, 1 caso contrario
     let mut confirm_byte = [0u8; 1];
     stream.read_exact(&mut confirm_byte).unwrap();
@@ -82,74 +134,104 @@ fn db_sync_handler(stream: &mut TcpStream, db: HashMap<Domain, DomainDatabase>)
         return;
     }
 
-    let mut entries_to_send: Vec<DNSEntry> = Vec::new();
     // get all SOA
-    entries_to_send.push(soas.primary_ns);
-    entries_to_send.push(soas.contact_email);
-    entries_to_send.push(soas.serial);
-    entries_to_send.push(soas.refresh);
-    entries_to_send.push(soas.retry);
-    entries_to_send.push(soas.expire);
+    let mut lines_to_send: Vec<String> = soas.get_zone_lines();
 
     // get all ns entries
-    for ns_records in domain_db.get_ns_records().values() {
+    for ns_records in domain_db.ns_records.values() {
         for entry in ns_records {
-            entries_to_send.push(entry.to_owned());
-        }
-    }
-
-    // get all A record
-    if let Some(a_records) = domain_db.get_a_records() {
-        for entry in a_records {
-            entries_to_send.push(entry);
+            lines_to_send.push(entry.get_string());
         }
     }
 
-    // get all A record
-    if let Some(cname_records) = domain_db.get_cname_records() {
-        for entry in cname_records {
-            entries_to_send.push(entry);
+    // get all records, os DNSSEC tal como foram assinados pelo SP
+    for query_type in [
+        QueryType::A,
+        QueryType::AAAA,
+        QueryType::CNAME,
+        QueryType::MX,
+        QueryType::PTR,
+        QueryType::SRV,
+        QueryType::TXT,
+        QueryType::DNSKEY,
+        QueryType::RRSIG,
+        QueryType::NSEC,
+        QueryType::DS,
+    ] {
+        for entry in domain_db.records_of(query_type) {
+            lines_to_send.push(entry.get_string());
         }
     }
-
-    // get all mx records
-    if let Some(mx_records) = domain_db.get_mx_records() {
-        for entry in mx_records {
-            entries_to_send.push(entry);
-        }
-    }
-
-    // get all ptr records
-    if let Some(ptr_records) = domain_db.get_ptr_records() {
-        for entry in ptr_records {
-            entries_to_send.push(entry);
+    // sequence number u16 antes de enviar: uma zona com mais linhas nao pode ser transferida
+    let entry_num: u16 = match lines_to_send.len().try_into() {
+        Ok(num) => num,
+        Err(_) => {
+            debug!(
+                "EZ {} SP too-many-entries {}",
+                peer_addr,
+                domain_name.to_string()
+            );
+            return;
         }
-    }
-    // to string em todas as entries
-    // sequence number u16 antes de enviar
-    let entry_num: u16 = entries_to_send.len().try_into().unwrap();
+    };
 
     let mut entry_num_bin = [0u8, 2];
     entry_num_bin[0] = (entry_num >> 8) as u8;
     entry_num_bin[1] = entry_num as u8;
     stream.write(&entry_num_bin).unwrap();
+    signed_data.extend_from_slice(&entry_num_bin);
 
-    byte_num = stream.read(&mut entry_num_bin).unwrap();
-    total_bytes_transfered += byte_num;
+    if stream.read_exact(&mut entry_num_bin).is_err() {
+        debug!("EZ {} SP connection-fail", peer_addr);
+        return;
+    }
+    total_bytes_transfered += entry_num_bin.len();
 
     let _recived_entry_num = (entry_num_bin[0] as u16 * 256) + entry_num_bin[1] as u16;
     let mut seq_number: u16 = 0;
     let mut ebuf: Vec<u8> = Vec::new();
     stream.set_nodelay(true).unwrap();
-    for entry in entries_to_send {
+    // cada linha vai com o numero de sequencia (u16) e o tamanho da linha (u16) antes do texto,
+    // para que o ss saiba onde acaba cada linha sem depender de como o TCP parte a stream
+    for line in lines_to_send {
+        let line_bytes = line.as_bytes();
+        let line_len: u16 = match line_bytes.len().try_into() {
+            Ok(len) => len,
+            Err(_) => {
+                debug!("EZ {} SP line-too-long", peer_addr);
+                return;
+            }
+        };
         ebuf.push((seq_number >> 8) as u8);
         ebuf.push(seq_number as u8);
-        ebuf.append(&mut entry.get_string().as_bytes().to_vec());
+        ebuf.push((line_len >> 8) as u8);
+        ebuf.push(line_len as u8);
+        ebuf.extend_from_slice(line_bytes);
         stream.write_all(ebuf.as_slice()).unwrap();
         stream.flush().unwrap();
+        total_bytes_transfered += ebuf.len();
+        signed_data.extend_from_slice(&ebuf);
         ebuf.clear();
         seq_number += 1;
-        thread::sleep(Duration::new(0, 100000));
+    }
+
+    // pedido assinado: termina com a hora e o MAC dos dados, encadeado no MAC do pedido
+    if let (Some(key), Some(context)) = (signing_key, &tsig) {
+        let time_signed = dns_tsig::now();
+        let mut trailer = time_signed.to_be_bytes()[2..].to_vec();
+        trailer.extend(dns_tsig::sign_data(
+            key,
+            context.get_mac(),
+            &signed_data,
+            time_signed,
+        ));
+        match dns_send::write_tcp_pdu(stream, &trailer) {
+            Ok(num_bytes) => total_bytes_transfered += num_bytes,
+            Err(_) => {
+                debug!("EZ {} SP connection-fail", peer_addr);
+                return;
+            }
+        }
     }
 
     debug!(
@@ -159,3 +241,60 @@ fn db_sync_handler(stream: &mut TcpStream, db: HashMap<Domain, DomainDatabase>)
         total_bytes_transfered
     );
 }
+
+// Volta a ler o ficheiro de uma zona assinada e assina-a de novo quando o serial do ficheiro muda
+// ou quando as assinaturas estao perto de expirar. Cada nova assinatura aumenta o serial servido,
+// para que os SS voltem a transferir a zona com as assinaturas novas
+pub fn zone_signer(
+    domain_name: Domain,
+    db_path: String,
+    ksk: ZoneKey,
+    zsk: ZoneKey,
+    db: Arc<Mutex<ZoneTree>>,
+) {
+    let (mut file_serial, mut served_serial) = match db.lock().unwrap().get(&domain_name) {
+        Some(ddb) => (
+            ddb.get_soa_records().get_serial_value(),
+            ddb.get_soa_records().get_serial_value(),
+        ),
+        None => return,
+    };
+    loop {
+        thread::sleep(ZONE_SIGNER_INTERVAL);
+        let mut zone = match domain_database_parse::get(db_path.to_owned()) {
+            Ok(zone) => zone,
+            Err(_) => {
+                error!("SP @ db-file-read-fail {}", domain_name.to_string());
+                continue;
+            }
+        };
+        let now = dns_dnssec::now();
+        let serial_changed = zone.get_soa_records().get_serial_value() != file_serial;
+        let expiring = match db.lock().unwrap().get(&domain_name) {
+            Some(ddb) => dns_dnssec::needs_resign(ddb, now),
+            None => true,
+        };
+        if !serial_changed && !expiring {
+            continue;
+        }
+
+        file_serial = zone.get_soa_records().get_serial_value();
+        served_serial = if serial_changed && file_serial > served_serial {
+            file_serial
+        } else {
+            served_serial.wrapping_add(1)
+        };
+        zone.soa_entries.serial = served_serial;
+        match dns_dnssec::sign_zone(&mut zone, &ksk, &zsk, now) {
+            Ok(()) => {
+                info!(
+                    "EV @ zone-signed {} {}",
+                    domain_name.to_string(),
+                    served_serial
+                );
+                db.lock().unwrap().insert(domain_name.to_owned(), zone);
+            }
+            Err(err) => error!("SP @ {} {}", err, domain_name.to_string()),
+        }
+    }
+}
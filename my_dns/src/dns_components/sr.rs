@@ -1,98 +1,631 @@
 use std::{
-    net::{SocketAddr, UdpSocket},
-    time::Duration,
+    collections::HashMap,
+    net::{SocketAddr, TcpStream, UdpSocket},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use log::{debug, error, info};
+use rand::random;
 
-
+use super::validator::{self, Chain, TrustAnchor};
 use crate::{
     dns_make::{
+        dns_dnssec,
         dns_recv::{self, RecvError},
-        dns_send,
+        dns_send::{self, EDNS_UDP_PAYLOAD},
+        dns_tls::{self, TlsConnector},
+        dns_tsig::{self, TsigKey},
+        dns_wire::{self, PduFormat},
+    },
+    dns_structs::{
+        dns_domain_name::Domain,
+        dns_message::{DNSMessage, DNSQueryInfo, Flags, QueryType, ResponseCode},
+        dns_resource_data::{Edns, RData},
     },
-    dns_structs::dns_message::DNSMessage,
 };
 
+// Numero maximo de CNAMEs seguidos numa cadeia, tanto pelo servidor como pelo resolver
+pub const MAX_CNAME_CHAIN: usize = 8;
+
+// Transporte usado pelo resolver para falar com os servidores
+#[derive(Clone, Debug)]
+pub enum Transport {
+    // UDP, repetindo a pergunta por TCP se a resposta vier truncada
+    Udp,
+    // So TCP, com o tamanho da mensagem em 2 bytes
+    Tcp,
+    // TCP cifrado com TLS (RFC 7858), com o mesmo enquadramento do TCP
+    Tls(TlsConnector),
+}
+
+// Como o resolver fala com os servidores
+#[derive(Clone, Debug)]
+pub struct ResolverOptions {
+    pub transport: Transport,
+    // O nome da pergunta leva maiusculas e minusculas aleatorias (0x20), que a resposta tem de
+    // repetir, para dificultar respostas forjadas
+    pub randomize_case: bool,
+    // As perguntas sao assinadas com esta chave (TSIG) e so se aceitam respostas assinadas por ela
+    pub tsig_key: Option<TsigKey>,
+    // Chaves DNSSEC ja validadas, partilhadas por todas as copias das opcoes
+    pub key_cache: KeyCache,
+}
+
+// Chaves DNSKEY validadas, pela zona e pelos DS (ou DNSKEY) que as validaram, e quando expiram
+type CachedKeys = HashMap<(Domain, Vec<RData>), (Vec<RData>, Instant)>;
+
+// Cache das chaves validadas por zone_keys, para nao pedir e validar de novo as chaves da raiz
+// (e das zonas abaixo) em cada pergunta. Cada entrada dura o menor TTL do RRset DNSKEY
+#[derive(Clone, Debug, Default)]
+pub struct KeyCache {
+    keys: Arc<Mutex<CachedKeys>>,
+}
+
+impl KeyCache {
+    pub fn new() -> Self {
+        KeyCache::default()
+    }
+
+    fn get(&self, zone: &Domain, anchors: &[RData]) -> Option<Vec<RData>> {
+        let mut keys = self.keys.lock().unwrap();
+        let entry = (zone.to_owned(), anchors.to_vec());
+        match keys.get(&entry) {
+            Some((zone_keys, expires)) if Instant::now() < *expires => Some(zone_keys.to_owned()),
+            Some(_) => {
+                keys.remove(&entry);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, zone: &Domain, anchors: &[RData], zone_keys: Vec<RData>, ttl: u32) {
+        let expires = Instant::now() + Duration::from_secs(ttl.into());
+        let entry = (zone.to_owned(), anchors.to_vec());
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(entry, (zone_keys, expires));
+    }
+}
+
+impl From<Transport> for ResolverOptions {
+    fn from(transport: Transport) -> Self {
+        ResolverOptions {
+            transport,
+            randomize_case: false,
+            tsig_key: None,
+            key_cache: KeyCache::new(),
+        }
+    }
+}
+
 pub fn resolver(
     dns_query: &mut DNSMessage,
     server_list: Vec<SocketAddr>,
     supports_recursive: bool,
 ) -> Result<DNSMessage, &'static str> {
-    // Inicializar a socket UDP
-    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
-    socket.set_write_timeout(Some(Duration::new(1, 0))).unwrap();
+    resolver_over(dns_query, server_list, supports_recursive, Transport::Udp)
+}
+
+pub fn resolver_over(
+    dns_query: &mut DNSMessage,
+    server_list: Vec<SocketAddr>,
+    supports_recursive: bool,
+    transport: Transport,
+) -> Result<DNSMessage, &'static str> {
+    resolver_with(dns_query, server_list, supports_recursive, transport.into())
+}
+
+pub fn resolver_with(
+    dns_query: &mut DNSMessage,
+    server_list: Vec<SocketAddr>,
+    supports_recursive: bool,
+    options: ResolverOptions,
+) -> Result<DNSMessage, &'static str> {
+    let response = resolve(
+        dns_query,
+        server_list.to_owned(),
+        supports_recursive,
+        &options,
+        None,
+    )?;
+    follow_cnames(dns_query, response, |target_query| {
+        resolve(
+            target_query,
+            server_list.to_owned(),
+            supports_recursive,
+            &options,
+            None,
+        )
+    })
+}
+
+// Resolve a pergunta validando as respostas com DNSSEC a partir da ancora de confianca, cuja zona
+// e servida pelos servidores da lista. Respostas que falham a validacao sao trocadas por um
+// SERVFAIL e as respostas validadas levam a flag AD
+pub fn validating_resolver(
+    dns_query: &mut DNSMessage,
+    server_list: Vec<SocketAddr>,
+    supports_recursive: bool,
+    options: ResolverOptions,
+    anchor: &TrustAnchor,
+) -> Result<DNSMessage, &'static str> {
+    let response = validate_from_anchor(
+        dns_query,
+        server_list.to_owned(),
+        supports_recursive,
+        &options,
+        anchor,
+    )?;
+    // Cada destino da cadeia e validado desde a ancora, porque pode estar noutra zona
+    follow_cnames(dns_query, response, |target_query| {
+        validate_from_anchor(
+            target_query,
+            server_list.to_owned(),
+            supports_recursive,
+            &options,
+            anchor,
+        )
+    })
+}
 
+fn validate_from_anchor(
+    dns_query: &mut DNSMessage,
+    server_list: Vec<SocketAddr>,
+    supports_recursive: bool,
+    options: &ResolverOptions,
+    anchor: &TrustAnchor,
+) -> Result<DNSMessage, &'static str> {
+    if !supports_recursive {
+        dns_query.header.flags.remove(Flags::RECURSIVE)
+    };
+    let zone = anchor.get_zone();
+    let chain = match zone_keys(
+        dns_query,
+        &zone,
+        &anchor.get_anchors(),
+        &server_list,
+        options,
+    ) {
+        Ok(keys) => Chain::Secure { zone, keys },
+        Err(err) => return Ok(servfail_answer(dns_query, err)),
+    };
+    resolve(
+        dns_query,
+        server_list,
+        supports_recursive,
+        options,
+        Some(chain),
+    )
+}
+
+// Quando a cadeia de CNAMEs da resposta sai da zona do servidor, o destino fica sem resposta e e
+// perguntado de novo desde o inicio. As respostas sao juntadas numa so, com a pergunta original.
+// Os ciclos param em get_cname_target e as cadeias longas em MAX_CNAME_CHAIN perguntas
+fn follow_cnames<F>(
+    dns_query: &DNSMessage,
+    mut response: DNSMessage,
+    mut resolve_target: F,
+) -> Result<DNSMessage, &'static str>
+where
+    F: FnMut(&mut DNSMessage) -> Result<DNSMessage, &'static str>,
+{
+    for _ in 0..MAX_CNAME_CHAIN {
+        let target = match response.get_cname_target() {
+            Some(target) => target,
+            None => return Ok(response),
+        };
+        debug!("EV @ following-cname {}", target.to_string());
+        let mut target_query = dns_query.to_owned();
+        target_query.header.message_id = random();
+        target_query.data.query_info.name = target;
+        let target_response = resolve_target(&mut target_query)?;
+        response = merge_cname_response(response, target_response);
+    }
+    if response.get_cname_target().is_some() {
+        error!(
+            "ER cname-chain-too-long {}",
+            dns_query.data.query_info.name.to_string()
+        );
+    }
+    Ok(response)
+}
+
+// Junta a resposta do destino do CNAME a resposta anterior. O codigo de resposta e as autoridades
+// passam a ser os do destino, e a flag AD so fica se as duas respostas foram validadas
+fn merge_cname_response(mut response: DNSMessage, target_response: DNSMessage) -> DNSMessage {
+    let mut answers = response.data.response_values.take().unwrap_or_default();
+    answers.extend(target_response.data.response_values.unwrap_or_default());
+    response.header.number_of_values = answers.len().try_into().ok();
+    response.data.response_values = Some(answers);
+
+    response.header.response_code = target_response.header.response_code;
+    response.header.number_of_authorities = target_response.header.number_of_authorities;
+    response.data.authorities_values = target_response.data.authorities_values;
+    response.header.number_of_extra_values = target_response.header.number_of_extra_values;
+    response.data.extra_values = target_response.data.extra_values;
+    if !target_response.header.flags.contains(Flags::AUTHENTICATED) {
+        response.header.flags.remove(Flags::AUTHENTICATED);
+    }
+    response
+}
+
+fn resolve(
+    dns_query: &mut DNSMessage,
+    server_list: Vec<SocketAddr>,
+    supports_recursive: bool,
+    options: &ResolverOptions,
+    chain: Option<Chain>,
+) -> Result<DNSMessage, &'static str> {
     if !supports_recursive {
-        dns_query.header.flags -= 2
+        dns_query.header.flags.remove(Flags::RECURSIVE)
     };
 
+    let (dns_recv_message, server_ip) =
+        query_servers(dns_query, &server_list, options, chain.is_some())?;
+    final_answer(
+        dns_query,
+        dns_recv_message,
+        server_ip,
+        supports_recursive,
+        options,
+        chain,
+    )
+}
+
+// Envia a pergunta a cada servidor da lista ate um responder. Com DNSSEC a pergunta leva o bit DO
+fn query_servers(
+    dns_query: &DNSMessage,
+    server_list: &[SocketAddr],
+    options: &ResolverOptions,
+    dnssec_ok: bool,
+) -> Result<(DNSMessage, SocketAddr), &'static str> {
     if server_list.is_empty() {
         return Err("Empty server list provided");
     };
 
-    for server_ip in server_list {
-        let _size_sent = match dns_send::send(dns_query.to_owned(), &socket, server_ip.to_string())
-        {
-            Ok(size_sent) => size_sent,
+    // A pergunta enviada anuncia EDNS(0) para receber respostas UDP maiores que 512 bytes
+    let mut edns_query = dns_query.to_owned();
+    let mut edns = Edns::new(EDNS_UDP_PAYLOAD);
+    edns.dnssec_ok = dnssec_ok;
+    edns_query.set_edns(edns);
+
+    for server_ip in server_list.iter().copied() {
+        let mut dns_recv_message = match query_server(&edns_query, server_ip, options) {
+            Ok(response) => response,
             Err(err) => {
-                panic!("{err}");
+                error!("TO {} {}", server_ip.to_owned(), err);
+                //When server doesn't respond back, we go to the next loop iteration
+                continue;
             }
         };
+        // O OPT e so da ligacao com este servidor, nao faz parte da resposta
+        dns_recv_message.take_edns();
+        return Ok((dns_recv_message, server_ip));
+    }
+    return Err("No servers answered your query");
+}
 
-        let (dns_recv_message, _src_addr) = match dns_recv::recv(&socket) {
-            Ok(response) => response,
-            Err(err) => match err {
-                RecvError::IOError(_io_error) => {
-                    error!("TO {} invalid-socket-address", server_ip.to_owned());
-                    //When server doesn't respond back, we go to the next loop iteration
-                    continue;
-                }
-                RecvError::DeserializeError(_deserialize_error) => {
-                    error!("ER pdu-deserialize-fail {}", server_ip.to_owned());
-                    panic!("Could not decode received DNSMessage");
-                }
-            },
-        };
+fn query_server(
+    edns_query: &DNSMessage,
+    server_ip: SocketAddr,
+    options: &ResolverOptions,
+) -> Result<DNSMessage, &'static str> {
+    let mut sent_query = edns_query.to_owned();
+    if options.randomize_case {
+        sent_query.data.query_info.name = randomize_case(&edns_query.data.query_info.name);
+    }
+    let exact_case = options.randomize_case;
+    let key = options.tsig_key.as_ref();
+    let mut dns_recv_message = match &options.transport {
+        Transport::Udp => udp_query(&sent_query, server_ip, exact_case, key)?,
+        Transport::Tcp => tcp_query(&sent_query, server_ip, exact_case, key)?,
+        Transport::Tls(connector) => tls_query(&sent_query, server_ip, connector, exact_case, key)?,
+    };
+    dns_recv_message.data.query_info.name = edns_query.data.query_info.name.to_owned();
+    Ok(dns_recv_message)
+}
 
-        match eval_and_respond(dns_query, dns_recv_message,supports_recursive) {
-            Ok(msg) => {
-                info!(
-                    "RR {} dns-msg-received: {}",
-                    server_ip.to_owned(),
-                    msg.get_string()
-                );
-                info!("SP 127.0.0.1 received-final-answer");
-                return Ok(msg);
-            }
-            Err(err) => {
-                error!("SP 127.0.0.1 {}", err);
-                panic!("Received Invalid Answer")
-            }
+// Codifica a pergunta e, com uma chave TSIG, assina-a. O MAC da pergunta e devolvido para
+// verificar a resposta; como cobre os bytes RFC 1035, uma pergunta assinada nunca vai em bincode
+fn query_pdu(
+    dns_query: &DNSMessage,
+    format: PduFormat,
+    tsig_key: Option<&TsigKey>,
+) -> Result<(Vec<u8>, Option<Vec<u8>>), &'static str> {
+    let key = match tsig_key {
+        Some(key) => key,
+        None => match dns_send::encode_pdu(dns_query, format) {
+            Ok(pdu) => return Ok((pdu, None)),
+            Err(_) => return Err("pdu-serialize-fail"),
+        },
+    };
+    let mut pdu = match dns_wire::encode(dns_query) {
+        Ok(pdu) => pdu,
+        Err(_) => return Err("pdu-serialize-fail"),
+    };
+    match dns_tsig::sign_request(&mut pdu, key, dns_tsig::now()) {
+        Ok(request_mac) => Ok((pdu, Some(request_mac))),
+        Err(_) => Err("tsig-sign-fail"),
+    }
+}
+
+// Tira e verifica o TSIG de uma resposta recebida por TCP ou TLS a uma pergunta assinada
+fn response_pdu(
+    pdu: Vec<u8>,
+    tsig_key: Option<&TsigKey>,
+    request_mac: Option<&[u8]>,
+    server_ip: SocketAddr,
+) -> Result<Vec<u8>, &'static str> {
+    match tsig_key.zip(request_mac) {
+        Some((key, mac)) => dns_tsig::verify_response(&pdu, key, mac, dns_tsig::now())
+            .inspect_err(|reason| error!("ER {} {}", reason, server_ip)),
+        None => Ok(pdu),
+    }
+}
+
+// A pergunta vai no formato RFC 1035, com o OPT e o nome com 0x20. Um servidor antigo, que so
+// percebe bincode, responde com FORMERR ou com algo que nao se consegue ler, e entao a pergunta e
+// repetida em bincode. Com TSIG nao ha repeticao, porque o MAC cobre os bytes RFC 1035
+fn udp_query(
+    dns_query: &DNSMessage,
+    server_ip: SocketAddr,
+    exact_case: bool,
+    tsig_key: Option<&TsigKey>,
+) -> Result<DNSMessage, &'static str> {
+    let response = udp_exchange(
+        dns_query,
+        server_ip,
+        PduFormat::Standard,
+        exact_case,
+        tsig_key,
+    );
+    let legacy_peer = match response {
+        Ok(ref message) => message.header.response_code == Some(ResponseCode::Malformed),
+        Err(err) => err == "pdu-deserialize-fail",
+    };
+    let dns_recv_message = if legacy_peer && tsig_key.is_none() {
+        debug!("EV @ standard-query-rejected-retry-legacy {}", server_ip);
+        udp_exchange(dns_query, server_ip, PduFormat::Legacy, exact_case, None)?
+    } else {
+        response?
+    };
+
+    // A resposta nao coube num datagrama UDP, a pergunta e repetida por TCP
+    if dns_recv_message.header.flags.contains(Flags::TRUNCATED) {
+        debug!("EV @ truncated-response-retry-tcp {}", server_ip);
+        return tcp_query(dns_query, server_ip, exact_case, tsig_key);
+    }
+    Ok(dns_recv_message)
+}
+
+// Envia a pergunta num datagrama UDP, no formato pedido, e espera pela resposta do servidor
+fn udp_exchange(
+    dns_query: &DNSMessage,
+    server_ip: SocketAddr,
+    format: PduFormat,
+    exact_case: bool,
+    tsig_key: Option<&TsigKey>,
+) -> Result<DNSMessage, &'static str> {
+    // Inicializar a socket UDP da familia de enderecos do servidor (IPv4 ou IPv6)
+    let bind_addr = if server_ip.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = match UdpSocket::bind(bind_addr) {
+        Ok(socket) => socket,
+        Err(_) => return Err("socket-bind-fail"),
+    };
+    socket.set_write_timeout(Some(Duration::new(1, 0))).unwrap();
+
+    let (pdu, request_mac) = query_pdu(dns_query, format, tsig_key)?;
+    if pdu.len() > EDNS_UDP_PAYLOAD as usize || socket.send_to(&pdu, server_ip).is_err() {
+        return Err("send-fail");
+    }
+
+    // So e aceite a resposta do servidor a esta pergunta; o resto e descartado ate passar o tempo
+    match dns_recv::recv_response(
+        &socket,
+        dns_query,
+        server_ip,
+        Duration::new(1, 0),
+        exact_case,
+        tsig_key.zip(request_mac.as_deref()),
+    ) {
+        Ok(response) => Ok(response),
+        Err(RecvError::IOError(_)) => Err("invalid-socket-address"),
+        Err(_) => Err("pdu-deserialize-fail"),
+    }
+}
+
+// Cada letra do nome passa a maiuscula ou minuscula ao acaso (draft-vixie-dnsext-dns0x20)
+fn randomize_case(name: &Domain) -> Domain {
+    let parts = name
+        .getparts()
+        .iter()
+        .map(|label| {
+            label
+                .chars()
+                .map(|c| match random::<bool>() {
+                    true => c.to_ascii_uppercase(),
+                    false => c.to_ascii_lowercase(),
+                })
+                .collect()
+        })
+        .collect();
+    let mut randomized = Domain::new_empty();
+    randomized.set_parts(parts);
+    randomized
+}
+
+// Pede as DNSKEY da zona aos seus servidores e valida-as com os DS da zona pai (ou da ancora).
+// As chaves validadas ficam na cache das opcoes ate acabar o seu TTL
+fn zone_keys(
+    dns_query: &DNSMessage,
+    zone: &Domain,
+    anchors: &[RData],
+    server_list: &[SocketAddr],
+    options: &ResolverOptions,
+) -> Result<Vec<RData>, &'static str> {
+    if let Some(keys) = options.key_cache.get(zone, anchors) {
+        debug!("EV @ dnssec-keys-cached {}", zone.to_string());
+        return Ok(keys);
+    }
+    let mut key_query = dns_query.to_owned();
+    key_query.data.query_info = DNSQueryInfo {
+        name: zone.to_owned(),
+        type_of_value: QueryType::DNSKEY,
+    };
+    let (response, server_ip) = query_servers(&key_query, server_list, options, true)?;
+    let keys = validator::trusted_keys(zone, &response, anchors, dns_dnssec::now())?;
+    debug!(
+        "EV @ dnssec-keys-trusted {} {}",
+        zone.to_string(),
+        server_ip
+    );
+    let ttl = response
+        .data
+        .response_values
+        .iter()
+        .flatten()
+        .filter(|entry| entry.domain_name == *zone && matches!(entry.data, RData::DNSKEY { .. }))
+        .map(|entry| entry.ttl)
+        .min()
+        .unwrap_or(0);
+    options
+        .key_cache
+        .insert(zone, anchors, keys.to_owned(), ttl);
+    Ok(keys)
+}
+
+fn final_answer(
+    dns_query: &mut DNSMessage,
+    dns_recv_message: DNSMessage,
+    server_ip: SocketAddr,
+    supports_recursive: bool,
+    options: &ResolverOptions,
+    chain: Option<Chain>,
+) -> Result<DNSMessage, &'static str> {
+    match eval_and_respond(
+        dns_query,
+        dns_recv_message,
+        supports_recursive,
+        options,
+        chain,
+    ) {
+        Ok(msg) => {
+            info!(
+                "RR {} dns-msg-received: {}",
+                server_ip.to_owned(),
+                msg.get_string()
+            );
+            info!("SP 127.0.0.1 received-final-answer");
+            Ok(msg)
+        }
+        Err(err) => {
+            error!("SP 127.0.0.1 {}", err);
+            Err(err)
         }
     }
-    return Err("No servers answered your query");
+}
+
+fn tcp_query(
+    dns_query: &DNSMessage,
+    server_ip: SocketAddr,
+    exact_case: bool,
+    tsig_key: Option<&TsigKey>,
+) -> Result<DNSMessage, &'static str> {
+    let mut stream = match TcpStream::connect_timeout(&server_ip, Duration::new(1, 0)) {
+        Ok(stream) => stream,
+        Err(_) => return Err("tcp-connect-fail"),
+    };
+    stream.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    stream.set_write_timeout(Some(Duration::new(1, 0))).unwrap();
+
+    let (pdu, request_mac) = query_pdu(dns_query, PduFormat::Standard, tsig_key)?;
+    if dns_send::write_tcp_pdu(&mut stream, &pdu).is_err() {
+        return Err("tcp-send-fail");
+    }
+    let pdu = match dns_recv::read_tcp_pdu(&mut stream) {
+        Ok(pdu) => response_pdu(pdu, tsig_key, request_mac.as_deref(), server_ip)?,
+        Err(_) => return Err("tcp-receive-fail"),
+    };
+    match dns_recv::decode_pdu(&pdu) {
+        Ok((response, _format)) => checked_response(dns_query, response, server_ip, exact_case),
+        Err(_) => Err("tcp-pdu-deserialize-fail"),
+    }
+}
+
+fn tls_query(
+    dns_query: &DNSMessage,
+    server_ip: SocketAddr,
+    connector: &TlsConnector,
+    exact_case: bool,
+    tsig_key: Option<&TsigKey>,
+) -> Result<DNSMessage, &'static str> {
+    let (pdu, request_mac) = query_pdu(dns_query, PduFormat::Standard, tsig_key)?;
+    let mut stream = connector.connect(server_ip, Duration::new(1, 0))?;
+
+    if dns_send::write_tcp_pdu(&mut stream, &pdu).is_err() {
+        return Err("tls-send-fail");
+    }
+    let pdu = dns_recv::read_tcp_pdu(&mut stream);
+    dns_tls::close(&mut stream);
+    let pdu = match pdu {
+        Ok(pdu) => response_pdu(pdu, tsig_key, request_mac.as_deref(), server_ip)?,
+        Err(_) => return Err("tls-receive-fail"),
+    };
+    match dns_recv::decode_pdu(&pdu) {
+        Ok((response, _format)) => checked_response(dns_query, response, server_ip, exact_case),
+        Err(_) => Err("tls-pdu-deserialize-fail"),
+    }
+}
+
+// Por TCP e TLS so chega uma resposta, que tem de corresponder a pergunta como no UDP
+fn checked_response(
+    dns_query: &DNSMessage,
+    response: DNSMessage,
+    server_ip: SocketAddr,
+    exact_case: bool,
+) -> Result<DNSMessage, &'static str> {
+    match dns_recv::response_mismatch(dns_query, &response, exact_case) {
+        Some(reason) => {
+            error!("ER {} {}", reason, server_ip);
+            Err(reason)
+        }
+        None => Ok(response),
+    }
 }
 
 fn eval_and_respond(
     dns_message: &mut DNSMessage,
     dns_recv_message: DNSMessage,
     supports_recursive: bool,
+    options: &ResolverOptions,
+    chain: Option<Chain>,
 ) -> Result<DNSMessage, &'static str> {
     let mut return_message = Ok(DNSMessage::new());
     if let Some(response_code) = dns_recv_message.header.response_code {
         match response_code {
-            // Codigo 0 => Mensagem de resposta valida
-            // Codigo 2 => domínio não existe.
-            // Codigo 3 => Malformed message.
-            0 | 2 | 3 => {
+            // Resposta final com valores ou a provar que nao existem, validada com DNSSEC
+            ResponseCode::NoError | ResponseCode::NXDomain => {
+                return_message = Ok(validate_answer(dns_message, dns_recv_message, &chain));
+            }
+            // Resposta final: mensagem mal formada ou o servidor nao conseguiu/recusou responder
+            ResponseCode::Malformed
+            | ResponseCode::ServerFailure
+            | ResponseCode::Refused
+            | ResponseCode::BadVersion
+            | ResponseCode::NotAuth => {
                 return_message = Ok(dns_recv_message.clone());
             }
-            // Codigo 1 =>  domínio existe mas não foi obtida a resposta de um servidor de autoridade
-            1 => match dns_recv_message.data.authorities_values {
+            // domínio existe mas não foi obtida a resposta de um servidor de autoridade
+            ResponseCode::NonAuthoritative => {
                 // Existe pelo menos um servidor de autoridade para o dominio na resposta recebida
-                Some(ref auth_values) => {
+                if let Some(ref auth_values) = dns_recv_message.data.authorities_values {
                     debug!("EV @ non-authoritative-msg-received");
 
                     let ip_vec = match DNSMessage::get_authorities_ip(
@@ -102,17 +635,146 @@ fn eval_and_respond(
                         auth_values.to_vec(),
                     ) {
                         Some(vec) => vec,
-                        None => {
-                            panic!("No NS found for the queried domain, cannot get answer")
-                        }
+                        // Delegacao sem enderecos (glue) para os servidores de autoridade
+                        None => return Err("no-ns-address-found"),
                     };
 
-                    return_message = resolver(dns_message, ip_vec, supports_recursive);
+                    let chain = match follow_referral(
+                        dns_message,
+                        &dns_recv_message,
+                        chain,
+                        &ip_vec,
+                        options,
+                    ) {
+                        Ok(chain) => chain,
+                        Err(err) => return Ok(servfail_answer(dns_message, err)),
+                    };
+                    return_message =
+                        resolve(dns_message, ip_vec, supports_recursive, options, chain);
                 }
-                None => {}
-            },
-            _ => return Err("response-code-invalid"),
+            }
         }
     }
     return_message
 }
+
+// Ao seguir uma delegacao a cadeia segura passa para a zona filha, cujas chaves sao validadas com
+// o DS da delegacao. Uma delegacao sem DS torna o resto da resolucao insegura
+fn follow_referral(
+    dns_message: &DNSMessage,
+    referral: &DNSMessage,
+    chain: Option<Chain>,
+    server_list: &[SocketAddr],
+    options: &ResolverOptions,
+) -> Result<Option<Chain>, &'static str> {
+    let (zone, keys) = match chain {
+        Some(Chain::Secure { zone, keys }) => (zone, keys),
+        other => return Ok(other),
+    };
+    match validator::check_referral(&zone, &keys, referral, dns_dnssec::now())? {
+        Some((child, ds)) => {
+            let keys = zone_keys(dns_message, &child, &ds, server_list, options)?;
+            Ok(Some(Chain::Secure { zone: child, keys }))
+        }
+        None => {
+            debug!("EV @ dnssec-insecure-delegation {}", zone.to_string());
+            Ok(Some(Chain::Insecure))
+        }
+    }
+}
+
+// Com a cadeia segura a resposta so e aceite se validar com as chaves da zona. Sem validacao, ou
+// abaixo de uma delegacao insegura, a resposta nunca leva a flag AD
+fn validate_answer(
+    dns_message: &DNSMessage,
+    mut dns_recv_message: DNSMessage,
+    chain: &Option<Chain>,
+) -> DNSMessage {
+    dns_recv_message.header.flags.remove(Flags::AUTHENTICATED);
+    if let Some(Chain::Secure { zone, keys }) = chain {
+        if let Err(err) = validator::check_answer(zone, keys, &dns_recv_message, dns_dnssec::now())
+        {
+            return servfail_answer(dns_message, err);
+        }
+        debug!(
+            "EV @ dnssec-secure-answer {}",
+            dns_message.data.query_info.name.to_string()
+        );
+        if let Err(err) = dns_recv_message.header.flags.insert(Flags::AUTHENTICATED) {
+            return servfail_answer(dns_message, err);
+        }
+    }
+    dns_recv_message
+}
+
+// Resposta SERVFAIL para dados que falharam a validacao (RFC 4035 5.5) ou para uma pergunta que
+// o resolver nao conseguiu responder
+pub fn servfail_answer(dns_message: &DNSMessage, err: &str) -> DNSMessage {
+    error!(
+        "ER {} {}",
+        err,
+        dns_message.data.query_info.name.to_string()
+    );
+    let mut response = DNSMessage::new();
+    response.header.message_id = dns_message.header.message_id;
+    response.header.flags = Flags::empty();
+    response.header.response_code = Some(ResponseCode::ServerFailure);
+    response.data.query_info = dns_message.data.query_info.to_owned();
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_structs::dns_message::DNSEntry;
+
+    #[test]
+    fn test_key_cache() {
+        let cache = KeyCache::new();
+        let root = Domain::new(".".to_string());
+        let anchors = [RData::A([10, 0, 0, 1].into())];
+        let keys = vec![RData::A([10, 0, 0, 2].into())];
+
+        cache.insert(&root, &anchors, keys.to_owned(), 3600);
+        assert_eq!(cache.get(&root, &anchors), Some(keys.to_owned()));
+        // As copias das opcoes partilham a cache
+        assert_eq!(cache.clone().get(&root, &anchors), Some(keys.to_owned()));
+        // Chaves validadas com outros DS, ou de outra zona, nao servem
+        assert_eq!(cache.get(&root, &[]), None);
+        assert_eq!(
+            cache.get(&Domain::new("example.".to_string()), &anchors),
+            None
+        );
+
+        // Com o TTL acabado as chaves sao pedidas de novo
+        cache.insert(&root, &anchors, keys, 0);
+        assert_eq!(cache.get(&root, &anchors), None);
+        assert!(cache.keys.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_referral_without_glue() {
+        let name = |name: &str| Domain::new(name.to_string());
+        let mut query = DNSMessage::new();
+        query.data.query_info.name = name("www.example.com.");
+        let mut referral = DNSMessage::new();
+        referral.header.response_code = Some(ResponseCode::NonAuthoritative);
+        referral.data.authorities_values = Some(vec![DNSEntry::new(
+            name("example.com."),
+            3600,
+            RData::NS(name("ns1.example.com.")),
+        )]);
+
+        // Sem enderecos para os servidores da delegacao a resposta e um erro, nao um panic
+        let options = ResolverOptions::from(Transport::Udp);
+        let answer = final_answer(
+            &mut query,
+            referral,
+            "127.0.0.1:53".parse().unwrap(),
+            false,
+            &options,
+            None,
+        );
+        assert_eq!(answer, Err("no-ns-address-found"));
+    }
+}
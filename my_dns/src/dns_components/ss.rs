@@ -1,5 +1,4 @@
 use std::{
-    collections::HashMap,
     io::{Read, Write},
     net::{SocketAddr, TcpStream},
     string::String,
@@ -11,10 +10,17 @@ use std::{
 use log::debug;
 
 use crate::{
+    dns_make::{
+        dns_recv, dns_send,
+        dns_tsig::{self, TsigKey},
+        dns_wire,
+    },
     dns_parse::domain_database_parse,
     dns_structs::{
         dns_domain_name::Domain,
+        dns_message::{DNSMessage, Flags, QueryType},
         domain_database_struct::{DomainDatabase, SOA},
+        zone_tree::ZoneTree,
     },
 };
 
@@ -23,12 +29,15 @@ enum ZoneTransferError {
     SERIAL,
     PARSEERR,
     CONERR,
+    // O SP nao assinou a transferencia com a chave TSIG do dominio
+    AuthErr,
 }
 
 pub fn db_sync(
     domain_name: Domain,
     sp_addr: SocketAddr,
-    db: Arc<Mutex<HashMap<Domain, DomainDatabase>>>,
+    db: Arc<Mutex<ZoneTree>>,
+    tsig_key: Option<TsigKey>,
 ) {
     // initial sync
     let mut domain_db: Option<DomainDatabase>;
@@ -41,7 +50,7 @@ pub fn db_sync(
     // antes disto tem de existir uma initial sync para poder existir isto
     // SOASERIAL?? nao esquecer de checkar onde isso vai
     loop {
-        match zone_transfer(&domain_name, sp_addr, serial) {
+        match zone_transfer(&domain_name, sp_addr, serial, tsig_key.as_ref()) {
             Ok(domain_db) => {
                 if !initial_flag {
                     soas = domain_db.get_soa_records();
@@ -61,7 +70,7 @@ pub fn db_sync(
             Err(ZoneTransferError::CONERR) => {
                 thread::sleep(Duration::from_secs(retry));
             }
-            Err(ZoneTransferError::PARSEERR) => {
+            Err(ZoneTransferError::PARSEERR) | Err(ZoneTransferError::AuthErr) => {
                 thread::sleep(Duration::from_secs(retry));
             }
         }
@@ -72,6 +81,7 @@ fn zone_transfer(
     domain_name: &Domain,
     sp_addr: SocketAddr,
     serial: u32,
+    tsig_key: Option<&TsigKey>,
 ) -> Result<DomainDatabase, ZoneTransferError> {
     let now = Instant::now();
 
@@ -83,14 +93,45 @@ fn zone_transfer(
             // panic!("Could't connect to addr {}", sp_addr);
         }
     };
-    // enviar o nome do dominio pretendido
-    stream.write(domain_name.to_string().as_bytes()).unwrap();
+    // pedir o dominio com uma pergunta SOA em formato RFC 1035, assinada com TSIG se o dominio
+    // tiver chave de transferencia
+    let mut soa_query = DNSMessage::new();
+    soa_query.header.flags = Flags::QUERY;
+    soa_query.data.query_info.name = domain_name.to_owned();
+    soa_query.data.query_info.type_of_value = QueryType::SOA;
+    let mut request = match dns_wire::encode(&soa_query) {
+        Ok(request) => request,
+        Err(_err) => {
+            debug!("EZ {} SS invalid-domain-name", sp_addr);
+            return Err(ZoneTransferError::PARSEERR);
+        }
+    };
+    let request_mac = match tsig_key {
+        Some(key) => match dns_tsig::sign_request(&mut request, key, dns_tsig::now()) {
+            Ok(mac) => Some(mac),
+            Err(_err) => {
+                debug!("EZ {} SS tsig-sign-fail", sp_addr);
+                return Err(ZoneTransferError::AuthErr);
+            }
+        },
+        None => None,
+    };
+    if dns_send::write_tcp_pdu(&mut stream, &request).is_err() {
+        debug!("EZ {} SS connection-fail", sp_addr);
+        return Err(ZoneTransferError::CONERR);
+    }
     let mut total_bytes_transfered = 0;
-    let mut buf = [0u8; 1000];
+    // tudo o que o SP envia depois do pedido e coberto pelo MAC final
+    let mut signed_data: Vec<u8> = Vec::new();
     // receber o SERIAL - MUDAR ISTO NO SP
     let mut serial_buf = [0u8; 4];
     total_bytes_transfered += 4;
-    stream.read_exact(&mut serial_buf);
+    if stream.read_exact(&mut serial_buf).is_err() {
+        // o SP fecha a ligacao sem responder quando recusa o pedido
+        debug!("EZ {} SS denied-zone-transfer", sp_addr);
+        return Err(ZoneTransferError::CONERR);
+    }
+    signed_data.extend_from_slice(&serial_buf);
     let received_serial = u32::from_ne_bytes(serial_buf);
 
     if received_serial == serial {
@@ -108,29 +149,84 @@ fn zone_transfer(
     stream.write(&[1u8]);
 
     // recebe as entries que existem
-    total_bytes_transfered += stream.read(&mut buf).unwrap();
+    let mut entries_buf = [0u8; 2];
+    if stream.read_exact(&mut entries_buf).is_err() {
+        debug!("EZ {} SS connection-fail", sp_addr);
+        return Err(ZoneTransferError::CONERR);
+    }
+    total_bytes_transfered += 2;
+    signed_data.extend_from_slice(&entries_buf);
 
-    let entries: u16 = (buf[0].to_owned() as u16 * 256) + buf[1].to_owned() as u16;
+    let entries: u16 = (entries_buf[0] as u16 * 256) + entries_buf[1] as u16;
 
-    // confirmacao resolver isto ...
-    stream.write(&mut buf).unwrap();
-    let mut unparsed_db: Vec<String> = Vec::with_capacity(entries.clone().into());
+    // confirmacao: devolve o numero de entries recebido
+    if stream.write_all(&entries_buf).is_err() {
+        debug!("EZ {} SS connection-fail", sp_addr);
+        return Err(ZoneTransferError::CONERR);
+    }
+    let mut unparsed_db: Vec<String> = vec![String::new(); entries.into()];
 
-    // codificao primeiros 2 bytes sao o numero de ordem da entry o resto e do tipo Entry
+    // codificacao: 2 bytes com o numero de ordem da entry, 2 bytes com o tamanho da linha e a linha
     for _i in 0..entries {
-        let num_bytes = match stream.read(&mut buf) {
-            Ok(bytes) => bytes,
-            Err(err) => panic!("{err}"),
-        };
-        total_bytes_transfered += num_bytes;
-        let seq_number: u16 = (buf[0] as u16 * 256) + buf[1] as u16;
+        let mut line_header = [0u8; 4];
+        if stream.read_exact(&mut line_header).is_err() {
+            debug!("EZ {} SS connection-fail", sp_addr);
+            return Err(ZoneTransferError::CONERR);
+        }
+        let seq_number: u16 = (line_header[0] as u16 * 256) + line_header[1] as u16;
+        let line_len: u16 = (line_header[2] as u16 * 256) + line_header[3] as u16;
 
-        let line_bin = buf[2..num_bytes - 2].to_vec();
+        let mut line_bin = vec![0u8; line_len.into()];
+        if stream.read_exact(&mut line_bin).is_err() {
+            debug!("EZ {} SS connection-fail", sp_addr);
+            return Err(ZoneTransferError::CONERR);
+        }
+        total_bytes_transfered += 4 + line_bin.len();
+        signed_data.extend_from_slice(&line_header);
+        signed_data.extend_from_slice(&line_bin);
 
-        let mut line = String::from_utf8(line_bin).unwrap().to_owned();
+        let mut line = match String::from_utf8(line_bin) {
+            Ok(line) => line,
+            Err(_err) => {
+                debug!("EZ {} SS invalid-line", sp_addr);
+                return Err(ZoneTransferError::PARSEERR);
+            }
+        };
         line.push('\n');
-        unparsed_db.insert(seq_number.to_owned().into(), line);
+        match unparsed_db.get_mut(seq_number as usize) {
+            Some(slot) => *slot = line,
+            None => {
+                debug!("EZ {} SS invalid-sequence-number {}", sp_addr, seq_number);
+                return Err(ZoneTransferError::PARSEERR);
+            }
+        }
+    }
+    // o SP termina com a hora e o MAC de todos os dados enviados, encadeado no MAC do pedido
+    if let (Some(key), Some(request_mac)) = (tsig_key, request_mac) {
+        let trailer = match dns_recv::read_tcp_pdu(&mut stream) {
+            Ok(trailer) if trailer.len() > 6 => trailer,
+            _ => {
+                debug!("EZ {} SS tsig-missing-signature", sp_addr);
+                return Err(ZoneTransferError::AuthErr);
+            }
+        };
+        total_bytes_transfered += 2 + trailer.len();
+        let time_signed = trailer[..6]
+            .iter()
+            .fold(0u64, |time, byte| (time << 8) | *byte as u64);
+        if let Err(err) = dns_tsig::verify_data(
+            key,
+            &request_mac,
+            &signed_data,
+            time_signed,
+            &trailer[6..],
+            dns_tsig::now(),
+        ) {
+            debug!("EZ {} SS {}", sp_addr, err.event());
+            return Err(ZoneTransferError::AuthErr);
+        }
     }
+
     let mut db_txt: String = String::new();
 
     for line in unparsed_db {
@@ -0,0 +1,589 @@
+use crate::{
+    dns_make::{
+        dns_dnssec::{self, label_count, nsec_covers, verify_rrsig, wildcard_of},
+        dns_wire::{self, TYPE_CNAME},
+    },
+    dns_parse::domain_database_parse,
+    dns_structs::{
+        dns_domain_name::Domain,
+        dns_message::{DNSEntry, DNSMessage, QueryType, ResponseCode},
+        dns_resource_data::RData,
+    },
+};
+
+// Ancora de confianca do resolver: os DS ou DNSKEY de uma zona (normalmente a raiz) em que se
+// confia sem validacao e a partir dos quais se valida o resto da arvore
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrustAnchor {
+    zone: Domain,
+    anchors: Vec<RData>,
+}
+
+// Estado da validacao ao descer pelas delegacoes
+#[derive(Clone, Debug, PartialEq)]
+pub enum Chain {
+    // Zona cujas chaves foram validadas a partir da ancora
+    Secure { zone: Domain, keys: Vec<RData> },
+    // Abaixo de uma delegacao provada sem DS: as respostas nao sao validadas
+    Insecure,
+}
+
+impl TrustAnchor {
+    // Le os DS e DNSKEY de um ficheiro no formato das bases de dados, com o TTL no fim da linha
+    pub fn from_file(path: &str) -> Result<Self, &'static str> {
+        let db = domain_database_parse::get(path.to_string())?;
+        let records: Vec<DNSEntry> = db
+            .records_of(QueryType::DS)
+            .chain(db.records_of(QueryType::DNSKEY))
+            .cloned()
+            .collect();
+        TrustAnchor::from_records(records)
+    }
+
+    // Todos os records tem de ser da mesma zona
+    pub fn from_records(records: Vec<DNSEntry>) -> Result<Self, &'static str> {
+        let zone = match records.first() {
+            Some(entry) => entry.domain_name.to_owned(),
+            None => return Err("Trust anchor without DS or DNSKEY records"),
+        };
+        if records.iter().any(|entry| entry.domain_name != zone) {
+            return Err("Trust anchor with records of more than one zone");
+        }
+        Ok(TrustAnchor {
+            zone,
+            anchors: records.into_iter().map(|entry| entry.data).collect(),
+        })
+    }
+
+    pub fn get_zone(&self) -> Domain {
+        self.zone.to_owned()
+    }
+
+    pub fn get_anchors(&self) -> Vec<RData> {
+        self.anchors.to_owned()
+    }
+}
+
+// Chaves de uma zona a partir da resposta a uma pergunta DNSKEY. O RRset tem de estar assinado por
+// uma chave que corresponde a um DS da zona pai ou da ancora (RFC 4035 5.2)
+pub fn trusted_keys(
+    zone: &Domain,
+    response: &DNSMessage,
+    anchors: &[RData],
+    now: u32,
+) -> Result<Vec<RData>, &'static str> {
+    let answers = response.data.response_values.to_owned().unwrap_or_default();
+    let rrset: Vec<DNSEntry> = answers
+        .iter()
+        .filter(|entry| matches!(entry.data, RData::DNSKEY { .. }) && entry.domain_name == *zone)
+        .cloned()
+        .collect();
+    if rrset.is_empty() {
+        return Err("dnssec-missing-dnskey");
+    }
+    let entry_points: Vec<RData> = rrset
+        .iter()
+        .map(|entry| entry.data.to_owned())
+        .filter(|dnskey| {
+            anchors
+                .iter()
+                .any(|anchor| matches_anchor(zone, dnskey, anchor))
+        })
+        .collect();
+    if verify_rrset(&rrset, &answers, zone, &entry_points, now) {
+        Ok(rrset.into_iter().map(|entry| entry.data).collect())
+    } else {
+        Err("dnssec-bogus-dnskey")
+    }
+}
+
+fn matches_anchor(zone: &Domain, dnskey: &RData, anchor: &RData) -> bool {
+    match anchor {
+        RData::DNSKEY { .. } => anchor == dnskey,
+        RData::DS { .. } => dns_dnssec::ds_for(zone, dnskey).is_ok_and(|ds| &ds == anchor),
+        _ => false,
+    }
+}
+
+// Numa delegacao a partir de uma zona segura, o DS assinado da zona filha continua a cadeia e um
+// NSEC assinado sem o tipo DS prova que a zona filha nao e assinada (RFC 4035 5.2)
+pub fn check_referral(
+    zone: &Domain,
+    keys: &[RData],
+    response: &DNSMessage,
+    now: u32,
+) -> Result<Option<(Domain, Vec<RData>)>, &'static str> {
+    let authorities = response
+        .data
+        .authorities_values
+        .to_owned()
+        .unwrap_or_default();
+    let cut = match authorities
+        .iter()
+        .find(|entry| matches!(entry.data, RData::NS(_)))
+    {
+        Some(entry) => entry.domain_name.to_owned(),
+        None => return Err("dnssec-bogus-referral"),
+    };
+    if !cut.is_subdomain_of(zone) || cut == *zone {
+        return Err("dnssec-bogus-referral");
+    }
+
+    let at_cut = |matches_type: fn(&RData) -> bool| -> Vec<DNSEntry> {
+        authorities
+            .iter()
+            .filter(|entry| matches_type(&entry.data) && entry.domain_name == cut)
+            .cloned()
+            .collect()
+    };
+    let ds = at_cut(|data| matches!(data, RData::DS { .. }));
+    if !ds.is_empty() {
+        return match verify_rrset(&ds, &authorities, zone, keys, now) {
+            true => Ok(Some((
+                cut,
+                ds.into_iter().map(|entry| entry.data).collect(),
+            ))),
+            false => Err("dnssec-bogus-ds"),
+        };
+    }
+    let nsec = at_cut(|data| matches!(data, RData::NSEC { .. }));
+    let proves_no_ds = nsec.iter().any(|entry| match &entry.data {
+        RData::NSEC { types, .. } => {
+            types.contains(&dns_wire::TYPE_NS) && !types.contains(&dns_wire::TYPE_DS)
+        }
+        _ => false,
+    });
+    match proves_no_ds && verify_rrset(&nsec, &authorities, zone, keys, now) {
+        true => Ok(None),
+        false => Err("dnssec-bogus-insecure-delegation"),
+    }
+}
+
+// Resposta final de uma zona segura: todos os RRsets das respostas e das autoridades tem de estar
+// assinados pela zona, e uma resposta sem valores ou gerada por um wildcard tem de ser provada
+// pelos NSEC (RFC 4035 5.4)
+pub fn check_answer(
+    zone: &Domain,
+    keys: &[RData],
+    response: &DNSMessage,
+    now: u32,
+) -> Result<(), &'static str> {
+    let answers = response.data.response_values.to_owned().unwrap_or_default();
+    let authorities = response
+        .data
+        .authorities_values
+        .to_owned()
+        .unwrap_or_default();
+    for section in [&answers, &authorities] {
+        if !rrsets(section)
+            .iter()
+            .all(|rrset| verify_rrset(rrset, section, zone, keys, now))
+        {
+            return Err("dnssec-bogus-rrset");
+        }
+    }
+
+    let queried_domain = &response.data.query_info.name;
+    let nsecs: Vec<&DNSEntry> = authorities
+        .iter()
+        .filter(|entry| matches!(entry.data, RData::NSEC { .. }))
+        .collect();
+    let is_covered = |name: &Domain| nsecs.iter().any(|nsec| nsec_covers(nsec, name));
+
+    if !answers.is_empty() {
+        let expanded = answers.iter().any(|entry| match entry.data {
+            RData::RRSIG { labels, .. } => labels < label_count(&entry.domain_name),
+            _ => false,
+        });
+        return match !expanded || is_covered(queried_domain) {
+            true => Ok(()),
+            false => Err("dnssec-bogus-wildcard"),
+        };
+    }
+
+    if response.header.response_code == Some(ResponseCode::NXDomain) {
+        // Um NSEC cobre o nome e outro cobre o wildcard do ancestral mais proximo que existe
+        let encloser = match nsecs.iter().find(|nsec| nsec_covers(nsec, queried_domain)) {
+            Some(nsec) => closest_encloser(queried_domain, nsec),
+            None => return Err("dnssec-bogus-nxdomain"),
+        };
+        return match is_covered(&wildcard_of(&encloser)) {
+            true => Ok(()),
+            false => Err("dnssec-bogus-nxdomain"),
+        };
+    }
+
+    // NODATA: o NSEC do nome nao tem o tipo pedido nem um CNAME. Se o nome nao existir, um NSEC
+    // cobre o nome e o NSEC do wildcard que o abrange nao tem o tipo (RFC 4035 3.1.3.4)
+    let query_type = dns_wire::type_code(response.data.query_info.type_of_value.to_owned());
+    let lacks_type = |owner: &Domain| {
+        nsecs.iter().any(|nsec| match &nsec.data {
+            RData::NSEC { types, .. } => {
+                nsec.domain_name == *owner
+                    && !types.contains(&query_type)
+                    && !types.contains(&TYPE_CNAME)
+            }
+            _ => false,
+        })
+    };
+    let proves_nodata = lacks_type(queried_domain)
+        || match nsecs.iter().find(|nsec| nsec_covers(nsec, queried_domain)) {
+            Some(nsec) => lacks_type(&wildcard_of(&closest_encloser(queried_domain, nsec))),
+            None => false,
+        };
+    match proves_nodata {
+        true => Ok(()),
+        false => Err("dnssec-bogus-nodata"),
+    }
+}
+
+// Um RRset e seguro se algum dos seus RRSIG, assinado pela zona, verifica com as chaves da zona
+fn verify_rrset(
+    rrset: &[DNSEntry],
+    section: &[DNSEntry],
+    zone: &Domain,
+    keys: &[RData],
+    now: u32,
+) -> bool {
+    let owner = &rrset[0].domain_name;
+    let type_code = dns_wire::rdata_type_code(&rrset[0].data);
+    section.iter().any(|entry| match &entry.data {
+        RData::RRSIG {
+            type_covered,
+            signer,
+            ..
+        } => {
+            *type_covered == type_code
+                && entry.domain_name == *owner
+                && signer == zone
+                && verify_rrsig(entry, rrset, keys, now)
+        }
+        _ => false,
+    })
+}
+
+// RRsets de uma seccao, agrupados por dono e tipo, sem os RRSIG e o OPT
+fn rrsets(section: &[DNSEntry]) -> Vec<Vec<DNSEntry>> {
+    let mut rrsets: Vec<Vec<DNSEntry>> = Vec::new();
+    for entry in section
+        .iter()
+        .filter(|entry| !matches!(entry.data, RData::RRSIG { .. } | RData::OPT(_)))
+    {
+        let type_code = dns_wire::rdata_type_code(&entry.data);
+        match rrsets.iter_mut().find(|rrset| {
+            rrset[0].domain_name == entry.domain_name
+                && dns_wire::rdata_type_code(&rrset[0].data) == type_code
+        }) {
+            Some(rrset) => rrset.push(entry.to_owned()),
+            None => rrsets.push(vec![entry.to_owned()]),
+        }
+    }
+    rrsets
+}
+
+// O ancestral mais proximo que existe e o maior ancestral do nome que tambem e ancestral do dono
+// ou do nome seguinte do NSEC que cobre o nome (RFC 5155 8.3 aplicado ao NSEC)
+fn closest_encloser(name: &Domain, nsec: &DNSEntry) -> Domain {
+    let next_domain = match &nsec.data {
+        RData::NSEC { next_domain, .. } => next_domain.to_owned(),
+        _ => Domain::new_empty(),
+    };
+    let parts = name.getparts();
+    for i in 1..parts.len() {
+        let mut ancestor = Domain::new_empty();
+        ancestor.set_parts(parts[i..].to_vec());
+        if nsec.domain_name.is_subdomain_of(&ancestor) || next_domain.is_subdomain_of(&ancestor) {
+            return ancestor;
+        }
+    }
+    Domain::new_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_make::dns_dnssec::{
+        add_dnssec_records, generate_pkcs8, now, sign_zone, ZoneKey, SEP_FLAG, ZONE_KEY_FLAG,
+    };
+    use crate::dns_structs::{dns_message::QueryType, domain_database_struct::DomainDatabase};
+
+    static ROOT: &str = "@ DEFAULT .
+@ SOASP ns.root. 3600
+@ SOAADMIN admin.root. 3600
+@ SOASERIAL 1 3600
+@ SOAREFRESH 14400 3600
+@ SOARETRY 3600 3600
+@ SOAEXPIRE 604800 3600
+@ SOAMINIMUM 300 3600
+@ NS ns.root. 3600
+example. NS ns.example. 3600
+plain. NS ns.plain. 3600
+ns.root. A 10.0.0.1 3600
+ns.example. A 10.0.0.2 3600
+ns.plain. A 10.0.0.3 3600
+";
+
+    static EXAMPLE: &str = "@ DEFAULT example.
+@ SOASP ns.example. 3600
+@ SOAADMIN admin.example. 3600
+@ SOASERIAL 1 3600
+@ SOAREFRESH 14400 3600
+@ SOARETRY 3600 3600
+@ SOAEXPIRE 604800 3600
+@ SOAMINIMUM 300 3600
+@ NS ns.example. 3600
+ns A 10.0.0.2 3600
+www A 10.0.0.4 3600
+";
+
+    struct SignedZone {
+        db: DomainDatabase,
+        ksk: ZoneKey,
+        zsk: ZoneKey,
+    }
+
+    fn sign(zone: String) -> SignedZone {
+        let mut db = domain_database_parse::parse_from_str(zone).unwrap();
+        let ksk =
+            ZoneKey::from_pkcs8(&generate_pkcs8().unwrap(), ZONE_KEY_FLAG | SEP_FLAG).unwrap();
+        let zsk = ZoneKey::from_pkcs8(&generate_pkcs8().unwrap(), ZONE_KEY_FLAG).unwrap();
+        sign_zone(&mut db, &ksk, &zsk, now()).unwrap();
+        SignedZone { db, ksk, zsk }
+    }
+
+    // Raiz assinada que delega para example. (assinada, com DS) e para plain. (sem DS)
+    fn signed_tree() -> (SignedZone, SignedZone) {
+        let example = sign(EXAMPLE.to_string());
+        let ds = dns_dnssec::ds_for(&name("example."), &example.ksk.get_dnskey()).unwrap();
+        let root = sign(format!("{}example. DS {} 3600\n", ROOT, ds.get_value()));
+        (root, example)
+    }
+
+    fn name(name: &str) -> Domain {
+        Domain::new(name.to_string())
+    }
+
+    // Resposta do servidor autoritativo da zona, com os records DNSSEC
+    fn answer(db: &DomainDatabase, queried: &str, query_type: QueryType) -> DNSMessage {
+        let mut response = DNSMessage::new();
+        response.data.query_info.name = name(queried);
+        response.data.query_info.type_of_value = query_type.to_owned();
+        let apex = db.get_soa_records().domain_name;
+        match db.get_domain_query(query_type, &name(queried)) {
+            Some(values) => {
+                response.header.response_code = Some(ResponseCode::NoError);
+                response.data.response_values = Some(values.into_owned());
+                response.data.authorities_values = db.ns_records.get(&apex).cloned();
+            }
+            None => match db.get_ns_of(&name(queried)) {
+                Some(ns) if ns.iter().all(|entry| entry.domain_name != apex) => {
+                    response.header.response_code = Some(ResponseCode::NonAuthoritative);
+                    response.data.authorities_values = Some(ns.to_vec());
+                }
+                _ => {
+                    let exists = db.has_domain(&name(queried))
+                        || db.get_wildcard_for(&name(queried)).is_some();
+                    response.header.response_code = match exists {
+                        true => Some(ResponseCode::NoError),
+                        false => Some(ResponseCode::NXDomain),
+                    };
+                    response.data.authorities_values =
+                        Some(vec![db.get_soa_records().get_negative_entry()]);
+                }
+            },
+        }
+        add_dnssec_records(&mut response, db);
+        response
+    }
+
+    fn root_keys(root: &SignedZone) -> Vec<RData> {
+        let anchor = TrustAnchor::from_records(vec![DNSEntry::new(
+            name("."),
+            3600,
+            dns_dnssec::ds_for(&name("."), &root.ksk.get_dnskey()).unwrap(),
+        )])
+        .unwrap();
+        trusted_keys(
+            &anchor.get_zone(),
+            &answer(&root.db, ".", QueryType::DNSKEY),
+            &anchor.get_anchors(),
+            now(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_trust_anchor() {
+        let (root, _) = signed_tree();
+        assert_eq!(
+            root_keys(&root),
+            vec![root.ksk.get_dnskey(), root.zsk.get_dnskey()]
+        );
+
+        // A ancora pode ser a propria DNSKEY, mas nao a ZSK, que nao assina o RRset DNSKEY
+        let dnskeys = answer(&root.db, ".", QueryType::DNSKEY);
+        assert!(trusted_keys(&name("."), &dnskeys, &[root.ksk.get_dnskey()], now()).is_ok());
+        assert_eq!(
+            trusted_keys(&name("."), &dnskeys, &[root.zsk.get_dnskey()], now()),
+            Err("dnssec-bogus-dnskey")
+        );
+        assert!(TrustAnchor::from_records(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_secure_delegation() {
+        let (root, example) = signed_tree();
+        let keys = root_keys(&root);
+
+        let referral = answer(&root.db, "www.example.", QueryType::A);
+        let (cut, ds) = check_referral(&name("."), &keys, &referral, now())
+            .unwrap()
+            .unwrap();
+        assert_eq!(cut, name("example."));
+
+        let example_keys = trusted_keys(
+            &cut,
+            &answer(&example.db, "example.", QueryType::DNSKEY),
+            &ds,
+            now(),
+        )
+        .unwrap();
+        let www = answer(&example.db, "www.example.", QueryType::A);
+        assert_eq!(check_answer(&cut, &example_keys, &www, now()), Ok(()));
+
+        // Chaves de outra zona nao validam a resposta
+        assert_eq!(
+            check_answer(&cut, &keys, &www, now()),
+            Err("dnssec-bogus-rrset")
+        );
+
+        // A delegacao sem DS e provada pelo NSEC
+        let plain = answer(&root.db, "www.plain.", QueryType::A);
+        assert_eq!(check_referral(&name("."), &keys, &plain, now()), Ok(None));
+    }
+
+    #[test]
+    fn test_negative_answers() {
+        let (_, example) = signed_tree();
+        let keys = vec![example.ksk.get_dnskey(), example.zsk.get_dnskey()];
+        let zone = name("example.");
+
+        let nxdomain = answer(&example.db, "xyz.example.", QueryType::A);
+        assert_eq!(nxdomain.header.response_code, Some(ResponseCode::NXDomain));
+        assert_eq!(check_answer(&zone, &keys, &nxdomain, now()), Ok(()));
+
+        let nodata = answer(&example.db, "www.example.", QueryType::MX);
+        assert_eq!(nodata.header.response_code, Some(ResponseCode::NoError));
+        assert_eq!(check_answer(&zone, &keys, &nodata, now()), Ok(()));
+
+        // Sem os NSEC a resposta negativa nao e provada
+        let mut stripped = nxdomain.to_owned();
+        stripped.data.authorities_values = Some(
+            nxdomain
+                .data
+                .authorities_values
+                .unwrap()
+                .into_iter()
+                .filter(|entry| {
+                    matches!(
+                        entry.data,
+                        RData::SOA { .. }
+                            | RData::RRSIG {
+                                type_covered: 6,
+                                ..
+                            }
+                    )
+                })
+                .collect(),
+        );
+        assert_eq!(
+            check_answer(&zone, &keys, &stripped, now()),
+            Err("dnssec-bogus-nxdomain")
+        );
+    }
+
+    #[test]
+    fn test_wildcard_answers() {
+        let example = sign(format!("{}*.wild A 10.0.0.7 3600\n", EXAMPLE));
+        let keys = vec![example.ksk.get_dnskey(), example.zsk.get_dnskey()];
+        let zone = name("example.");
+
+        let expanded = answer(&example.db, "host.wild.example.", QueryType::A);
+        let values = expanded.data.response_values.to_owned().unwrap();
+        assert!(values
+            .iter()
+            .all(|entry| entry.domain_name == name("host.wild.example.")));
+        assert_eq!(check_answer(&zone, &keys, &expanded, now()), Ok(()));
+
+        let nodata = answer(&example.db, "host.wild.example.", QueryType::MX);
+        assert_eq!(nodata.header.response_code, Some(ResponseCode::NoError));
+        assert_eq!(check_answer(&zone, &keys, &nodata, now()), Ok(()));
+
+        // Sem o NSEC que cobre o nome, nada prova que nao havia um record exato
+        let mut stripped = expanded.to_owned();
+        stripped.data.authorities_values = expanded.data.authorities_values.map(|values| {
+            values
+                .into_iter()
+                .filter(|entry| {
+                    !matches!(
+                        entry.data,
+                        RData::NSEC { .. }
+                            | RData::RRSIG {
+                                type_covered: dns_wire::TYPE_NSEC,
+                                ..
+                            }
+                    )
+                })
+                .collect()
+        });
+        assert_eq!(
+            check_answer(&zone, &keys, &stripped, now()),
+            Err("dnssec-bogus-wildcard")
+        );
+    }
+
+    #[test]
+    fn test_bogus_data() {
+        let (root, example) = signed_tree();
+        let keys = vec![example.ksk.get_dnskey(), example.zsk.get_dnskey()];
+
+        // Valor alterado depois de assinado
+        let mut www = answer(&example.db, "www.example.", QueryType::A);
+        if let Some(values) = &mut www.data.response_values {
+            values[0].data = RData::A("10.9.9.9".parse().unwrap());
+        }
+        assert_eq!(
+            check_answer(&name("example."), &keys, &www, now()),
+            Err("dnssec-bogus-rrset")
+        );
+
+        // Assinaturas expiradas
+        let www = answer(&example.db, "www.example.", QueryType::A);
+        let expired = now() + dns_dnssec::SIGNATURE_VALIDITY + 1;
+        assert_eq!(
+            check_answer(&name("example."), &keys, &www, expired),
+            Err("dnssec-bogus-rrset")
+        );
+
+        // Referencia sem DS nem NSEC
+        let mut referral = answer(&root.db, "www.example.", QueryType::A);
+        referral.data.authorities_values = referral.data.authorities_values.map(|values| {
+            values
+                .into_iter()
+                .filter(|entry| matches!(entry.data, RData::NS(_)))
+                .collect()
+        });
+        assert_eq!(
+            check_referral(&name("."), &root_keys(&root), &referral, now()),
+            Err("dnssec-bogus-insecure-delegation")
+        );
+
+        // DS que nao corresponde a KSK da zona filha
+        let dnskeys = answer(&example.db, "example.", QueryType::DNSKEY);
+        let wrong_ds = dns_dnssec::ds_for(&name("example."), &root.ksk.get_dnskey()).unwrap();
+        assert_eq!(
+            trusted_keys(&name("example."), &dnskeys, &[wrong_ds], now()),
+            Err("dnssec-bogus-dnskey")
+        );
+    }
+}
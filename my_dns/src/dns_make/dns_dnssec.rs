@@ -0,0 +1,818 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{
+        EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED,
+        ECDSA_P256_SHA256_FIXED_SIGNING,
+    },
+};
+
+use crate::{
+    dns_make::dns_wire::{self, WireError, TYPE_DNSKEY, TYPE_DS, TYPE_NS, TYPE_NSEC, TYPE_RRSIG},
+    dns_structs::{
+        dns_domain_name::Domain,
+        dns_message::{DNSEntry, DNSMessage, QueryType, ResponseCode},
+        dns_resource_data::RData,
+        domain_database_struct::DomainDatabase,
+    },
+};
+
+// Unico algoritmo suportado: ECDSA P-256 com SHA-256 (RFC 6605)
+pub const ECDSAP256SHA256: u8 = 13;
+
+// Flags de uma DNSKEY (RFC 4034 2.1.1): todas sao chaves da zona e a KSK e o ponto de entrada
+pub const ZONE_KEY_FLAG: u16 = 0x0100;
+pub const SEP_FLAG: u16 = 0x0001;
+const DNSSEC_PROTOCOL: u8 = 3;
+
+// Unico digest suportado nos DS: SHA-256 (RFC 4509)
+pub const DIGEST_SHA256: u8 = 2;
+
+// Validade das assinaturas e margem antes da expiracao a partir da qual a zona e assinada de novo
+pub const SIGNATURE_VALIDITY: u32 = 30 * 24 * 3600;
+pub const RESIGN_MARGIN: u32 = 7 * 24 * 3600;
+// As assinaturas comecam a valer uma hora antes, para tolerar relogios atrasados
+const INCEPTION_SKEW: u32 = 3600;
+
+// Chave privada de uma zona, KSK (assina o RRset DNSKEY) ou ZSK (assina os restantes RRsets)
+pub struct ZoneKey {
+    flags: u16,
+    key_pair: EcdsaKeyPair,
+}
+
+impl ZoneKey {
+    // Le a chave (PKCS#8 em DER) do ficheiro, criando uma chave nova se o ficheiro nao existir
+    pub fn load_or_generate(path: &str, flags: u16) -> Result<Self, &'static str> {
+        let pkcs8 = match fs::read(path) {
+            Ok(pkcs8) => pkcs8,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let pkcs8 = generate_pkcs8()?;
+                // A chave privada so pode ser lida pelo dono do ficheiro
+                let written = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(&pkcs8));
+                if written.is_err() {
+                    return Err("Failed to write DNSSEC key file");
+                }
+                pkcs8
+            }
+            Err(_) => return Err("Failed to read DNSSEC key file"),
+        };
+        ZoneKey::from_pkcs8(&pkcs8, flags)
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8], flags: u16) -> Result<Self, &'static str> {
+        match EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            pkcs8,
+            &SystemRandom::new(),
+        ) {
+            Ok(key_pair) => Ok(ZoneKey { flags, key_pair }),
+            Err(_) => Err("Invalid DNSSEC key file"),
+        }
+    }
+
+    // A chave publica vai na DNSKEY sem o prefixo 0x04 do ponto nao comprimido (RFC 6605 4)
+    pub fn get_dnskey(&self) -> RData {
+        RData::DNSKEY {
+            flags: self.flags,
+            protocol: DNSSEC_PROTOCOL,
+            algorithm: ECDSAP256SHA256,
+            public_key: self.key_pair.public_key().as_ref()[1..].to_vec(),
+        }
+    }
+
+    pub fn get_key_tag(&self) -> u16 {
+        key_tag(&self.get_dnskey())
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        match self.key_pair.sign(&SystemRandom::new(), data) {
+            Ok(signature) => Ok(signature.as_ref().to_vec()),
+            Err(_) => Err("dnssec-sign-fail"),
+        }
+    }
+}
+
+pub fn generate_pkcs8() -> Result<Vec<u8>, &'static str> {
+    match EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new()) {
+        Ok(pkcs8) => Ok(pkcs8.as_ref().to_vec()),
+        Err(_) => Err("Failed to generate DNSSEC key"),
+    }
+}
+
+// Identificador de uma chave, usado nos RRSIG para indicar a DNSKEY que assinou (RFC 4034 B)
+pub fn key_tag(dnskey: &RData) -> u16 {
+    let rdata = dns_wire::canonical_rdata(dnskey).unwrap_or_default();
+    let mut ac: u32 = 0;
+    for (i, byte) in rdata.iter().enumerate() {
+        ac += if i % 2 == 0 {
+            (*byte as u32) << 8
+        } else {
+            *byte as u32
+        };
+    }
+    ac += (ac >> 16) & 0xFFFF;
+    (ac & 0xFFFF) as u16
+}
+
+// DS de uma DNSKEY: digest do nome do dono na forma canonica seguido do rdata da DNSKEY
+// (RFC 4034 5.1.4)
+pub fn ds_for(owner: &Domain, dnskey: &RData) -> Result<RData, WireError> {
+    let algorithm = match dnskey {
+        RData::DNSKEY { algorithm, .. } => *algorithm,
+        _ => return Err(WireError::BadRDataLength),
+    };
+    let mut data = dns_wire::canonical_owner(owner)?;
+    data.extend(dns_wire::canonical_rdata(dnskey)?);
+    Ok(RData::DS {
+        key_tag: key_tag(dnskey),
+        algorithm,
+        digest_type: DIGEST_SHA256,
+        digest: digest::digest(&digest::SHA256, &data).as_ref().to_vec(),
+    })
+}
+
+// Verifica um RRSIG sobre um RRset com uma das DNSKEY dadas: o RRSIG tem de estar dentro da
+// validade e indicar uma chave da zona com o mesmo key tag e algoritmo (RFC 4035 5.3.1)
+pub fn verify_rrsig(rrsig: &DNSEntry, rrset: &[DNSEntry], dnskeys: &[RData], now: u32) -> bool {
+    let (algorithm, expiration, inception, tag, signature) = match &rrsig.data {
+        RData::RRSIG {
+            algorithm,
+            expiration,
+            inception,
+            key_tag,
+            signature,
+            ..
+        } => (*algorithm, *expiration, *inception, *key_tag, signature),
+        _ => return false,
+    };
+    if algorithm != ECDSAP256SHA256 || now < inception || now > expiration {
+        return false;
+    }
+    let data = match signed_data(&rrsig.data, rrset) {
+        Ok(data) => data,
+        Err(_) => return false,
+    };
+    dnskeys.iter().any(|dnskey| match dnskey {
+        RData::DNSKEY {
+            flags,
+            algorithm: key_algorithm,
+            public_key,
+            ..
+        } if flags & ZONE_KEY_FLAG != 0
+            && *key_algorithm == algorithm
+            && key_tag(dnskey) == tag =>
+        {
+            let mut point = vec![0x04];
+            point.extend_from_slice(public_key);
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point)
+                .verify(&data, signature)
+                .is_ok()
+        }
+        _ => false,
+    })
+}
+
+// Segundos desde a epoch, no formato das datas de um RRSIG
+pub fn now() -> u32 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as u32,
+        Err(_) => 0,
+    }
+}
+
+// Numero de labels do dono, sem a raiz e sem o "*" de um wildcard (RFC 4034 3.1.3)
+pub fn label_count(name: &Domain) -> u8 {
+    let parts = name.getparts();
+    match parts.first() {
+        Some(label) if label == "*" => (parts.len() - 1) as u8,
+        _ => parts.len() as u8,
+    }
+}
+
+// Dados cobertos por um RRSIG (RFC 4034 3.1.8.1): o rdata do RRSIG sem a assinatura, seguido dos
+// records do RRset na forma canonica e ordenados pelo rdata. Um RRSIG com menos labels que o dono
+// foi gerado a partir de um wildcard, que e o nome que entra nos dados
+pub fn signed_data(rrsig: &RData, rrset: &[DNSEntry]) -> Result<Vec<u8>, WireError> {
+    let (labels, original_ttl) = match rrsig {
+        RData::RRSIG {
+            labels,
+            original_ttl,
+            ..
+        } => (*labels as usize, *original_ttl),
+        _ => return Err(WireError::BadRDataLength),
+    };
+    let first = match rrset.first() {
+        Some(first) => first,
+        None => return Err(WireError::BadRDataLength),
+    };
+    let mut unsigned = rrsig.to_owned();
+    if let RData::RRSIG { signature, .. } = &mut unsigned {
+        signature.clear();
+    }
+    let mut data = dns_wire::canonical_rdata(&unsigned)?;
+
+    let parts = first.domain_name.getparts();
+    let owner = if labels < label_count(&first.domain_name) as usize {
+        let mut wildcard = vec!["*".to_string()];
+        wildcard.extend_from_slice(&parts[parts.len() - labels..]);
+        let mut owner = Domain::new_empty();
+        owner.set_parts(wildcard);
+        owner
+    } else {
+        first.domain_name.to_owned()
+    };
+    let type_code = dns_wire::rdata_type_code(&first.data);
+    let mut rdatas = rrset
+        .iter()
+        .map(|entry| dns_wire::canonical_rdata(&entry.data))
+        .collect::<Result<Vec<Vec<u8>>, WireError>>()?;
+    rdatas.sort();
+    rdatas.dedup();
+    for rdata in rdatas {
+        data.extend(dns_wire::canonical_rr(
+            &owner,
+            type_code,
+            original_ttl,
+            &rdata,
+        )?);
+    }
+    Ok(data)
+}
+
+// Assina a zona: gera o RRset DNSKEY, a cadeia NSEC e um RRSIG por cada RRset autoritativo.
+// O RRset DNSKEY e assinado pela KSK e os restantes pela ZSK. Os records DNSSEC anteriores sao
+// substituidos
+pub fn sign_zone(
+    db: &mut DomainDatabase,
+    ksk: &ZoneKey,
+    zsk: &ZoneKey,
+    now: u32,
+) -> Result<(), &'static str> {
+    db.clear_dnssec_records();
+    let soa = db.get_soa_records();
+    let apex = soa.domain_name.to_owned();
+    for key in [ksk, zsk] {
+        db.add_entry(DNSEntry::new(apex.to_owned(), soa.ttl, key.get_dnskey()));
+    }
+
+    // Abaixo de uma delegacao so o NS e o NSEC pertencem a zona; o resto e glue da zona filha
+    let delegations: Vec<Domain> = db
+        .ns_records
+        .keys()
+        .filter(|name| name.is_subdomain_of(&apex) && **name != apex)
+        .cloned()
+        .collect();
+    let is_occluded = |name: &Domain, type_code: u16| {
+        delegations.iter().any(|cut| {
+            name.is_subdomain_of(cut)
+                && (name != cut || (type_code != TYPE_NS && type_code != TYPE_DS))
+        })
+    };
+
+    // RRsets da zona, pela ordem canonica do dono
+    let mut rrsets: BTreeMap<(Domain, u16), Vec<DNSEntry>> = BTreeMap::new();
+    for entry in zone_entries(db) {
+        let type_code = dns_wire::rdata_type_code(&entry.data);
+        if !entry.domain_name.is_subdomain_of(&apex) || is_occluded(&entry.domain_name, type_code) {
+            continue;
+        }
+        rrsets
+            .entry((entry.domain_name.to_owned(), type_code))
+            .or_default()
+            .push(entry);
+    }
+
+    // Cadeia NSEC: cada nome aponta para o seguinte na ordem canonica e o ultimo volta ao apex
+    let mut owners: BTreeMap<Domain, Vec<u16>> = BTreeMap::new();
+    for (owner, type_code) in rrsets.keys() {
+        owners
+            .entry(owner.to_owned())
+            .or_insert_with(|| vec![TYPE_RRSIG, TYPE_NSEC])
+            .push(*type_code);
+    }
+    let owners: Vec<(Domain, Vec<u16>)> = owners.into_iter().collect();
+    for (i, (owner, types)) in owners.iter().enumerate() {
+        let mut types = types.to_owned();
+        types.sort_unstable();
+        let nsec = DNSEntry::new(
+            owner.to_owned(),
+            soa.minimum,
+            RData::NSEC {
+                next_domain: owners[(i + 1) % owners.len()].0.to_owned(),
+                types,
+            },
+        );
+        rrsets
+            .entry((owner.to_owned(), TYPE_NSEC))
+            .or_default()
+            .push(nsec.to_owned());
+        db.add_entry(nsec);
+    }
+
+    let inception = now.saturating_sub(INCEPTION_SKEW);
+    let expiration = now.saturating_add(SIGNATURE_VALIDITY);
+    for ((_, type_code), rrset) in &rrsets {
+        // O NS de uma delegacao pertence a zona filha e nao e assinado (RFC 4035 2.2)
+        if *type_code == TYPE_NS && delegations.contains(&rrset[0].domain_name) {
+            continue;
+        }
+        let key = if *type_code == TYPE_DNSKEY { ksk } else { zsk };
+        let rrsig = sign_rrset(rrset, key, &apex, inception, expiration)?;
+        db.add_entry(rrsig);
+    }
+    Ok(())
+}
+
+// A zona e assinada de novo quando falta menos de RESIGN_MARGIN para a primeira assinatura expirar
+pub fn needs_resign(db: &DomainDatabase, now: u32) -> bool {
+    db.records
+        .of_type(QueryType::RRSIG)
+        .filter_map(|entry| match entry.data {
+            RData::RRSIG { expiration, .. } => Some(expiration),
+            _ => None,
+        })
+        .min()
+        .is_none_or(|expiration| expiration.saturating_sub(now) < RESIGN_MARGIN)
+}
+
+fn sign_rrset(
+    rrset: &[DNSEntry],
+    key: &ZoneKey,
+    signer: &Domain,
+    inception: u32,
+    expiration: u32,
+) -> Result<DNSEntry, &'static str> {
+    let owner = &rrset[0].domain_name;
+    let ttl = rrset.iter().map(|entry| entry.ttl).min().unwrap_or(0);
+    let mut rrsig = RData::RRSIG {
+        type_covered: dns_wire::rdata_type_code(&rrset[0].data),
+        algorithm: ECDSAP256SHA256,
+        labels: label_count(owner),
+        original_ttl: ttl,
+        expiration,
+        inception,
+        key_tag: key.get_key_tag(),
+        signer: signer.to_owned(),
+        signature: Vec::new(),
+    };
+    let data = match signed_data(&rrsig, rrset) {
+        Ok(data) => data,
+        Err(_) => return Err("dnssec-encode-fail"),
+    };
+    if let RData::RRSIG { signature, .. } = &mut rrsig {
+        *signature = key.sign(&data)?;
+    }
+    Ok(DNSEntry::new(owner.to_owned(), ttl, rrsig))
+}
+
+// Records da zona que sao assinados, incluindo o SOA e o RRset DNSKEY
+fn zone_entries(db: &DomainDatabase) -> Vec<DNSEntry> {
+    let mut entries = vec![db.get_soa_records().get_entry()];
+    entries.extend(db.ns_records.values().flatten().cloned());
+    for query_type in [
+        QueryType::A,
+        QueryType::CNAME,
+        QueryType::MX,
+        QueryType::PTR,
+        QueryType::AAAA,
+        QueryType::TXT,
+        QueryType::SRV,
+        QueryType::DNSKEY,
+        QueryType::DS,
+    ] {
+        entries.extend(db.records_of(query_type).cloned());
+    }
+    entries
+}
+
+// Junta a uma resposta autoritativa de uma zona assinada os RRSIG dos RRsets e, nas respostas
+// negativas, nas delegacoes e nas respostas de um wildcard, os NSEC que as provam (RFC 4035 3.1)
+pub fn add_dnssec_records(dns_message: &mut DNSMessage, db: &DomainDatabase) {
+    let queried_domain = dns_message.data.query_info.name.to_owned();
+    let apex = db.get_soa_records().domain_name;
+    let wildcard = db.get_wildcard_for(&queried_domain);
+    let mut answers = dns_message.data.response_values.take().unwrap_or_default();
+    let mut authorities = dns_message
+        .data
+        .authorities_values
+        .take()
+        .unwrap_or_default();
+
+    let delegation = authorities
+        .iter()
+        .find(|entry| matches!(entry.data, RData::NS(_)) && entry.domain_name != apex)
+        .map(|entry| entry.domain_name.to_owned());
+    let is_negative = authorities
+        .iter()
+        .any(|entry| matches!(entry.data, RData::SOA { .. }));
+
+    if answers.is_empty() && is_negative {
+        match nsec_at(db, &queried_domain) {
+            // NODATA: o NSEC do nome mostra que o tipo pedido nao existe
+            Some(nsec) => authorities.push(nsec),
+            // Nome sem records: um NSEC cobre o nome e, no NXDOMAIN, outro cobre o wildcard do
+            // ancestral mais proximo que existe. Se o wildcard existir sem o tipo pedido, o seu
+            // NSEC mostra que o tipo nao existe
+            None => {
+                authorities.extend(covering_nsec(db, &queried_domain));
+                if let Some(wildcard) = &wildcard {
+                    authorities.extend(nsec_at(db, wildcard));
+                } else if dns_message.header.response_code == Some(ResponseCode::NXDomain) {
+                    let wildcard = wildcard_of(&closest_encloser(db, &queried_domain, &apex));
+                    if let Some(nsec) = covering_nsec(db, &wildcard) {
+                        if !authorities.contains(&nsec) {
+                            authorities.push(nsec);
+                        }
+                    }
+                }
+            }
+        }
+    } else if let (true, Some(cut)) = (answers.is_empty(), delegation) {
+        // O DS liga a zona filha a esta zona; sem DS, o NSEC da delegacao prova que a zona filha
+        // nao e assinada
+        match db.get_domain_query(QueryType::DS, &cut) {
+            Some(ds) if !ds.is_empty() => authorities.extend(ds.iter().cloned()),
+            _ => authorities.extend(nsec_at(db, &cut)),
+        }
+    } else if wildcard.is_some() {
+        // Resposta de um wildcard: o NSEC que cobre o nome mostra que nao havia um record exato
+        authorities.extend(covering_nsec(db, &queried_domain));
+    }
+
+    let answer_signatures = rrsigs_for(db, &answers);
+    answers.extend(answer_signatures);
+    let authority_signatures = rrsigs_for(db, &authorities);
+    authorities.extend(authority_signatures);
+
+    dns_message.header.number_of_values = match answers.len() {
+        0 => None,
+        len => len.try_into().ok(),
+    };
+    dns_message.header.number_of_authorities = match authorities.len() {
+        0 => None,
+        len => len.try_into().ok(),
+    };
+    dns_message.data.response_values = (!answers.is_empty()).then_some(answers);
+    dns_message.data.authorities_values = (!authorities.is_empty()).then_some(authorities);
+}
+
+// RRSIG da zona que cobrem os RRsets presentes numa seccao. Um RRset sintetizado por um wildcard
+// leva os RRSIG do wildcard, com o nome pedido como dono (RFC 4035 3.1.3.3)
+fn rrsigs_for(db: &DomainDatabase, entries: &[DNSEntry]) -> Vec<DNSEntry> {
+    let rrsets: BTreeMap<(Domain, u16), Domain> = entries
+        .iter()
+        .filter(|entry| !matches!(entry.data, RData::RRSIG { .. } | RData::OPT(_)))
+        .map(|entry| {
+            let owner = &entry.domain_name;
+            let signed_owner = db.get_wildcard_for(owner).unwrap_or(owner.to_owned());
+            (
+                (signed_owner, dns_wire::rdata_type_code(&entry.data)),
+                owner.to_owned(),
+            )
+        })
+        .collect();
+    rrsets
+        .iter()
+        .flat_map(|((signed_owner, type_code), owner)| {
+            db.get_rrset(signed_owner, QueryType::RRSIG)
+                .unwrap_or_default()
+                .iter()
+                .filter(move |entry| match entry.data {
+                    RData::RRSIG { type_covered, .. } => type_covered == *type_code,
+                    _ => false,
+                })
+                .map(move |entry| DNSEntry {
+                    domain_name: owner.to_owned(),
+                    ..entry.to_owned()
+                })
+        })
+        .collect()
+}
+
+fn nsec_at(db: &DomainDatabase, name: &Domain) -> Option<DNSEntry> {
+    db.get_rrset(name, QueryType::NSEC)?.first().cloned()
+}
+
+// O NSEC que cobre um nome e o do dono anterior na ordem canonica
+fn covering_nsec(db: &DomainDatabase, name: &Domain) -> Option<DNSEntry> {
+    db.records
+        .preceding(name, QueryType::NSEC)?
+        .iter()
+        .find(|entry| nsec_covers(entry, name))
+        .cloned()
+}
+
+// Um NSEC cobre os nomes no intervalo entre o dono e o nome seguinte. O ultimo NSEC da cadeia
+// aponta para o apex e cobre todos os nomes depois dele
+pub fn nsec_covers(nsec: &DNSEntry, name: &Domain) -> bool {
+    match &nsec.data {
+        RData::NSEC { next_domain, .. } => {
+            nsec.domain_name < *name && (name < next_domain || *next_domain <= nsec.domain_name)
+        }
+        _ => false,
+    }
+}
+
+// Ancestral mais proximo do nome que existe na zona (RFC 4592 3.3.1)
+fn closest_encloser(db: &DomainDatabase, name: &Domain, apex: &Domain) -> Domain {
+    let parts = name.getparts();
+    for i in 1..parts.len() {
+        let mut ancestor = Domain::new_empty();
+        ancestor.set_parts(parts[i..].to_vec());
+        if !ancestor.is_subdomain_of(apex) {
+            break;
+        }
+        if db.has_domain(&ancestor) {
+            return ancestor;
+        }
+    }
+    apex.to_owned()
+}
+
+pub fn wildcard_of(name: &Domain) -> Domain {
+    let mut parts = vec!["*".to_string()];
+    parts.extend_from_slice(name.getparts());
+    let mut wildcard = Domain::new_empty();
+    wildcard.set_parts(parts);
+    wildcard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_parse::domain_database_parse;
+
+    static ZONE: &str = "@ DEFAULT example.com.
+@ SOASP ns1.example.com. 3600
+@ SOAADMIN admin.example.com. 3600
+@ SOASERIAL 1 3600
+@ SOAREFRESH 14400 3600
+@ SOARETRY 3600 3600
+@ SOAEXPIRE 604800 3600
+@ SOAMINIMUM 300 3600
+@ NS ns1.example.com. 3600
+sub NS ns.sub.example.com. 3600
+ns1 A 10.0.0.1 3600
+www A 10.0.0.2 3600
+www A 10.0.0.3 3600
+ns.sub A 10.0.0.4 3600
+";
+
+    fn signed_zone() -> (DomainDatabase, ZoneKey, ZoneKey) {
+        let mut db = domain_database_parse::parse_from_str(ZONE.to_string()).unwrap();
+        let ksk =
+            ZoneKey::from_pkcs8(&generate_pkcs8().unwrap(), ZONE_KEY_FLAG | SEP_FLAG).unwrap();
+        let zsk = ZoneKey::from_pkcs8(&generate_pkcs8().unwrap(), ZONE_KEY_FLAG).unwrap();
+        sign_zone(&mut db, &ksk, &zsk, now()).unwrap();
+        (db, ksk, zsk)
+    }
+
+    fn name(name: &str) -> Domain {
+        Domain::new(name.to_string())
+    }
+
+    fn verify(rrsig: &DNSEntry, rrset: &[DNSEntry], dnskey: &RData) -> bool {
+        let (signature, public_key) = match (&rrsig.data, dnskey) {
+            (RData::RRSIG { signature, .. }, RData::DNSKEY { public_key, .. }) => {
+                (signature, public_key)
+            }
+            _ => return false,
+        };
+        let mut point = vec![0x04];
+        point.extend_from_slice(public_key);
+        let data = signed_data(&rrsig.data, rrset).unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point)
+            .verify(&data, signature)
+            .is_ok()
+    }
+
+    #[test]
+    fn test_key_tag() {
+        // DNSKEY do exemplo da RFC 6605 6.1
+        let dnskey = RData::from_zone_str(
+            "DNSKEY",
+            "257 3 13 GojIhhXUN/u4v54ZQqGSnyhWJwaubCvTmeexv7bR6edbkrSqQpF64cYbcB7wNcP+e+MAnLr+Wi9xMWyQLc8NAA==",
+            None,
+            &Domain::new_empty(),
+        )
+        .unwrap();
+        assert_eq!(key_tag(&dnskey), 55648);
+        assert_eq!(
+            ds_for(&name("example.net."), &dnskey).unwrap().get_value(),
+            "55648 13 2 B4C8C1FE2E7477127B27115656AD6256F424625BF5C1E2770CE6D6E37DF61D17"
+        );
+    }
+
+    #[test]
+    fn test_key_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("zone-key-{}.der", std::process::id()));
+        let path_str = path.to_string_lossy().to_string();
+        let key = ZoneKey::load_or_generate(&path_str, ZONE_KEY_FLAG).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        // A chave criada e lida de novo do mesmo ficheiro
+        let loaded = ZoneKey::load_or_generate(&path_str, ZONE_KEY_FLAG).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(loaded.get_dnskey(), key.get_dnskey());
+    }
+
+    #[test]
+    fn test_signatures_verify() {
+        let (db, ksk, zsk) = signed_zone();
+        let www = db
+            .get_domain_query(QueryType::A, &name("www.example.com."))
+            .unwrap();
+        let rrsigs = db
+            .get_domain_query(QueryType::RRSIG, &name("www.example.com."))
+            .unwrap();
+        let a_rrsig = rrsigs
+            .iter()
+            .find(|entry| {
+                matches!(
+                    entry.data,
+                    RData::RRSIG {
+                        type_covered: 1,
+                        ..
+                    }
+                )
+            })
+            .unwrap();
+        assert!(verify(a_rrsig, &www, &zsk.get_dnskey()));
+        assert!(!verify(a_rrsig, &www[..1], &zsk.get_dnskey()));
+        let dnskeys = [ksk.get_dnskey(), zsk.get_dnskey()];
+        assert!(verify_rrsig(a_rrsig, &www, &dnskeys, now()));
+        assert!(!verify_rrsig(a_rrsig, &www[..1], &dnskeys, now()));
+        assert!(!verify_rrsig(a_rrsig, &www, &dnskeys[..1], now()));
+        assert!(!verify_rrsig(
+            a_rrsig,
+            &www,
+            &dnskeys,
+            now() + SIGNATURE_VALIDITY + 1
+        ));
+
+        // O RRset DNSKEY e assinado pela KSK
+        let dnskeys = db
+            .get_domain_query(QueryType::DNSKEY, &name("example.com."))
+            .unwrap();
+        let dnskey_rrsig = db
+            .get_domain_query(QueryType::RRSIG, &name("example.com."))
+            .unwrap()
+            .iter()
+            .cloned()
+            .find(|entry| {
+                matches!(
+                    entry.data,
+                    RData::RRSIG {
+                        type_covered: 48,
+                        ..
+                    }
+                )
+            })
+            .unwrap();
+        assert!(verify(&dnskey_rrsig, &dnskeys, &ksk.get_dnskey()));
+
+        // A delegacao e o glue nao sao assinados
+        assert_eq!(
+            db.get_domain_query(QueryType::RRSIG, &name("ns.sub.example.com.")),
+            None
+        );
+        let sub_rrsigs = db
+            .get_domain_query(QueryType::RRSIG, &name("sub.example.com."))
+            .unwrap();
+        assert_eq!(sub_rrsigs.len(), 1);
+        assert!(matches!(
+            sub_rrsigs[0].data,
+            RData::RRSIG {
+                type_covered: 47,
+                ..
+            }
+        ));
+        assert!(!needs_resign(&db, now()));
+        assert!(needs_resign(&db, now() + SIGNATURE_VALIDITY));
+    }
+
+    #[test]
+    fn test_nsec_chain() {
+        let (db, _, _) = signed_zone();
+        let chain: Vec<(String, String)> = db
+            .records_of(QueryType::NSEC)
+            .map(|entry| {
+                let value = entry.get_value();
+                (entry.domain_name.to_string(), value)
+            })
+            .collect();
+        assert_eq!(
+            chain,
+            vec![
+                (
+                    "example.com.".to_string(),
+                    "ns1.example.com. NS SOA RRSIG NSEC DNSKEY".to_string()
+                ),
+                (
+                    "ns1.example.com.".to_string(),
+                    "sub.example.com. A RRSIG NSEC".to_string()
+                ),
+                (
+                    "sub.example.com.".to_string(),
+                    "www.example.com. NS RRSIG NSEC".to_string()
+                ),
+                (
+                    "www.example.com.".to_string(),
+                    "example.com. A RRSIG NSEC".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zone_transfer_lines() {
+        // Os records DNSSEC passam pelas linhas da transferencia de zona sem alteracoes
+        let (db, _, _) = signed_zone();
+        let mut lines = db.get_soa_records().get_zone_lines();
+        let dnssec_types = [QueryType::DNSKEY, QueryType::RRSIG, QueryType::NSEC];
+        for query_type in dnssec_types {
+            lines.extend(db.records_of(query_type).map(|entry| entry.get_string()));
+        }
+        let transferred = domain_database_parse::parse_from_str(lines.join("\n")).unwrap();
+        for query_type in dnssec_types {
+            assert!(transferred
+                .records_of(query_type)
+                .eq(db.records_of(query_type)));
+        }
+    }
+
+    #[test]
+    fn test_signed_delegation() {
+        // Com um DS na delegacao, o DS e assinado e vai na referencia em vez do NSEC
+        let zone = format!("{}sub DS 12345 13 2 0A1B2C3D 3600\n", ZONE);
+        let mut db = domain_database_parse::parse_from_str(zone).unwrap();
+        let ksk =
+            ZoneKey::from_pkcs8(&generate_pkcs8().unwrap(), ZONE_KEY_FLAG | SEP_FLAG).unwrap();
+        let zsk = ZoneKey::from_pkcs8(&generate_pkcs8().unwrap(), ZONE_KEY_FLAG).unwrap();
+        sign_zone(&mut db, &ksk, &zsk, now()).unwrap();
+        assert_eq!(
+            nsec_at(&db, &name("sub.example.com.")).unwrap().get_value(),
+            "www.example.com. NS DS RRSIG NSEC"
+        );
+
+        let mut response = DNSMessage::new();
+        response.data.query_info.name = name("www.sub.example.com.");
+        response.data.query_info.type_of_value = QueryType::A;
+        response.data.authorities_values = db
+            .get_ns_of(&name("sub.example.com."))
+            .map(|ns| ns.to_vec());
+        add_dnssec_records(&mut response, &db);
+        let authorities = response.data.authorities_values.unwrap();
+        let ds: Vec<DNSEntry> = authorities
+            .iter()
+            .filter(|entry| matches!(entry.data, RData::DS { .. }))
+            .cloned()
+            .collect();
+        assert_eq!(ds.len(), 1);
+        assert!(!authorities
+            .iter()
+            .any(|entry| matches!(entry.data, RData::NSEC { .. })));
+        let rrsig = authorities
+            .iter()
+            .find(|entry| matches!(entry.data, RData::RRSIG { .. }))
+            .unwrap();
+        assert!(verify_rrsig(rrsig, &ds, &[zsk.get_dnskey()], now()));
+    }
+
+    #[test]
+    fn test_negative_proofs() {
+        let (db, _, _) = signed_zone();
+        let mut response = DNSMessage::new();
+        response.data.query_info.name = name("xyz.example.com.");
+        response.data.query_info.type_of_value = QueryType::A;
+        response.header.response_code = Some(ResponseCode::NXDomain);
+        response.data.authorities_values = Some(vec![db.get_soa_records().get_negative_entry()]);
+        add_dnssec_records(&mut response, &db);
+
+        // SOA, o NSEC que cobre xyz, o NSEC que cobre *.example.com e os seus RRSIG
+        let authorities = response.data.authorities_values.unwrap();
+        let nsec_owners: Vec<String> = authorities
+            .iter()
+            .filter(|entry| matches!(entry.data, RData::NSEC { .. }))
+            .map(|entry| entry.domain_name.to_string())
+            .collect();
+        assert_eq!(nsec_owners, vec!["www.example.com.", "example.com."]);
+        assert_eq!(authorities.len(), 6);
+        assert_eq!(response.header.number_of_authorities, Some(6));
+    }
+}
@@ -0,0 +1,285 @@
+use std::io::{self, BufRead, Read, Write};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::dns_structs::{dns_message::DNSMessage, dns_resource_data::RData};
+
+// Caminho e tipo MIME do DNS sobre HTTPS (RFC 8484)
+pub const DOH_PATH: &str = "/dns-query";
+pub const DNS_MESSAGE_TYPE: &str = "application/dns-message";
+
+// Limites de um pedido HTTP, para que um cliente nao consiga esgotar a memoria do servidor
+const MAX_LINE_LEN: usize = 8192;
+const MAX_HEADERS: usize = 64;
+const MAX_BODY_LEN: usize = 65535;
+
+#[derive(Debug)]
+pub enum HttpError {
+    // Pedido HTTP mal formado ou sem uma mensagem DNS valida
+    BadRequest,
+    // Caminho diferente de DOH_PATH
+    NotFound,
+    // Metodo diferente de GET e POST
+    MethodNotAllowed,
+    // Corpo maior que uma mensagem DNS
+    PayloadTooLarge,
+    // POST com um Content-Type diferente de DNS_MESSAGE_TYPE
+    UnsupportedMediaType,
+    // Erro de leitura na ligacao, depois do qual nao se responde
+    IOError(io::Error),
+}
+
+impl HttpError {
+    pub fn status(&self) -> &'static str {
+        match self {
+            HttpError::BadRequest | HttpError::IOError(_) => "400 Bad Request",
+            HttpError::NotFound => "404 Not Found",
+            HttpError::MethodNotAllowed => "405 Method Not Allowed",
+            HttpError::PayloadTooLarge => "413 Payload Too Large",
+            HttpError::UnsupportedMediaType => "415 Unsupported Media Type",
+        }
+    }
+}
+
+// Pergunta DNS recebida num pedido HTTP
+#[derive(Debug, PartialEq)]
+pub struct DohRequest {
+    pub pdu: Vec<u8>,
+    // HTTP/1.1 mantem a ligacao aberta a nao ser que o cliente peca o contrario
+    pub keep_alive: bool,
+}
+
+// Le um pedido GET (?dns= em base64url) ou POST (corpo application/dns-message).
+// Devolve None quando o cliente fecha a ligacao entre pedidos
+pub fn read_doh_request(stream: &mut impl BufRead) -> Result<Option<DohRequest>, HttpError> {
+    let request_line = match read_line(stream)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(HttpError::BadRequest),
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(HttpError::BadRequest);
+    }
+
+    let mut content_length: Option<usize> = None;
+    let mut content_type: Option<String> = None;
+    let mut keep_alive = version == "HTTP/1.1";
+    let mut header_count = 0;
+    loop {
+        let line = match read_line(stream)? {
+            Some(line) => line,
+            None => return Err(HttpError::BadRequest),
+        };
+        if line.is_empty() {
+            break;
+        }
+        header_count += 1;
+        if header_count > MAX_HEADERS {
+            return Err(HttpError::BadRequest);
+        }
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+            None => return Err(HttpError::BadRequest),
+        };
+        match name.as_str() {
+            "content-length" => match value.parse() {
+                Ok(len) => content_length = Some(len),
+                Err(_) => return Err(HttpError::BadRequest),
+            },
+            "content-type" => content_type = Some(value.to_ascii_lowercase()),
+            "connection" => match value.to_ascii_lowercase().as_str() {
+                "close" => keep_alive = false,
+                "keep-alive" => keep_alive = true,
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+
+    let pdu = match method {
+        "GET" => {
+            // O corpo de um GET nao e usado, mas tem de ser lido para o proximo pedido
+            if let Some(len) = content_length {
+                discard_body(stream, len)?;
+            }
+            if path != DOH_PATH {
+                return Err(HttpError::NotFound);
+            }
+            let dns_param = query
+                .into_iter()
+                .flat_map(|query| query.split('&'))
+                .find_map(|param| param.strip_prefix("dns="));
+            match dns_param.map(|param| URL_SAFE_NO_PAD.decode(param)) {
+                Some(Ok(pdu)) if !pdu.is_empty() => pdu,
+                _ => return Err(HttpError::BadRequest),
+            }
+        }
+        "POST" => {
+            let len = match content_length {
+                Some(len) if len > MAX_BODY_LEN => return Err(HttpError::PayloadTooLarge),
+                Some(len) => len,
+                None => return Err(HttpError::BadRequest),
+            };
+            let mut body = vec![0u8; len];
+            if let Err(err) = stream.read_exact(&mut body) {
+                return Err(HttpError::IOError(err));
+            }
+            if path != DOH_PATH {
+                return Err(HttpError::NotFound);
+            }
+            if content_type.as_deref() != Some(DNS_MESSAGE_TYPE) {
+                return Err(HttpError::UnsupportedMediaType);
+            }
+            body
+        }
+        _ => return Err(HttpError::MethodNotAllowed),
+    };
+
+    Ok(Some(DohRequest { pdu, keep_alive }))
+}
+
+// Resposta 200 com a mensagem DNS; o Cache-Control segue o menor TTL da resposta (RFC 8484 5.1)
+pub fn write_doh_response(
+    stream: &mut impl Write,
+    pdu: &[u8],
+    max_age: u32,
+    keep_alive: bool,
+) -> Result<(), io::Error> {
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: max-age={}\r\nConnection: {}\r\n\r\n",
+        DNS_MESSAGE_TYPE,
+        pdu.len(),
+        max_age,
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    let mut response = head.into_bytes();
+    response.extend_from_slice(pdu);
+    stream.write_all(&response)?;
+    stream.flush()
+}
+
+// Resposta de erro sem corpo; a ligacao e sempre fechada a seguir
+pub fn write_http_error(stream: &mut impl Write, err: &HttpError) -> Result<(), io::Error> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        err.status()
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+// Menor TTL dos registos da resposta, sem contar com o OPT
+pub fn max_age(dns_message: &DNSMessage) -> u32 {
+    let data = &dns_message.data;
+    [
+        &data.response_values,
+        &data.authorities_values,
+        &data.extra_values,
+    ]
+    .into_iter()
+    .flatten()
+    .flatten()
+    .filter(|entry| !matches!(entry.data, RData::OPT(_)))
+    .map(|entry| entry.ttl)
+    .min()
+    .unwrap_or(0)
+}
+
+// Le uma linha terminada em CRLF (ou LF), sem o terminador
+fn read_line(stream: &mut impl BufRead) -> Result<Option<String>, HttpError> {
+    let mut line = Vec::new();
+    let read = match stream
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_until(b'\n', &mut line)
+    {
+        Ok(read) => read,
+        Err(err) => return Err(HttpError::IOError(err)),
+    };
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.len() > MAX_LINE_LEN || !line.ends_with(b"\n") {
+        return Err(HttpError::BadRequest);
+    }
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    match String::from_utf8(line) {
+        Ok(line) => Ok(Some(line)),
+        Err(_) => Err(HttpError::BadRequest),
+    }
+}
+
+fn discard_body(stream: &mut impl BufRead, len: usize) -> Result<(), HttpError> {
+    if len > MAX_BODY_LEN {
+        return Err(HttpError::PayloadTooLarge);
+    }
+    let mut body = vec![0u8; len];
+    match stream.read_exact(&mut body) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(HttpError::IOError(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(request: &[u8]) -> Result<Option<DohRequest>, HttpError> {
+        read_doh_request(&mut &request[..])
+    }
+
+    #[test]
+    fn test_get_request() {
+        // Exemplo da RFC 8484 4.1.1: pergunta A de www.example.com com id 0
+        let request = b"GET /dns-query?dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB HTTP/1.1\r\nHost: dnsserver.example.net\r\nAccept: application/dns-message\r\n\r\n";
+        let doh_request = read(request).unwrap().unwrap();
+        assert!(doh_request.keep_alive);
+        assert_eq!(&doh_request.pdu[..4], &[0, 0, 1, 0]);
+        assert_eq!(doh_request.pdu.len(), 33);
+    }
+
+    #[test]
+    fn test_post_request() {
+        let mut request = b"POST /dns-query HTTP/1.1\r\nContent-Type: application/dns-message\r\nContent-Length: 4\r\nConnection: close\r\n\r\n".to_vec();
+        request.extend_from_slice(&[1, 2, 3, 4]);
+        let doh_request = read(&request).unwrap().unwrap();
+        assert_eq!(doh_request.pdu, vec![1, 2, 3, 4]);
+        assert!(!doh_request.keep_alive);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        assert!(matches!(read(b""), Ok(None)));
+        assert!(matches!(
+            read(b"GET /other?dns=AAAB HTTP/1.1\r\n\r\n"),
+            Err(HttpError::NotFound)
+        ));
+        assert!(matches!(
+            read(b"GET /dns-query?dns=%%% HTTP/1.1\r\n\r\n"),
+            Err(HttpError::BadRequest)
+        ));
+        assert!(matches!(
+            read(b"PUT /dns-query HTTP/1.1\r\n\r\n"),
+            Err(HttpError::MethodNotAllowed)
+        ));
+        assert!(matches!(
+            read(b"POST /dns-query HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 1\r\n\r\nA"),
+            Err(HttpError::UnsupportedMediaType)
+        ));
+        assert!(matches!(
+            read(b"POST /dns-query HTTP/1.1\r\nContent-Length: 70000\r\n\r\n"),
+            Err(HttpError::PayloadTooLarge)
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+use idna::uts46::{AsciiDenyList, DnsLength, Hyphens, Uts46};
+
+// Nomes internacionalizados (IDNA): as labels com caracteres nao ASCII (U-labels) sao guardadas
+// e enviadas na forma ASCII "xn--" seguida do punycode (A-labels, RFC 5890 e RFC 3492). A
+// conversao e o processamento UTS-46 do crate idna: mapeamento (maiusculas, larguras), NFC e as
+// regras de validade do IDNA2008 (codepoints, hifens, CONTEXTJ e bidi)
+pub const ACE_PREFIX: &str = "xn--";
+
+// Converte uma label para a forma ASCII. As labels ASCII ficam como estao, com as maiusculas e
+// nomes como _sip; as restantes tem de ser U-labels validas depois de mapeadas e normalizadas
+pub fn to_ascii(label: &str) -> Result<String, &'static str> {
+    if label.is_ascii() {
+        return Ok(label.to_string());
+    }
+    if label.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("Invalid character in domain name");
+    }
+    let ascii = Uts46::new().to_ascii(
+        label.as_bytes(),
+        AsciiDenyList::STD3,
+        Hyphens::Check,
+        DnsLength::Ignore,
+    );
+    match ascii {
+        // O mapeamento pode apagar a label ou criar um ponto (U+FF0E) que a partiria em duas
+        Ok(ascii) if !ascii.is_empty() && !ascii.contains('.') => Ok(ascii.into_owned()),
+        _ => Err("Invalid internationalized label"),
+    }
+}
+
+// Converte uma A-label para a forma Unicode, para mostrar ao utilizador. As outras labels, e as
+// A-labels que nao sao validas, ficam como estao
+pub fn to_unicode(label: &str) -> String {
+    match label.get(..ACE_PREFIX.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(ACE_PREFIX) && !label.contains('.') => (),
+        _ => return label.to_string(),
+    }
+    match Uts46::new().to_unicode(label.as_bytes(), AsciiDenyList::STD3, Hyphens::Check) {
+        (unicode, Ok(())) if !unicode.is_ascii() => unicode.into_owned(),
+        _ => label.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punycode() {
+        // Exemplos da RFC 3492 7.1 e labels comuns
+        let samples = [
+            ("他们为什么不说中文", "xn--ihqwcrb4cv8a8dqg056pqjye"),
+            (
+                "почемужеонинеговорятпорусски",
+                "xn--b1abfaaepdrnnbgefbadotcwatmq2g4l",
+            ),
+            ("café", "xn--caf-dma"),
+            ("bücher", "xn--bcher-kva"),
+        ];
+        for (unicode, ascii) in samples {
+            assert_eq!(to_ascii(unicode).unwrap(), ascii);
+            assert_eq!(to_unicode(ascii), unicode);
+        }
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(to_ascii("example").unwrap(), "example");
+        assert_eq!(to_ascii("_Sip").unwrap(), "_Sip");
+        assert_eq!(to_ascii("café").unwrap(), "xn--caf-dma");
+        assert_eq!(to_ascii("CAFÉ").unwrap(), "xn--caf-dma");
+        assert_eq!(to_ascii("ca fé"), Err("Invalid character in domain name"));
+
+        assert_eq!(to_unicode("xn--caf-dma"), "café");
+        assert_eq!(to_unicode("XN--caf-dma"), "café");
+        assert_eq!(to_unicode("example"), "example");
+        assert_eq!(to_unicode("cafés"), "cafés");
+        // Punycode invalido ou so com ASCII nao e uma A-label valida
+        assert_eq!(to_unicode("xn--caf-d!a"), "xn--caf-d!a");
+        assert_eq!(to_unicode("xn--abc-"), "xn--abc-");
+    }
+
+    #[test]
+    fn test_idna2008() {
+        // A forma decomposta (NFD) e a de largura total dao a mesma A-label
+        assert_eq!(to_ascii("cafe\u{301}").unwrap(), "xn--caf-dma");
+        assert_eq!(to_ascii("ｃａｆé").unwrap(), "xn--caf-dma");
+        // O sharp s nao e mapeado para "ss" (processamento nao transicional)
+        assert_eq!(to_ascii("faß").unwrap(), "xn--fa-hia");
+
+        let invalid = [
+            // Hifen no inicio da label
+            "-café",
+            // ZERO WIDTH JOINER fora do contexto permitido (CONTEXTJ)
+            "a\u{200D}é",
+            // Label com letras da esquerda para a direita e hebraico (bidi)
+            "é\u{05D0}",
+            // Ponto de largura total, que partiria a label
+            "a\u{FF0E}é",
+            // So com caracteres que o mapeamento apaga
+            "\u{00AD}\u{00AD}",
+            // Codepoint de uso privado, proibido
+            "a\u{E000}é",
+        ];
+        for label in invalid {
+            assert_eq!(
+                to_ascii(label),
+                Err("Invalid internationalized label"),
+                "{label}"
+            );
+        }
+    }
+}
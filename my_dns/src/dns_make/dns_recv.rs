@@ -1,21 +1,279 @@
+use crate::dns_make::dns_tsig::{self, TsigKey};
+use crate::dns_make::dns_wire::{self, PduFormat, WireError};
 use crate::dns_structs::dns_message::DNSMessage;
 use bincode;
-use std::{net::{SocketAddr, UdpSocket}, io};
+use log::error;
+use std::{net::{SocketAddr, UdpSocket}, io::{self, Read}, time::{Duration, Instant}};
+
+// Maior datagrama UDP possivel, para que nenhuma mensagem seja cortada na rececao
+const MAX_DATAGRAM_LEN: usize = 65535;
 
 pub enum RecvError{
     IOError(io::Error),
-    DeserializeError(bincode::Error)
+    DeserializeError(bincode::Error),
+    WireError(WireError)
 }
 
 pub fn recv(incoming_socket: &UdpSocket) -> Result<(DNSMessage,SocketAddr),RecvError> {
-    let mut recv_buf = [0; 1000];
-    let (_, src_addr) = match incoming_socket.recv_from(&mut recv_buf) {
+    let mut recv_buf = vec![0; MAX_DATAGRAM_LEN];
+    let (size, src_addr) = match incoming_socket.recv_from(&mut recv_buf) {
         Ok(bytes_and_addr) => bytes_and_addr,
         Err(err) => return Err(RecvError::IOError(err))
     };
-    let dns_message: DNSMessage = match bincode::deserialize(&recv_buf){
-        Ok(message) => message,
-        Err(err) => return Err(RecvError::DeserializeError(err))
-    };
+    let (dns_message, _format) = decode_pdu(&recv_buf[..size])?;
     Ok((dns_message,src_addr))
 }
+
+// Espera pela resposta a uma pergunta enviada por UDP. Datagramas de outra origem, que nao se
+// conseguem ler ou que nao correspondem a pergunta (ID ou pergunta diferentes) sao descartados,
+// para dificultar respostas forjadas, e a espera continua ate acabar o tempo. Com `exact_case` o
+// nome da pergunta tem de vir com as mesmas maiusculas e minusculas (0x20). Se a pergunta foi
+// assinada, `tsig` tem a chave e o MAC da pergunta e so e aceite uma resposta assinada por ela.
+// Se ate ao fim do tempo o servidor so enviou datagramas que nao se conseguem ler, e esse o erro
+// devolvido, para quem perguntou poder repetir a pergunta noutro formato
+pub fn recv_response(
+    socket: &UdpSocket,
+    dns_query: &DNSMessage,
+    server_addr: SocketAddr,
+    timeout: Duration,
+    exact_case: bool,
+    tsig: Option<(&TsigKey, &[u8])>,
+) -> Result<DNSMessage, RecvError> {
+    let deadline = Instant::now() + timeout;
+    let mut recv_buf = vec![0; MAX_DATAGRAM_LEN];
+    let mut undecodable: Option<RecvError> = None;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            let timed_out = io::Error::from(io::ErrorKind::TimedOut);
+            return Err(undecodable.unwrap_or(RecvError::IOError(timed_out)));
+        }
+        if let Err(err) = socket.set_read_timeout(Some(remaining)) {
+            return Err(RecvError::IOError(err));
+        }
+        let (size, src_addr) = match socket.recv_from(&mut recv_buf) {
+            Ok(bytes_and_addr) => bytes_and_addr,
+            Err(err) => return Err(undecodable.unwrap_or(RecvError::IOError(err))),
+        };
+        if !is_expected_source(server_addr, src_addr) {
+            error!("ER response-source-mismatch {} {}", src_addr, server_addr);
+            continue;
+        }
+        let received = &recv_buf[..size];
+        let pdu = match tsig {
+            Some((key, mac)) => {
+                match dns_tsig::verify_response(received, key, mac, dns_tsig::now()) {
+                    Ok(pdu) => pdu,
+                    Err(reason) => {
+                        error!("ER {} {}", reason, src_addr);
+                        continue;
+                    }
+                }
+            }
+            None => received.to_vec(),
+        };
+        let dns_message = match decode_pdu(&pdu) {
+            Ok((dns_message, _format)) => dns_message,
+            Err(err) => {
+                error!("ER pdu-deserialize-fail {}", src_addr);
+                undecodable = Some(err);
+                continue;
+            }
+        };
+        match response_mismatch(dns_query, &dns_message, exact_case) {
+            Some(reason) => error!("ER {} {}", reason, src_addr),
+            None => return Ok(dns_message),
+        }
+    }
+}
+
+// Uma pergunta enviada para 0.0.0.0 (ou ::) e respondida a partir de um endereco local
+fn is_expected_source(server_addr: SocketAddr, src_addr: SocketAddr) -> bool {
+    src_addr == server_addr
+        || (server_addr.ip().is_unspecified()
+            && src_addr.ip().is_loopback()
+            && src_addr.port() == server_addr.port())
+}
+
+// A resposta tem de repetir o ID e a pergunta enviados. O nome e comparado sem distinguir
+// maiusculas de minusculas, exceto com `exact_case`
+pub fn response_mismatch(
+    dns_query: &DNSMessage,
+    response: &DNSMessage,
+    exact_case: bool,
+) -> Option<&'static str> {
+    let query_info = &dns_query.data.query_info;
+    let response_info = &response.data.query_info;
+    if response.header.message_id != dns_query.header.message_id {
+        Some("response-id-mismatch")
+    } else if response_info.type_of_value != query_info.type_of_value
+        || response_info.name != query_info.name
+    {
+        Some("response-question-mismatch")
+    } else if exact_case && response_info.name.getparts() != query_info.name.getparts() {
+        Some("response-case-mismatch")
+    } else {
+        None
+    }
+}
+
+// Le uma mensagem de uma ligacao TCP, precedida do seu tamanho em 2 bytes
+pub fn recv_tcp(stream: &mut impl Read) -> Result<(DNSMessage, PduFormat), RecvError> {
+    let pdu = match read_tcp_pdu(stream) {
+        Ok(pdu) => pdu,
+        Err(err) => return Err(RecvError::IOError(err)),
+    };
+    decode_pdu(&pdu)
+}
+
+pub fn read_tcp_pdu(stream: &mut impl Read) -> Result<Vec<u8>, io::Error> {
+    let mut len_buf = [0u8; 2];
+    stream.read_exact(&mut len_buf)?;
+    let mut pdu = vec![0u8; u16::from_be_bytes(len_buf).into()];
+    stream.read_exact(&mut pdu)?;
+    Ok(pdu)
+}
+
+// Descobre o formato do PDU recebido: primeiro tenta o formato RFC 1035, que e estrito
+// (todos os bytes tem de ser consumidos), e depois a DNSMessage serializada com bincode
+pub fn decode_pdu(buf: &[u8]) -> Result<(DNSMessage, PduFormat), RecvError> {
+    let wire_err = match dns_wire::decode(buf) {
+        Ok(message) => return Ok((message, PduFormat::Standard)),
+        Err(err) => err,
+    };
+    match bincode::deserialize::<DNSMessage>(buf) {
+        Ok(message) => Ok((message, PduFormat::Legacy)),
+        Err(err) => {
+            // Um cabecalho RFC 1035 valido indica que o erro relevante e o do formato standard
+            if dns_wire::query_header_id(buf).is_some() {
+                Err(RecvError::WireError(wire_err))
+            } else {
+                Err(RecvError::DeserializeError(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_make::dns_send::{self, UDP_PAYLOAD_LIMIT};
+    use crate::dns_structs::{dns_domain_name::Domain, dns_message::QueryType};
+
+    fn query() -> DNSMessage {
+        let mut query = DNSMessage::new();
+        query.data.query_info.name = Domain::new("www.example.com.".to_string());
+        query.data.query_info.type_of_value = QueryType::A;
+        query
+    }
+
+    #[test]
+    fn test_response_mismatch() {
+        let query = query();
+        assert_eq!(response_mismatch(&query, &query, true), None);
+
+        let mut response = query.to_owned();
+        response.data.query_info.name = Domain::new("WWW.Example.COM.".to_string());
+        assert_eq!(response_mismatch(&query, &response, false), None);
+        assert_eq!(
+            response_mismatch(&query, &response, true),
+            Some("response-case-mismatch")
+        );
+
+        response.header.message_id = query.header.message_id.wrapping_add(1);
+        assert_eq!(
+            response_mismatch(&query, &response, false),
+            Some("response-id-mismatch")
+        );
+
+        let mut response = query.to_owned();
+        response.data.query_info.type_of_value = QueryType::MX;
+        assert_eq!(
+            response_mismatch(&query, &response, false),
+            Some("response-question-mismatch")
+        );
+        response.data.query_info = query.data.query_info.to_owned();
+        response.data.query_info.name = Domain::new("mail.example.com.".to_string());
+        assert_eq!(
+            response_mismatch(&query, &response, false),
+            Some("response-question-mismatch")
+        );
+    }
+
+    #[test]
+    fn test_recv_response_drops_spoofed() {
+        let resolver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver_addr = resolver.local_addr().unwrap().to_string();
+        let server_addr = server.local_addr().unwrap();
+        let query = query();
+
+        let mut wrong_id = query.to_owned();
+        wrong_id.header.message_id = query.header.message_id.wrapping_add(1);
+        let mut wrong_question = query.to_owned();
+        wrong_question.data.query_info.name = Domain::new("evil.example.com.".to_string());
+
+        // Pacotes forjados chegam antes da resposta verdadeira e sao todos ignorados
+        dns_send::send(
+            query.to_owned(),
+            &spoofer,
+            resolver_addr.to_owned(),
+            UDP_PAYLOAD_LIMIT,
+        )
+        .unwrap();
+        dns_send::send(
+            wrong_id,
+            &server,
+            resolver_addr.to_owned(),
+            UDP_PAYLOAD_LIMIT,
+        )
+        .unwrap();
+        dns_send::send(
+            wrong_question,
+            &server,
+            resolver_addr.to_owned(),
+            UDP_PAYLOAD_LIMIT,
+        )
+        .unwrap();
+        server.send_to(&[0xFF; 3], &resolver_addr).unwrap();
+        let mut answer = query.to_owned();
+        answer.header.number_of_values = Some(0);
+        dns_send::send(
+            answer.to_owned(),
+            &server,
+            resolver_addr.to_owned(),
+            UDP_PAYLOAD_LIMIT,
+        )
+        .unwrap();
+
+        let timeout = Duration::new(1, 0);
+        let received = recv_response(&resolver, &query, server_addr, timeout, false, None)
+            .ok()
+            .unwrap();
+        assert_eq!(received, answer);
+
+        // So com pacotes forjados a espera acaba quando passa o tempo
+        dns_send::send(
+            query.to_owned(),
+            &spoofer,
+            resolver_addr.to_owned(),
+            UDP_PAYLOAD_LIMIT,
+        )
+        .unwrap();
+        let started = Instant::now();
+        let timeout = Duration::from_millis(200);
+        match recv_response(&resolver, &query, server_addr, timeout, false, None) {
+            Err(RecvError::IOError(_)) => (),
+            _ => panic!("Spoofed response accepted"),
+        }
+        assert!(started.elapsed() >= timeout);
+
+        // Uma resposta do servidor que nao se consegue ler e o erro devolvido no fim do tempo
+        server.send_to(&[0xFF; 3], &resolver_addr).unwrap();
+        match recv_response(&resolver, &query, server_addr, timeout, false, None) {
+            Err(RecvError::DeserializeError(_)) => (),
+            _ => panic!("Undecodable response not reported"),
+        }
+    }
+}
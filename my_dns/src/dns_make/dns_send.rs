@@ -1,15 +1,83 @@
+use crate::dns_make::dns_wire::{self, PduFormat};
 use crate::dns_structs::dns_message::*;
 use bincode;
-use std::{io, net::UdpSocket};
+use std::{
+    io::{self, Write},
+    net::UdpSocket,
+};
 
+// Tamanho maximo de uma resposta enviada por UDP (RFC 1035). Respostas maiores sao
+// substituidas por uma resposta truncada (flag TC), que o cliente repete por TCP
+pub const UDP_PAYLOAD_LIMIT: usize = 512;
+
+// Tamanho UDP anunciado no OPT quando se usa EDNS(0), que evita a fragmentacao IP
+pub const EDNS_UDP_PAYLOAD: u16 = 1232;
+
+// Envia por UDP truncando a resposta se passar o tamanho negociado com o cliente
 pub fn send(
     dns_message: DNSMessage,
     socket: &UdpSocket,
     remote_addr_and_port: String,
+    payload_limit: usize,
+) -> Result<usize, std::io::Error> {
+    send_as(
+        dns_message,
+        socket,
+        remote_addr_and_port,
+        PduFormat::Legacy,
+        payload_limit,
+    )
+}
+
+pub fn send_as(
+    dns_message: DNSMessage,
+    socket: &UdpSocket,
+    remote_addr_and_port: String,
+    format: PduFormat,
+    payload_limit: usize,
 ) -> Result<usize, std::io::Error> {
-    //Serialize the DNSMessage to JSON (Temporarily)
-    let dns_message_serialized = bincode::serialize(&dns_message).unwrap();
+    let mut dns_message_serialized = encode_pdu(&dns_message, format)?;
+    if dns_message_serialized.len() > payload_limit {
+        dns_message_serialized = encode_pdu(&dns_message.truncated(), format)?;
+    }
     let dns_message_bytes: &[u8] = &dns_message_serialized;
     //Send DNSMessage to the Dns Server
     socket.send_to(dns_message_bytes, remote_addr_and_port.as_str())
 }
+
+// Por TCP cada mensagem vai precedida do seu tamanho em 2 bytes (RFC 1035 4.2.2)
+pub fn send_tcp(
+    dns_message: &DNSMessage,
+    stream: &mut impl Write,
+    format: PduFormat,
+) -> Result<usize, io::Error> {
+    let dns_message_serialized = encode_pdu(dns_message, format)?;
+    write_tcp_pdu(stream, &dns_message_serialized)
+}
+
+pub fn write_tcp_pdu(stream: &mut impl Write, pdu: &[u8]) -> Result<usize, io::Error> {
+    let pdu_len: u16 = match pdu.len().try_into() {
+        Ok(len) => len,
+        Err(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message too long for TCP",
+            ))
+        }
+    };
+    let mut framed = Vec::with_capacity(pdu.len() + 2);
+    framed.extend_from_slice(&pdu_len.to_be_bytes());
+    framed.extend_from_slice(pdu);
+    stream.write_all(&framed)?;
+    stream.flush()?;
+    Ok(framed.len())
+}
+
+pub fn encode_pdu(dns_message: &DNSMessage, format: PduFormat) -> Result<Vec<u8>, io::Error> {
+    match format {
+        PduFormat::Standard => dns_wire::encode(dns_message)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err))),
+        PduFormat::Legacy => bincode::serialize(dns_message)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+    }
+}
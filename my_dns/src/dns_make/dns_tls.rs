@@ -0,0 +1,143 @@
+use std::{
+    io::Write,
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    time::Duration,
+};
+
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+// Porta do DNS sobre TLS (RFC 7858)
+pub const DOT_PORT: u16 = 853;
+
+// Identificador ALPN do DNS sobre TLS
+const DOT_ALPN: &[u8] = b"dot";
+
+// Configuracao TLS do servidor, carregada uma vez e partilhada por todas as ligacoes
+pub type TlsServerConfig = Arc<ServerConfig>;
+pub type TlsServerStream = StreamOwned<ServerConnection, TcpStream>;
+pub type TlsClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+// Configuracao TLS do servidor a partir do certificado (cadeia) e chave privada em PEM
+pub fn load_server_config(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<TlsServerConfig, &'static str> {
+    let certs = read_certificates(cert_path)?;
+    let key = match PrivateKeyDer::from_pem_file(key_path) {
+        Ok(key) => key,
+        Err(_) => return Err("Invalid private key file"),
+    };
+    let mut config = match ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+    {
+        Ok(builder) => match builder.with_no_client_auth().with_single_cert(certs, key) {
+            Ok(config) => config,
+            Err(_) => return Err("Certificate does not match the private key"),
+        },
+        Err(_) => return Err("Unsupported TLS protocol versions"),
+    };
+    config.alpn_protocols = vec![DOT_ALPN.to_vec()];
+    Ok(Arc::new(config))
+}
+
+// Faz o handshake do lado do servidor sobre uma ligacao TCP ja aceite
+pub fn accept(
+    config: &TlsServerConfig,
+    stream: TcpStream,
+) -> Result<TlsServerStream, &'static str> {
+    let connection = match ServerConnection::new(config.clone()) {
+        Ok(connection) => connection,
+        Err(_) => return Err("tls-handshake-fail"),
+    };
+    let mut tls_stream = StreamOwned::new(connection, stream);
+    while tls_stream.conn.is_handshaking() {
+        if tls_stream.conn.complete_io(&mut tls_stream.sock).is_err() {
+            return Err("tls-handshake-fail");
+        }
+    }
+    Ok(tls_stream)
+}
+
+// Avisa o outro lado que a sessao TLS terminou antes de fechar a ligacao
+pub fn close<C, S>(tls_stream: &mut StreamOwned<C, TcpStream>)
+where
+    C: std::ops::DerefMut + std::ops::Deref<Target = rustls::ConnectionCommon<S>>,
+    S: rustls::SideData,
+{
+    tls_stream.conn.send_close_notify();
+    let _ = tls_stream.flush();
+}
+
+// Lado cliente do DNS sobre TLS. So confia nos certificados do ficheiro PEM dado, que pode
+// ser a CA dos servidores ou o proprio certificado auto-assinado de um servidor
+#[derive(Clone, Debug)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+}
+
+impl TlsConnector {
+    pub fn from_ca_file(ca_path: &str) -> Result<Self, &'static str> {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certificates(ca_path)? {
+            if roots.add(cert).is_err() {
+                return Err("Invalid CA certificate");
+            }
+        }
+        let mut config =
+            match ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+            {
+                Ok(builder) => builder.with_root_certificates(roots).with_no_client_auth(),
+                Err(_) => return Err("Unsupported TLS protocol versions"),
+            };
+        config.alpn_protocols = vec![DOT_ALPN.to_vec()];
+        Ok(TlsConnector {
+            config: Arc::new(config),
+        })
+    }
+
+    // Liga ao servidor e valida o certificado contra o endereco IP do servidor
+    pub fn connect(
+        &self,
+        server_ip: SocketAddr,
+        timeout: Duration,
+    ) -> Result<TlsClientStream, &'static str> {
+        let stream = match TcpStream::connect_timeout(&server_ip, timeout) {
+            Ok(stream) => stream,
+            Err(_) => return Err("tls-connect-fail"),
+        };
+        if stream.set_read_timeout(Some(timeout)).is_err()
+            || stream.set_write_timeout(Some(timeout)).is_err()
+        {
+            return Err("tls-connect-fail");
+        }
+        let server_name = ServerName::IpAddress(server_ip.ip().into());
+        let connection = match ClientConnection::new(self.config.clone(), server_name) {
+            Ok(connection) => connection,
+            Err(_) => return Err("tls-handshake-fail"),
+        };
+        let mut tls_stream = StreamOwned::new(connection, stream);
+        while tls_stream.conn.is_handshaking() {
+            if tls_stream.conn.complete_io(&mut tls_stream.sock).is_err() {
+                return Err("tls-handshake-fail");
+            }
+        }
+        Ok(tls_stream)
+    }
+}
+
+fn read_certificates(cert_path: &str) -> Result<Vec<CertificateDer<'static>>, &'static str> {
+    let certs = match CertificateDer::pem_file_iter(cert_path) {
+        Ok(certs) => certs.collect::<Result<Vec<_>, _>>(),
+        Err(_) => return Err("Failed to open certificate file"),
+    };
+    match certs {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        _ => Err("Invalid certificate file"),
+    }
+}
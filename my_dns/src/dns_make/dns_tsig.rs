@@ -0,0 +1,484 @@
+use std::{
+    slice,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::hmac;
+
+use crate::{
+    dns_make::dns_wire::{self, TsigRecord, WireError},
+    dns_structs::dns_domain_name::Domain,
+};
+
+// Unico algoritmo de assinatura suportado (RFC 8945 6)
+pub const HMAC_SHA256: &str = "hmac-sha256";
+
+// Diferenca maxima, em segundos, entre o relogio de quem assina e o de quem verifica
+pub const FUDGE: u16 = 300;
+
+// Codigos de erro do campo error do TSIG (RFC 8945 3)
+const BADSIG: u16 = 16;
+const BADKEY: u16 = 17;
+const BADTIME: u16 = 18;
+
+// Segredo partilhado entre dois servidores ou entre um cliente e um servidor,
+// identificado pelo nome da chave
+#[derive(Clone, Debug, PartialEq)]
+pub struct TsigKey {
+    name: Domain,
+    secret: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TsigError {
+    // Chave desconhecida ou algoritmo diferente de HMAC_SHA256
+    BadKey,
+    // O MAC nao corresponde a mensagem
+    BadSig,
+    // Assinatura feita fora da janela de FUDGE segundos
+    BadTime,
+}
+
+// Resultado da verificacao do TSIG de uma mensagem, guardado para assinar a resposta
+#[derive(Clone, Debug)]
+pub struct TsigContext {
+    request: TsigRecord,
+    key: Option<TsigKey>,
+    error: Option<TsigError>,
+}
+
+impl TsigKey {
+    pub fn new(name: Domain, secret: Vec<u8>) -> Self {
+        TsigKey { name, secret }
+    }
+
+    pub fn from_base64(name: Domain, secret: &str) -> Result<Self, &'static str> {
+        match STANDARD.decode(secret) {
+            Ok(secret) if !secret.is_empty() => Ok(TsigKey::new(name, secret)),
+            _ => Err("Invalid TSIG secret"),
+        }
+    }
+
+    pub fn get_name(&self) -> &Domain {
+        &self.name
+    }
+
+    // Os nomes das chaves comparam-se sem distinguir maiusculas
+    pub fn is_named(&self, name: &Domain) -> bool {
+        self.name == *name
+    }
+
+    fn hmac_key(&self) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, &self.secret)
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        hmac::sign(&self.hmac_key(), data).as_ref().to_vec()
+    }
+
+    // Comparacao em tempo constante; um MAC truncado nunca e aceite
+    fn verify_mac(&self, data: &[u8], mac: &[u8]) -> bool {
+        hmac::verify(&self.hmac_key(), data, mac).is_ok()
+    }
+}
+
+impl TsigError {
+    fn code(&self) -> u16 {
+        match self {
+            TsigError::BadKey => BADKEY,
+            TsigError::BadSig => BADSIG,
+            TsigError::BadTime => BADTIME,
+        }
+    }
+
+    // Descricao usada nos eventos EZ
+    pub fn event(&self) -> &'static str {
+        match self {
+            TsigError::BadKey => "tsig-bad-key",
+            TsigError::BadSig => "tsig-bad-signature",
+            TsigError::BadTime => "tsig-bad-time",
+        }
+    }
+}
+
+impl TsigContext {
+    // Chave com que a mensagem foi assinada, so quando a assinatura e valida
+    pub fn verified_key(&self) -> Option<&TsigKey> {
+        match self.error {
+            None => self.key.as_ref(),
+            Some(_) => None,
+        }
+    }
+
+    pub fn get_error(&self) -> Option<TsigError> {
+        self.error
+    }
+
+    // MAC da mensagem verificada, que encadeia a assinatura do que vem a seguir
+    pub fn get_mac(&self) -> &[u8] {
+        &self.request.mac
+    }
+
+    // Assina a resposta com a chave da pergunta. Se a chave ou o MAC da pergunta nao forem
+    // validos a resposta leva o erro num TSIG sem MAC (RFC 8945 5.3.2)
+    pub fn sign_response(&self, response: &mut Vec<u8>, now: u64) -> Result<(), WireError> {
+        let error = self.error.map(|error| error.code()).unwrap_or(0);
+        match (&self.key, self.error) {
+            (Some(key), None) => {
+                sign_with(response, key, Some(&self.request.mac), now, 0, Vec::new())?;
+            }
+            // A hora do servidor vai nos other data para o cliente poder corrigir o relogio
+            (Some(key), Some(TsigError::BadTime)) => {
+                sign_with(
+                    response,
+                    key,
+                    Some(&self.request.mac),
+                    self.request.time_signed,
+                    error,
+                    now.to_be_bytes()[2..].to_vec(),
+                )?;
+            }
+            _ => {
+                let record = TsigRecord {
+                    key_name: self.request.key_name.clone(),
+                    algorithm: self.request.algorithm.clone(),
+                    time_signed: now,
+                    fudge: FUDGE,
+                    mac: Vec::new(),
+                    original_id: message_id(response)?,
+                    error,
+                    other_data: Vec::new(),
+                };
+                dns_wire::append_tsig(response, &record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Assina uma pergunta ja codificada. Devolve o MAC, necessario para verificar a resposta
+pub fn sign_request(message: &mut Vec<u8>, key: &TsigKey, now: u64) -> Result<Vec<u8>, WireError> {
+    sign_with(message, key, None, now, 0, Vec::new())
+}
+
+// Separa e verifica o TSIG de uma mensagem recebida; request_mac e o MAC da pergunta quando a
+// mensagem e uma resposta. Devolve a mensagem sem o TSIG e, se vinha assinada, o resultado
+pub fn verify_message(
+    bytes: &[u8],
+    keys: &[TsigKey],
+    request_mac: Option<&[u8]>,
+    now: u64,
+) -> Result<Option<(Vec<u8>, TsigContext)>, WireError> {
+    let (message, record) = match dns_wire::split_tsig(bytes)? {
+        Some(message_and_record) => message_and_record,
+        None => return Ok(None),
+    };
+    let key = keys.iter().find(|key| key.is_named(&record.key_name));
+    let algorithm_supported = record.algorithm == Domain::new(HMAC_SHA256.to_string());
+    let error = match key {
+        Some(key) if algorithm_supported => {
+            let mut data = mac_prefix(request_mac);
+            data.extend_from_slice(&message);
+            data.extend_from_slice(&dns_wire::tsig_variables(&record)?);
+            if !key.verify_mac(&data, &record.mac) {
+                Some(TsigError::BadSig)
+            } else if now.abs_diff(record.time_signed) > record.fudge as u64 {
+                Some(TsigError::BadTime)
+            } else {
+                None
+            }
+        }
+        _ => Some(TsigError::BadKey),
+    };
+    let context = TsigContext {
+        request: record,
+        key: key.cloned(),
+        error,
+    };
+    Ok(Some((message, context)))
+}
+
+// Verifica a resposta a uma pergunta assinada com a chave e devolve-a sem o TSIG. Uma resposta
+// sem TSIG, ou com um TSIG que nao verifica, e recusada (RFC 8945 5.3.2)
+pub fn verify_response(
+    bytes: &[u8],
+    key: &TsigKey,
+    request_mac: &[u8],
+    now: u64,
+) -> Result<Vec<u8>, &'static str> {
+    match verify_message(bytes, slice::from_ref(key), Some(request_mac), now) {
+        Ok(Some((message, context))) => match context.get_error() {
+            None => Ok(message),
+            Some(error) => Err(error.event()),
+        },
+        Ok(None) => Err("tsig-unsigned-response"),
+        Err(_) => Err("tsig-invalid-response"),
+    }
+}
+
+// MAC dos dados que seguem uma mensagem assinada na mesma ligacao (como as mensagens seguintes
+// de um AXFR, RFC 8945 5.3.1): cobre o MAC anterior, os dados e a hora da assinatura
+pub fn sign_data(key: &TsigKey, prior_mac: &[u8], data: &[u8], time_signed: u64) -> Vec<u8> {
+    key.mac(&stream_digest_data(prior_mac, data, time_signed))
+}
+
+pub fn verify_data(
+    key: &TsigKey,
+    prior_mac: &[u8],
+    data: &[u8],
+    time_signed: u64,
+    mac: &[u8],
+    now: u64,
+) -> Result<(), TsigError> {
+    if !key.verify_mac(&stream_digest_data(prior_mac, data, time_signed), mac) {
+        return Err(TsigError::BadSig);
+    }
+    if now.abs_diff(time_signed) > FUDGE as u64 {
+        return Err(TsigError::BadTime);
+    }
+    Ok(())
+}
+
+// Segundos desde a epoch, a hora usada nas assinaturas
+pub fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+fn sign_with(
+    message: &mut Vec<u8>,
+    key: &TsigKey,
+    request_mac: Option<&[u8]>,
+    time_signed: u64,
+    error: u16,
+    other_data: Vec<u8>,
+) -> Result<Vec<u8>, WireError> {
+    let mut record = TsigRecord {
+        key_name: key.name.clone(),
+        algorithm: Domain::new(HMAC_SHA256.to_string()),
+        time_signed,
+        fudge: FUDGE,
+        mac: Vec::new(),
+        original_id: message_id(message)?,
+        error,
+        other_data,
+    };
+    let mut data = mac_prefix(request_mac);
+    data.extend_from_slice(message);
+    data.extend_from_slice(&dns_wire::tsig_variables(&record)?);
+    record.mac = key.mac(&data);
+    dns_wire::append_tsig(message, &record)?;
+    Ok(record.mac)
+}
+
+// O MAC da pergunta entra no MAC da resposta precedido do seu tamanho
+fn mac_prefix(request_mac: Option<&[u8]>) -> Vec<u8> {
+    match request_mac {
+        Some(mac) => {
+            let mut prefix = (mac.len() as u16).to_be_bytes().to_vec();
+            prefix.extend_from_slice(mac);
+            prefix
+        }
+        None => Vec::new(),
+    }
+}
+
+fn stream_digest_data(prior_mac: &[u8], data: &[u8], time_signed: u64) -> Vec<u8> {
+    let mut digest_data = mac_prefix(Some(prior_mac));
+    digest_data.extend_from_slice(data);
+    digest_data.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    digest_data.extend_from_slice(&FUDGE.to_be_bytes());
+    digest_data
+}
+
+fn message_id(message: &[u8]) -> Result<u16, WireError> {
+    match message.get(0..2) {
+        Some(id) => Ok(u16::from_be_bytes([id[0], id[1]])),
+        None => Err(WireError::Truncated),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static QUERY_WWW_A: &[u8] = include_bytes!("../../etc/fixtures/query-www-example-com-a.bin");
+    static RESPONSE_WWW_A: &[u8] =
+        include_bytes!("../../etc/fixtures/response-www-example-com-a.bin");
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn key(name: &str, secret: &[u8]) -> TsigKey {
+        TsigKey::new(Domain::new(name.to_string()), secret.to_vec())
+    }
+
+    fn signed_query(key: &TsigKey) -> (Vec<u8>, Vec<u8>) {
+        let mut query = QUERY_WWW_A.to_vec();
+        let mac = sign_request(&mut query, key, NOW).unwrap();
+        (query, mac)
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let transfer_key = key("transfer-key", b"segredo partilhado");
+        let (query, request_mac) = signed_query(&transfer_key);
+        assert_eq!(mac_prefix(Some(&request_mac)).len(), 34);
+
+        let (message, context) =
+            verify_message(&query, std::slice::from_ref(&transfer_key), None, NOW + 10)
+                .unwrap()
+                .unwrap();
+        assert_eq!(message, QUERY_WWW_A);
+        assert_eq!(context.verified_key(), Some(&transfer_key));
+
+        // A resposta e verificada com o MAC da pergunta
+        let mut response = RESPONSE_WWW_A.to_vec();
+        context.sign_response(&mut response, NOW + 10).unwrap();
+        let (message, context) = verify_message(
+            &response,
+            std::slice::from_ref(&transfer_key),
+            Some(&request_mac),
+            NOW + 20,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(message, RESPONSE_WWW_A);
+        assert_eq!(context.get_error(), None);
+        assert!(dns_wire::decode(&message).is_ok());
+
+        // Sem o MAC da pergunta a resposta nao e valida
+        let (_, context) = verify_message(
+            &response,
+            std::slice::from_ref(&transfer_key),
+            None,
+            NOW + 20,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(context.get_error(), Some(TsigError::BadSig));
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let query_key = key("query-key", b"segredo");
+        let (query, request_mac) = signed_query(&query_key);
+        let (_, context) = verify_message(&query, std::slice::from_ref(&query_key), None, NOW)
+            .unwrap()
+            .unwrap();
+        let mut response = RESPONSE_WWW_A.to_vec();
+        context.sign_response(&mut response, NOW).unwrap();
+        assert_eq!(
+            verify_response(&response, &query_key, &request_mac, NOW),
+            Ok(RESPONSE_WWW_A.to_vec())
+        );
+
+        // Uma resposta sem TSIG ou assinada com outra chave e recusada
+        assert_eq!(
+            verify_response(RESPONSE_WWW_A, &query_key, &request_mac, NOW),
+            Err("tsig-unsigned-response")
+        );
+        let other_key = key("query-key", b"outro segredo");
+        assert_eq!(
+            verify_response(&response, &other_key, &request_mac, NOW),
+            Err("tsig-bad-signature")
+        );
+    }
+
+    #[test]
+    fn test_unsigned_message() {
+        let keys = [key("transfer-key", b"segredo")];
+        assert!(verify_message(QUERY_WWW_A, &keys, None, NOW)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let (mut query, _) = signed_query(&key("transfer-key", b"segredo"));
+
+        let wrong_secret = [key("TRANSFER-KEY", b"outro segredo")];
+        let (_, context) = verify_message(&query, &wrong_secret, None, NOW)
+            .unwrap()
+            .unwrap();
+        assert_eq!(context.get_error(), Some(TsigError::BadSig));
+        assert!(context.verified_key().is_none());
+
+        // Alterar a mensagem invalida o MAC
+        query[2] ^= 0x01;
+        let keys = [key("transfer-key", b"segredo")];
+        let (_, context) = verify_message(&query, &keys, None, NOW).unwrap().unwrap();
+        assert_eq!(context.get_error(), Some(TsigError::BadSig));
+
+        let mut response = RESPONSE_WWW_A.to_vec();
+        context.sign_response(&mut response, NOW).unwrap();
+        let (_, record) = dns_wire::split_tsig(&response).unwrap().unwrap();
+        assert_eq!(record.error, BADSIG);
+        assert!(record.mac.is_empty());
+    }
+
+    #[test]
+    fn test_bad_key() {
+        let (query, _) = signed_query(&key("transfer-key", b"segredo"));
+        let (_, context) = verify_message(&query, &[key("query-key", b"segredo")], None, NOW)
+            .unwrap()
+            .unwrap();
+        assert_eq!(context.get_error(), Some(TsigError::BadKey));
+
+        let mut response = RESPONSE_WWW_A.to_vec();
+        context.sign_response(&mut response, NOW).unwrap();
+        let (_, record) = dns_wire::split_tsig(&response).unwrap().unwrap();
+        assert_eq!(record.error, BADKEY);
+        assert!(record.mac.is_empty());
+    }
+
+    #[test]
+    fn test_bad_time() {
+        let transfer_key = key("transfer-key", b"segredo");
+        let (query, request_mac) = signed_query(&transfer_key);
+        let late = NOW + FUDGE as u64 + 1;
+        let (_, context) = verify_message(&query, std::slice::from_ref(&transfer_key), None, late)
+            .unwrap()
+            .unwrap();
+        assert_eq!(context.get_error(), Some(TsigError::BadTime));
+
+        // O erro BADTIME vai assinado, com a hora do servidor nos other data
+        let mut response = RESPONSE_WWW_A.to_vec();
+        context.sign_response(&mut response, late).unwrap();
+        let (_, record) = dns_wire::split_tsig(&response).unwrap().unwrap();
+        assert_eq!(record.error, BADTIME);
+        assert_eq!(record.time_signed, NOW);
+        assert_eq!(record.other_data, late.to_be_bytes()[2..].to_vec());
+        let (_, context) = verify_message(
+            &response,
+            std::slice::from_ref(&transfer_key),
+            Some(&request_mac),
+            NOW,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(context.get_error(), None);
+    }
+
+    #[test]
+    fn test_signed_data() {
+        let transfer_key = key("transfer-key", b"segredo");
+        let (_, prior_mac) = signed_query(&transfer_key);
+        let data = b"example.com. SOASERIAL 2022111801";
+        let mac = sign_data(&transfer_key, &prior_mac, data, NOW);
+        assert_eq!(
+            verify_data(&transfer_key, &prior_mac, data, NOW, &mac, NOW),
+            Ok(())
+        );
+        assert_eq!(
+            verify_data(&transfer_key, &prior_mac, b"example.com.", NOW, &mac, NOW),
+            Err(TsigError::BadSig)
+        );
+        assert_eq!(
+            verify_data(&transfer_key, &prior_mac, data, NOW, &mac, NOW + 3600),
+            Err(TsigError::BadTime)
+        );
+    }
+}
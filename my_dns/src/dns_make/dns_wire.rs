@@ -0,0 +1,1547 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::dns_structs::{
+    dns_domain_name::Domain,
+    dns_message::{
+        DNSEntry, DNSMessage, DNSMessageData, DNSMessageHeaders, DNSQueryInfo, Flags, QueryType,
+        ResponseCode,
+    },
+    dns_resource_data::{Edns, EdnsOption, RData},
+};
+
+// Codificacao e descodificacao de mensagens DNS no formato binario do RFC 1035
+// (cabecalho de 12 bytes, seccao de pergunta e seccoes de resource records)
+
+const HEADER_LEN: usize = 12;
+const CLASS_IN: u16 = 1;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+const MAX_POINTER_HOPS: usize = 64;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_DS: u16 = 43;
+pub const TYPE_OPT: u16 = 41;
+pub const TYPE_RRSIG: u16 = 46;
+pub const TYPE_NSEC: u16 = 47;
+pub const TYPE_DNSKEY: u16 = 48;
+const TYPE_TSIG: u16 = 250;
+const CLASS_ANY: u16 = 255;
+
+const QR_BIT: u16 = 0x8000;
+const OPCODE_MASK: u16 = 0x7800;
+const AA_BIT: u16 = 0x0400;
+const TC_BIT: u16 = 0x0200;
+const RD_BIT: u16 = 0x0100;
+const AD_BIT: u16 = 0x0020;
+const RCODE_MASK: u16 = 0x000F;
+// Bit DO no TTL do OPT
+const DO_BIT: u32 = 0x8000;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PduFormat {
+    // Formato binario do RFC 1035, usado por resolvers standard (dig, kdig, drill)
+    Standard,
+    // DNSMessage serializada com bincode, usada pelas versoes anteriores do client e do server
+    Legacy,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WireError {
+    // O pacote acaba antes do campo que estava a ser lido
+    Truncated,
+    // Existem bytes depois da ultima seccao anunciada no cabecalho
+    TrailingBytes,
+    // A mensagem tem de ter exatamente uma pergunta
+    QuestionCount(u16),
+    UnsupportedOpcode(u8),
+    UnsupportedResponseCode(u16),
+    UnsupportedType(u16),
+    UnsupportedClass(u16),
+    // Mais resource records numa seccao do que cabem no DNSMessageHeaders
+    TooManyRecords(u16),
+    LabelTooLong,
+    NameTooLong,
+    EmptyLabel,
+    BadLabelType(u8),
+    // Label que nao e UTF-8 valido, que nao pode ser guardada num Domain sem perder bytes
+    InvalidLabel,
+    PointerLoop,
+    // O rdlength nao corresponde ao conteudo do rdata
+    BadRDataLength,
+    // Character-string de um TXT com mais de 255 bytes
+    StringTooLong,
+    // OPT fora dos valores extra, repetido, com dono diferente da raiz ou em falta
+    // numa resposta com RCODE estendido
+    BadOpt,
+    // TSIG que nao e o ultimo dos valores extra, com classe ou TTL errados ou rdata mal formado
+    BadTsig,
+    // Pergunta com as flags AA ou TC, que so existem em respostas
+    InvalidFlags,
+}
+
+// Resource record TSIG (RFC 8945). Nao faz parte da DNSMessage: e separado da mensagem em bytes
+// antes de a descodificar e acrescentado depois de a codificar, porque o MAC cobre os bytes
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TsigRecord {
+    pub key_name: Domain,
+    pub algorithm: Domain,
+    // Segundos desde a epoch, em 48 bits
+    pub time_signed: u64,
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    pub original_id: u16,
+    pub error: u16,
+    pub other_data: Vec<u8>,
+}
+
+pub fn encode(dns_message: &DNSMessage) -> Result<Vec<u8>, WireError> {
+    let mut encoder = Encoder::new();
+    let header = &dns_message.header;
+    let data = &dns_message.data;
+
+    // Os 8 bits de cima do RCODE vao no OPT
+    let rcode = response_code_value(header.response_code);
+    if rcode > RCODE_MASK && dns_message.get_edns().is_none() {
+        return Err(WireError::BadOpt);
+    }
+    encoder.extended_rcode = (rcode >> 4) as u8;
+
+    let response_values = data.response_values.as_deref().unwrap_or(&[]);
+    let authorities_values = data.authorities_values.as_deref().unwrap_or(&[]);
+    let extra_values = data.extra_values.as_deref().unwrap_or(&[]);
+
+    encoder.put_u16(header.message_id);
+    encoder.put_u16(encode_flags(header));
+    encoder.put_u16(1);
+    encoder.put_u16(section_len(response_values)?);
+    encoder.put_u16(section_len(authorities_values)?);
+    encoder.put_u16(section_len(extra_values)?);
+
+    encoder.put_name(&data.query_info.name)?;
+    encoder.put_u16(type_code(data.query_info.type_of_value));
+    encoder.put_u16(CLASS_IN);
+
+    for entry in response_values
+        .iter()
+        .chain(authorities_values)
+        .chain(extra_values)
+    {
+        encoder.put_entry(entry)?;
+    }
+    Ok(encoder.buf)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<DNSMessage, WireError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(WireError::Truncated);
+    }
+    let mut decoder = Decoder::new(bytes);
+
+    let message_id = decoder.get_u16()?;
+    let wire_flags = decoder.get_u16()?;
+    let question_count = decoder.get_u16()?;
+    let answer_count = decoder.get_u16()?;
+    let authority_count = decoder.get_u16()?;
+    let additional_count = decoder.get_u16()?;
+
+    let opcode = ((wire_flags & OPCODE_MASK) >> 11) as u8;
+    if opcode != 0 {
+        return Err(WireError::UnsupportedOpcode(opcode));
+    }
+    if question_count != 1 {
+        return Err(WireError::QuestionCount(question_count));
+    }
+
+    let name = decoder.get_name()?;
+    let type_of_value = query_type_of(decoder.get_u16()?)?;
+    let class = decoder.get_u16()?;
+    if class != CLASS_IN {
+        return Err(WireError::UnsupportedClass(class));
+    }
+
+    let response_values = decoder.get_section(answer_count)?;
+    let authorities_values = decoder.get_section(authority_count)?;
+    let extra_values = decoder.get_section(additional_count)?;
+
+    if !decoder.is_empty() {
+        return Err(WireError::TrailingBytes);
+    }
+    if has_opt(&response_values) || has_opt(&authorities_values) {
+        return Err(WireError::BadOpt);
+    }
+
+    let is_response = wire_flags & QR_BIT != 0;
+    let flags = [
+        (Flags::QUERY, !is_response),
+        (Flags::RECURSIVE, wire_flags & RD_BIT != 0),
+        (Flags::AUTHORITATIVE, wire_flags & AA_BIT != 0),
+        (Flags::TRUNCATED, wire_flags & TC_BIT != 0),
+        (Flags::AUTHENTICATED, wire_flags & AD_BIT != 0),
+    ]
+    .iter()
+    .filter(|(_, active)| *active)
+    .try_fold(Flags::empty(), |flags, (flag, _)| flags.with(*flag))
+    .map_err(|_| WireError::InvalidFlags)?;
+
+    let response_code = if is_response {
+        Some(decode_response_code(
+            ((decoder.extended_rcode.unwrap_or(0) as u16) << 4) | (wire_flags & RCODE_MASK),
+            wire_flags & AA_BIT != 0,
+            answer_count,
+            authority_count,
+        )?)
+    } else {
+        None
+    };
+
+    let header = DNSMessageHeaders {
+        message_id,
+        flags,
+        response_code,
+        number_of_values: section_count(&response_values),
+        number_of_authorities: section_count(&authorities_values),
+        number_of_extra_values: section_count(&extra_values),
+    };
+    let data = DNSMessageData {
+        query_info: DNSQueryInfo {
+            name,
+            type_of_value,
+        },
+        response_values,
+        authorities_values,
+        extra_values,
+    };
+    Ok(DNSMessage { header, data })
+}
+
+// Verifica se os bytes comecam com um cabecalho de pergunta RFC 1035 plausivel,
+// devolvendo o ID da mensagem para que possa ser respondida com FORMERR
+pub fn query_header_id(bytes: &[u8]) -> Option<u16> {
+    if bytes.len() < HEADER_LEN {
+        return None;
+    }
+    let wire_flags = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let question_count = u16::from_be_bytes([bytes[4], bytes[5]]);
+    if wire_flags & QR_BIT != 0 || wire_flags & OPCODE_MASK != 0 || question_count != 1 {
+        return None;
+    }
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// Resposta apenas com cabecalho, para pacotes que nem a pergunta permitem ler
+pub fn encode_format_error(message_id: u16) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN);
+    buf.extend_from_slice(&message_id.to_be_bytes());
+    buf.extend_from_slice(&(QR_BIT | 1).to_be_bytes());
+    buf.extend_from_slice(&[0; 8]);
+    buf
+}
+
+// Separa o TSIG, se existir, do fim de uma mensagem. Devolve a mensagem como foi assinada:
+// sem o TSIG, com o ARCOUNT decrementado e com o ID original
+pub fn split_tsig(bytes: &[u8]) -> Result<Option<(Vec<u8>, TsigRecord)>, WireError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(WireError::Truncated);
+    }
+    let mut decoder = Decoder::new(bytes);
+    decoder.pos = 4;
+    let question_count = decoder.get_u16()?;
+    let record_count = decoder.get_u16()? as usize
+        + decoder.get_u16()? as usize
+        + decoder.get_u16()? as usize;
+    for _ in 0..question_count {
+        decoder.get_name()?;
+        decoder.take(4)?;
+    }
+
+    let mut tsig = None;
+    for _ in 0..record_count {
+        if tsig.is_some() {
+            return Err(WireError::BadTsig);
+        }
+        let record_start = decoder.pos;
+        decoder.get_name()?;
+        let type_code = decoder.get_u16()?;
+        decoder.take(6)?;
+        let rdlength = decoder.get_u16()? as usize;
+        decoder.take(rdlength)?;
+        if type_code == TYPE_TSIG {
+            tsig = Some(record_start);
+        }
+    }
+    if !decoder.is_empty() {
+        return Err(WireError::TrailingBytes);
+    }
+
+    let record_start = match tsig {
+        Some(record_start) => record_start,
+        None => return Ok(None),
+    };
+    let mut decoder = Decoder::new(bytes);
+    decoder.pos = record_start;
+    let record = decoder.get_tsig()?;
+    if !decoder.is_empty() {
+        return Err(WireError::BadTsig);
+    }
+
+    let mut message = bytes[..record_start].to_vec();
+    let additional_count = u16::from_be_bytes([message[10], message[11]]);
+    if additional_count == 0 {
+        return Err(WireError::BadTsig);
+    }
+    message[0..2].copy_from_slice(&record.original_id.to_be_bytes());
+    message[10..12].copy_from_slice(&(additional_count - 1).to_be_bytes());
+    Ok(Some((message, record)))
+}
+
+// Acrescenta o TSIG ao fim de uma mensagem ja codificada, como ultimo dos valores extra
+pub fn append_tsig(bytes: &mut Vec<u8>, record: &TsigRecord) -> Result<(), WireError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(WireError::Truncated);
+    }
+    let additional_count = u16::from_be_bytes([bytes[10], bytes[11]])
+        .checked_add(1)
+        .ok_or(WireError::TooManyRecords(u16::MAX))?;
+
+    let mut encoder = Encoder::new();
+    encoder.put_name_with(&record.key_name, false)?;
+    encoder.put_u16(TYPE_TSIG);
+    encoder.put_u16(CLASS_ANY);
+    encoder.put_u32(0);
+    let rdlength_pos = encoder.buf.len();
+    encoder.put_u16(0);
+    encoder.put_name_with(&record.algorithm, false)?;
+    encoder.put_u48(record.time_signed);
+    encoder.put_u16(record.fudge);
+    encoder.put_bytes(&record.mac)?;
+    encoder.put_u16(record.original_id);
+    encoder.put_u16(record.error);
+    encoder.put_bytes(&record.other_data)?;
+    let rdlength = (encoder.buf.len() - rdlength_pos - 2) as u16;
+    encoder.buf[rdlength_pos..rdlength_pos + 2].copy_from_slice(&rdlength.to_be_bytes());
+
+    bytes[10..12].copy_from_slice(&additional_count.to_be_bytes());
+    bytes.extend_from_slice(&encoder.buf);
+    Ok(())
+}
+
+// Variaveis do TSIG que entram no calculo do MAC (RFC 8945 4.3.3), com os nomes em minusculas
+pub fn tsig_variables(record: &TsigRecord) -> Result<Vec<u8>, WireError> {
+    let mut encoder = Encoder::new();
+    encoder.put_name_with(&canonical_name(&record.key_name), false)?;
+    encoder.put_u16(CLASS_ANY);
+    encoder.put_u32(0);
+    encoder.put_name_with(&canonical_name(&record.algorithm), false)?;
+    encoder.put_u48(record.time_signed);
+    encoder.put_u16(record.fudge);
+    encoder.put_u16(record.error);
+    encoder.put_bytes(&record.other_data)?;
+    Ok(encoder.buf)
+}
+
+// Nome com todos os labels em minusculas, usado nas comparacoes e nos calculos criptograficos
+pub fn canonical_name(name: &Domain) -> Domain {
+    let mut canonical = Domain::new_empty();
+    canonical.set_parts(
+        name.getparts()
+            .iter()
+            .map(|label| label.to_ascii_lowercase())
+            .collect(),
+    );
+    canonical
+}
+
+fn encode_flags(header: &DNSMessageHeaders) -> u16 {
+    let mut wire_flags = 0;
+    if !header.flags.contains(Flags::QUERY) {
+        wire_flags |= QR_BIT;
+    }
+    if header.flags.contains(Flags::RECURSIVE) {
+        wire_flags |= RD_BIT;
+    }
+    if header.flags.contains(Flags::AUTHORITATIVE) {
+        wire_flags |= AA_BIT;
+    }
+    if header.flags.contains(Flags::TRUNCATED) {
+        wire_flags |= TC_BIT;
+    }
+    if header.flags.contains(Flags::AUTHENTICATED) {
+        wire_flags |= AD_BIT;
+    }
+    wire_flags | (response_code_value(header.response_code) & RCODE_MASK)
+}
+
+// RCODE de 12 bits: os 4 de baixo vao no cabecalho e os restantes no OPT.
+// Um referral (NonAuthoritative) e um NOERROR sem respostas, reconhecido pelas seccoes da mensagem
+fn response_code_value(response_code: Option<ResponseCode>) -> u16 {
+    match response_code {
+        None | Some(ResponseCode::NoError) | Some(ResponseCode::NonAuthoritative) => 0,
+        Some(ResponseCode::Malformed) => 1,
+        Some(ResponseCode::ServerFailure) => 2,
+        Some(ResponseCode::NXDomain) => 3,
+        Some(ResponseCode::Refused) => 5,
+        Some(ResponseCode::NotAuth) => 9,
+        Some(ResponseCode::BadVersion) => 16,
+    }
+}
+
+fn decode_response_code(
+    rcode: u16,
+    authoritative: bool,
+    answer_count: u16,
+    authority_count: u16,
+) -> Result<ResponseCode, WireError> {
+    match rcode {
+        0 if !authoritative && answer_count == 0 && authority_count > 0 => {
+            Ok(ResponseCode::NonAuthoritative)
+        }
+        0 => Ok(ResponseCode::NoError),
+        1 => Ok(ResponseCode::Malformed),
+        2 => Ok(ResponseCode::ServerFailure),
+        3 => Ok(ResponseCode::NXDomain),
+        5 => Ok(ResponseCode::Refused),
+        9 => Ok(ResponseCode::NotAuth),
+        16 => Ok(ResponseCode::BadVersion),
+        _ => Err(WireError::UnsupportedResponseCode(rcode)),
+    }
+}
+
+fn has_opt(entries: &Option<Vec<DNSEntry>>) -> bool {
+    entries
+        .iter()
+        .flatten()
+        .any(|entry| matches!(entry.data, RData::OPT(_)))
+}
+
+fn section_len(entries: &[DNSEntry]) -> Result<u16, WireError> {
+    entries
+        .len()
+        .try_into()
+        .map_err(|_| WireError::TooManyRecords(u16::MAX))
+}
+
+fn section_count(entries: &Option<Vec<DNSEntry>>) -> Option<u8> {
+    entries.as_ref().map(|vec| vec.len() as u8)
+}
+
+pub fn type_code(query_type: QueryType) -> u16 {
+    match query_type {
+        QueryType::A => TYPE_A,
+        QueryType::NS => TYPE_NS,
+        QueryType::CNAME => TYPE_CNAME,
+        QueryType::PTR => TYPE_PTR,
+        QueryType::MX => TYPE_MX,
+        QueryType::AAAA => TYPE_AAAA,
+        QueryType::TXT => TYPE_TXT,
+        QueryType::SRV => TYPE_SRV,
+        QueryType::SOA => TYPE_SOA,
+        QueryType::DNSKEY => TYPE_DNSKEY,
+        QueryType::RRSIG => TYPE_RRSIG,
+        QueryType::NSEC => TYPE_NSEC,
+        QueryType::DS => TYPE_DS,
+    }
+}
+
+fn query_type_of(code: u16) -> Result<QueryType, WireError> {
+    match code {
+        TYPE_A => Ok(QueryType::A),
+        TYPE_NS => Ok(QueryType::NS),
+        TYPE_CNAME => Ok(QueryType::CNAME),
+        TYPE_PTR => Ok(QueryType::PTR),
+        TYPE_MX => Ok(QueryType::MX),
+        TYPE_AAAA => Ok(QueryType::AAAA),
+        TYPE_TXT => Ok(QueryType::TXT),
+        TYPE_SRV => Ok(QueryType::SRV),
+        TYPE_SOA => Ok(QueryType::SOA),
+        TYPE_DNSKEY => Ok(QueryType::DNSKEY),
+        TYPE_RRSIG => Ok(QueryType::RRSIG),
+        TYPE_NSEC => Ok(QueryType::NSEC),
+        TYPE_DS => Ok(QueryType::DS),
+        _ => Err(WireError::UnsupportedType(code)),
+    }
+}
+
+pub fn rdata_type_code(data: &RData) -> u16 {
+    match data {
+        RData::A(_) => TYPE_A,
+        RData::NS(_) => TYPE_NS,
+        RData::CNAME(_) => TYPE_CNAME,
+        RData::SOA { .. } => TYPE_SOA,
+        RData::PTR(_) => TYPE_PTR,
+        RData::MX { .. } => TYPE_MX,
+        RData::AAAA(_) => TYPE_AAAA,
+        RData::TXT(_) => TYPE_TXT,
+        RData::SRV { .. } => TYPE_SRV,
+        RData::OPT(_) => TYPE_OPT,
+        RData::DNSKEY { .. } => TYPE_DNSKEY,
+        RData::RRSIG { .. } => TYPE_RRSIG,
+        RData::NSEC { .. } => TYPE_NSEC,
+        RData::DS { .. } => TYPE_DS,
+    }
+}
+
+// Record na forma canonica (RFC 4034 6.2), com o rdata ja canonico, tal como entra nos dados
+// cobertos por um RRSIG
+pub fn canonical_rr(
+    owner: &Domain,
+    type_code: u16,
+    ttl: u32,
+    rdata: &[u8],
+) -> Result<Vec<u8>, WireError> {
+    let mut encoder = Encoder::new();
+    encoder.put_name_with(&canonical_name(owner), false)?;
+    encoder.put_u16(type_code);
+    encoder.put_u16(CLASS_IN);
+    encoder.put_u32(ttl);
+    encoder.put_bytes(rdata)?;
+    Ok(encoder.buf)
+}
+
+// Nome de um dono na forma canonica, em minusculas e sem compressao
+pub fn canonical_owner(owner: &Domain) -> Result<Vec<u8>, WireError> {
+    let mut encoder = Encoder::new();
+    encoder.put_name_with(&canonical_name(owner), false)?;
+    Ok(encoder.buf)
+}
+
+// Forma canonica de um rdata (RFC 4034 6.2), usada nas assinaturas DNSSEC. O nome seguinte de um
+// NSEC mantem as maiusculas (RFC 6840 5.1)
+pub fn canonical_rdata(data: &RData) -> Result<Vec<u8>, WireError> {
+    let data = match data {
+        RData::NS(domain) => RData::NS(canonical_name(domain)),
+        RData::CNAME(domain) => RData::CNAME(canonical_name(domain)),
+        RData::PTR(domain) => RData::PTR(canonical_name(domain)),
+        RData::MX {
+            preference,
+            exchange,
+        } => RData::MX {
+            preference: *preference,
+            exchange: canonical_name(exchange),
+        },
+        RData::SRV {
+            priority,
+            weight,
+            port,
+            target,
+        } => RData::SRV {
+            priority: *priority,
+            weight: *weight,
+            port: *port,
+            target: canonical_name(target),
+        },
+        RData::SOA {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => RData::SOA {
+            mname: canonical_name(mname),
+            rname: canonical_name(rname),
+            serial: *serial,
+            refresh: *refresh,
+            retry: *retry,
+            expire: *expire,
+            minimum: *minimum,
+        },
+        RData::RRSIG {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer,
+            signature,
+        } => RData::RRSIG {
+            type_covered: *type_covered,
+            algorithm: *algorithm,
+            labels: *labels,
+            original_ttl: *original_ttl,
+            expiration: *expiration,
+            inception: *inception,
+            key_tag: *key_tag,
+            signer: canonical_name(signer),
+            signature: signature.clone(),
+        },
+        data => data.clone(),
+    };
+    let mut encoder = Encoder::new();
+    encoder.put_rdata(&data, false)?;
+    Ok(encoder.buf)
+}
+
+struct Encoder {
+    buf: Vec<u8>,
+    // Posicao de cada sufixo ja escrito, usada para a compressao de nomes
+    names: HashMap<Vec<String>, u16>,
+    // Bits de cima do RCODE, escritos no TTL do OPT
+    extended_rcode: u8,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Encoder {
+            buf: Vec::with_capacity(512),
+            names: HashMap::new(),
+            extended_rcode: 0,
+        }
+    }
+
+    fn put_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_u48(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_be_bytes()[2..]);
+    }
+
+    // Campo binario precedido do seu tamanho em 2 bytes
+    fn put_bytes(&mut self, bytes: &[u8]) -> Result<(), WireError> {
+        let len: u16 = match bytes.len().try_into() {
+            Ok(len) => len,
+            Err(_) => return Err(WireError::BadRDataLength),
+        };
+        self.put_u16(len);
+        self.buf.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn put_name(&mut self, name: &Domain) -> Result<(), WireError> {
+        self.put_name_with(name, true)
+    }
+
+    // O target de um SRV nao pode ser comprimido (RFC 2782)
+    fn put_name_with(&mut self, name: &Domain, compress: bool) -> Result<(), WireError> {
+        let labels = name.getparts();
+        let wire_len: usize = labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+        if wire_len > MAX_NAME_LEN {
+            return Err(WireError::NameTooLong);
+        }
+        for i in 0..labels.len() {
+            let suffix = labels[i..].to_vec();
+            if let Some(offset) = self.names.get(&suffix).filter(|_| compress) {
+                self.put_u16(0xC000 | offset);
+                return Ok(());
+            }
+            let label = labels[i].as_bytes();
+            if label.is_empty() {
+                return Err(WireError::EmptyLabel);
+            }
+            if label.len() > MAX_LABEL_LEN {
+                return Err(WireError::LabelTooLong);
+            }
+            // Apenas posicoes alcancaveis por um ponteiro de 14 bits
+            if self.buf.len() < 0x4000 {
+                self.names.insert(suffix, self.buf.len() as u16);
+            }
+            self.buf.push(label.len() as u8);
+            self.buf.extend_from_slice(label);
+        }
+        self.buf.push(0);
+        Ok(())
+    }
+
+    fn put_entry(&mut self, entry: &DNSEntry) -> Result<(), WireError> {
+        // No OPT a classe e o tamanho UDP anunciado e o TTL leva o RCODE estendido, a versao e o DO
+        let (class, ttl) = match &entry.data {
+            RData::OPT(edns) => {
+                let mut ttl = ((self.extended_rcode as u32) << 24) | ((edns.version as u32) << 16);
+                if edns.dnssec_ok {
+                    ttl |= DO_BIT;
+                }
+                (edns.udp_payload_size, ttl)
+            }
+            _ => (CLASS_IN, entry.ttl),
+        };
+        self.put_name(&entry.domain_name)?;
+        self.put_u16(rdata_type_code(&entry.data));
+        self.put_u16(class);
+        self.put_u32(ttl);
+
+        // O rdlength so e conhecido depois de escrever o rdata
+        let rdlength_pos = self.buf.len();
+        self.put_u16(0);
+        self.put_rdata(&entry.data, true)?;
+        let rdlength = match u16::try_from(self.buf.len() - rdlength_pos - 2) {
+            Ok(rdlength) => rdlength,
+            Err(_) => return Err(WireError::BadRDataLength),
+        };
+        self.buf[rdlength_pos..rdlength_pos + 2].copy_from_slice(&rdlength.to_be_bytes());
+        Ok(())
+    }
+
+    // Sem compressao todos os nomes sao escritos por extenso, como na forma canonica
+    fn put_rdata(&mut self, data: &RData, compress: bool) -> Result<(), WireError> {
+        match data {
+            RData::A(ip) => self.buf.extend_from_slice(&ip.octets()),
+            RData::AAAA(ip) => self.buf.extend_from_slice(&ip.octets()),
+            RData::NS(domain) | RData::CNAME(domain) | RData::PTR(domain) => {
+                self.put_name_with(domain, compress)?
+            }
+            RData::MX {
+                preference,
+                exchange,
+            } => {
+                self.put_u16(*preference);
+                self.put_name_with(exchange, compress)?;
+            }
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                self.put_u16(*priority);
+                self.put_u16(*weight);
+                self.put_u16(*port);
+                self.put_name_with(target, false)?;
+            }
+            RData::OPT(edns) => {
+                for option in &edns.options {
+                    let len: u16 = match option.data.len().try_into() {
+                        Ok(len) => len,
+                        Err(_) => return Err(WireError::BadRDataLength),
+                    };
+                    self.put_u16(option.code);
+                    self.put_u16(len);
+                    self.buf.extend_from_slice(&option.data);
+                }
+            }
+            RData::TXT(strings) => {
+                for string in strings {
+                    if string.len() > u8::MAX as usize {
+                        return Err(WireError::StringTooLong);
+                    }
+                    self.buf.push(string.len() as u8);
+                    self.buf.extend_from_slice(string);
+                }
+            }
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                self.put_name_with(mname, compress)?;
+                self.put_name_with(rname, compress)?;
+                for value in [serial, refresh, retry, expire, minimum] {
+                    self.put_u32(*value);
+                }
+            }
+            RData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                self.put_u16(*flags);
+                self.buf.push(*protocol);
+                self.buf.push(*algorithm);
+                self.buf.extend_from_slice(public_key);
+            }
+            // O signer e o nome seguinte de um NSEC nunca sao comprimidos (RFC 4034 3.1.7 e 4.1.1)
+            RData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => {
+                self.put_u16(*type_covered);
+                self.buf.push(*algorithm);
+                self.buf.push(*labels);
+                self.put_u32(*original_ttl);
+                self.put_u32(*expiration);
+                self.put_u32(*inception);
+                self.put_u16(*key_tag);
+                self.put_name_with(signer, false)?;
+                self.buf.extend_from_slice(signature);
+            }
+            RData::NSEC { next_domain, types } => {
+                self.put_name_with(next_domain, false)?;
+                self.put_type_bitmap(types);
+            }
+            RData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                self.put_u16(*key_tag);
+                self.buf.push(*algorithm);
+                self.buf.push(*digest_type);
+                self.buf.extend_from_slice(digest);
+            }
+        }
+        Ok(())
+    }
+
+    // Mapa de tipos de um NSEC (RFC 4034 4.1.2): uma janela por cada byte de cima dos tipos,
+    // com um bit por tipo e sem os bytes a zero do fim
+    fn put_type_bitmap(&mut self, types: &[u16]) {
+        let mut windows: Vec<(u8, [u8; 32])> = Vec::new();
+        for code in types {
+            let window = (code >> 8) as u8;
+            let low = (code & 0xFF) as usize;
+            let position = match windows.iter().position(|(number, _)| *number == window) {
+                Some(position) => position,
+                None => {
+                    windows.push((window, [0u8; 32]));
+                    windows.len() - 1
+                }
+            };
+            windows[position].1[low / 8] |= 0x80 >> (low % 8);
+        }
+        windows.sort_by_key(|(number, _)| *number);
+        for (number, bitmap) in windows {
+            let len = bitmap.iter().rposition(|byte| *byte != 0).unwrap() + 1;
+            self.buf.push(number);
+            self.buf.push(len as u8);
+            self.buf.extend_from_slice(&bitmap[..len]);
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // Bits de cima do RCODE, lidos do OPT
+    extended_rcode: Option<u8>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Decoder {
+            bytes,
+            pos: 0,
+            extended_rcode: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        let end = self.pos.checked_add(len).ok_or(WireError::Truncated)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(WireError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn get_u16(&mut self) -> Result<u16, WireError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn get_u32(&mut self) -> Result<u32, WireError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn get_u48(&mut self) -> Result<u64, WireError> {
+        let bytes = self.take(6)?;
+        Ok(bytes
+            .iter()
+            .fold(0u64, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    fn get_bytes(&mut self) -> Result<Vec<u8>, WireError> {
+        let len = self.get_u16()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn get_tsig(&mut self) -> Result<TsigRecord, WireError> {
+        let key_name = self.get_name()?;
+        if self.get_u16()? != TYPE_TSIG || self.get_u16()? != CLASS_ANY || self.get_u32()? != 0 {
+            return Err(WireError::BadTsig);
+        }
+        let rdlength = self.get_u16()? as usize;
+        let rdata_end = self.pos + rdlength;
+        let record = TsigRecord {
+            key_name,
+            algorithm: self.get_name()?,
+            time_signed: self.get_u48()?,
+            fudge: self.get_u16()?,
+            mac: self.get_bytes()?,
+            original_id: self.get_u16()?,
+            error: self.get_u16()?,
+            other_data: self.get_bytes()?,
+        };
+        if self.pos != rdata_end {
+            return Err(WireError::BadTsig);
+        }
+        Ok(record)
+    }
+
+    fn get_name(&mut self) -> Result<Domain, WireError> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut wire_len = 1;
+        let mut pos = self.pos;
+        // Posicao onde a leitura continua depois do primeiro ponteiro
+        let mut resume: Option<usize> = None;
+        let mut hops = 0;
+
+        loop {
+            let len = *self.bytes.get(pos).ok_or(WireError::Truncated)? as usize;
+            match len & 0xC0 {
+                0x00 => {
+                    pos += 1;
+                    if len == 0 {
+                        break;
+                    }
+                    let label = self.bytes.get(pos..pos + len).ok_or(WireError::Truncated)?;
+                    wire_len += len + 1;
+                    if wire_len > MAX_NAME_LEN {
+                        return Err(WireError::NameTooLong);
+                    }
+                    match String::from_utf8(label.to_vec()) {
+                        Ok(label) => labels.push(label),
+                        Err(_) => return Err(WireError::InvalidLabel),
+                    }
+                    pos += len;
+                }
+                0xC0 => {
+                    let low = *self.bytes.get(pos + 1).ok_or(WireError::Truncated)? as usize;
+                    if resume.is_none() {
+                        resume = Some(pos + 2);
+                    }
+                    hops += 1;
+                    if hops > MAX_POINTER_HOPS {
+                        return Err(WireError::PointerLoop);
+                    }
+                    pos = ((len & 0x3F) << 8) | low;
+                }
+                _ => return Err(WireError::BadLabelType(len as u8)),
+            }
+        }
+        self.pos = resume.unwrap_or(pos);
+
+        let mut domain = Domain::new_empty();
+        domain.set_parts(labels);
+        Ok(domain)
+    }
+
+    fn get_section(&mut self, count: u16) -> Result<Option<Vec<DNSEntry>>, WireError> {
+        if count == 0 {
+            return Ok(None);
+        }
+        if count > u8::MAX as u16 {
+            return Err(WireError::TooManyRecords(count));
+        }
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            entries.push(self.get_entry()?);
+        }
+        Ok(Some(entries))
+    }
+
+    fn get_entry(&mut self) -> Result<DNSEntry, WireError> {
+        let domain_name = self.get_name()?;
+        let type_code = self.get_u16()?;
+        let class = self.get_u16()?;
+        if class != CLASS_IN && type_code != TYPE_OPT {
+            return Err(WireError::UnsupportedClass(class));
+        }
+        let mut ttl = self.get_u32()?;
+        let rdlength = self.get_u16()? as usize;
+        let rdata_end = self.pos + rdlength;
+        if rdata_end > self.bytes.len() {
+            return Err(WireError::Truncated);
+        }
+
+        let data = match type_code {
+            TYPE_A => {
+                if rdlength != 4 {
+                    return Err(WireError::BadRDataLength);
+                }
+                let octets = self.take(4)?;
+                RData::A(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+            }
+            TYPE_AAAA => {
+                if rdlength != 16 {
+                    return Err(WireError::BadRDataLength);
+                }
+                let octets: [u8; 16] = self.take(16)?.try_into().unwrap();
+                RData::AAAA(Ipv6Addr::from(octets))
+            }
+            TYPE_NS => RData::NS(self.get_name()?),
+            TYPE_CNAME => RData::CNAME(self.get_name()?),
+            TYPE_PTR => RData::PTR(self.get_name()?),
+            TYPE_MX => RData::MX {
+                preference: self.get_u16()?,
+                exchange: self.get_name()?,
+            },
+            TYPE_SRV => RData::SRV {
+                priority: self.get_u16()?,
+                weight: self.get_u16()?,
+                port: self.get_u16()?,
+                target: self.get_name()?,
+            },
+            TYPE_OPT => {
+                if !domain_name.is_root() || self.extended_rcode.is_some() {
+                    return Err(WireError::BadOpt);
+                }
+                self.extended_rcode = Some((ttl >> 24) as u8);
+                let mut options = Vec::new();
+                while self.pos < rdata_end {
+                    let code = self.get_u16()?;
+                    let len = self.get_u16()? as usize;
+                    if self.pos + len > rdata_end {
+                        return Err(WireError::BadRDataLength);
+                    }
+                    options.push(EdnsOption {
+                        code,
+                        data: self.take(len)?.to_vec(),
+                    });
+                }
+                let edns = Edns {
+                    udp_payload_size: class,
+                    version: (ttl >> 16) as u8,
+                    dnssec_ok: ttl & DO_BIT != 0,
+                    options,
+                };
+                ttl = 0;
+                RData::OPT(edns)
+            }
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                while self.pos < rdata_end {
+                    let len = self.take(1)?[0] as usize;
+                    if self.pos + len > rdata_end {
+                        return Err(WireError::BadRDataLength);
+                    }
+                    strings.push(self.take(len)?.to_vec());
+                }
+                RData::TXT(strings)
+            }
+            TYPE_SOA => RData::SOA {
+                mname: self.get_name()?,
+                rname: self.get_name()?,
+                serial: self.get_u32()?,
+                refresh: self.get_u32()?,
+                retry: self.get_u32()?,
+                expire: self.get_u32()?,
+                minimum: self.get_u32()?,
+            },
+            TYPE_DNSKEY => {
+                if rdlength <= 4 {
+                    return Err(WireError::BadRDataLength);
+                }
+                let fixed = self.take(4)?;
+                RData::DNSKEY {
+                    flags: u16::from_be_bytes([fixed[0], fixed[1]]),
+                    protocol: fixed[2],
+                    algorithm: fixed[3],
+                    public_key: self.take(rdata_end - self.pos)?.to_vec(),
+                }
+            }
+            TYPE_RRSIG => {
+                if rdlength < 18 {
+                    return Err(WireError::BadRDataLength);
+                }
+                let type_covered = self.get_u16()?;
+                let fixed = self.take(2)?;
+                let (algorithm, labels) = (fixed[0], fixed[1]);
+                let original_ttl = self.get_u32()?;
+                let expiration = self.get_u32()?;
+                let inception = self.get_u32()?;
+                let key_tag = self.get_u16()?;
+                let signer = self.get_name()?;
+                if self.pos >= rdata_end {
+                    return Err(WireError::BadRDataLength);
+                }
+                RData::RRSIG {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature: self.take(rdata_end - self.pos)?.to_vec(),
+                }
+            }
+            TYPE_DS => {
+                if rdlength <= 4 {
+                    return Err(WireError::BadRDataLength);
+                }
+                let fixed = self.take(4)?;
+                RData::DS {
+                    key_tag: u16::from_be_bytes([fixed[0], fixed[1]]),
+                    algorithm: fixed[2],
+                    digest_type: fixed[3],
+                    digest: self.take(rdata_end - self.pos)?.to_vec(),
+                }
+            }
+            TYPE_NSEC => {
+                let next_domain = self.get_name()?;
+                if self.pos > rdata_end {
+                    return Err(WireError::BadRDataLength);
+                }
+                let types = self.get_type_bitmap(rdata_end)?;
+                RData::NSEC { next_domain, types }
+            }
+            _ => return Err(WireError::UnsupportedType(type_code)),
+        };
+        if self.pos != rdata_end {
+            return Err(WireError::BadRDataLength);
+        }
+
+        Ok(DNSEntry::new(domain_name, ttl, data))
+    }
+
+    fn get_type_bitmap(&mut self, rdata_end: usize) -> Result<Vec<u16>, WireError> {
+        let mut types = Vec::new();
+        let mut last_window = None;
+        while self.pos < rdata_end {
+            let header = self.take(2)?;
+            let (window, len) = (header[0], header[1] as usize);
+            // As janelas vem por ordem crescente e tem entre 1 e 32 bytes
+            if last_window.is_some_and(|last| last >= window)
+                || len == 0
+                || len > 32
+                || self.pos + len > rdata_end
+            {
+                return Err(WireError::BadRDataLength);
+            }
+            last_window = Some(window);
+            for (i, byte) in self.take(len)?.iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (0x80 >> bit) != 0 {
+                        types.push(((window as u16) << 8) | (i * 8 + bit) as u16);
+                    }
+                }
+            }
+        }
+        Ok(types)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static QUERY_WWW_A: &[u8] = include_bytes!("../../etc/fixtures/query-www-example-com-a.bin");
+    static RESPONSE_WWW_A: &[u8] =
+        include_bytes!("../../etc/fixtures/response-www-example-com-a.bin");
+    static RESPONSE_MX: &[u8] = include_bytes!("../../etc/fixtures/response-example-com-mx.bin");
+    static RESPONSE_NXDOMAIN: &[u8] =
+        include_bytes!("../../etc/fixtures/response-nxdomain-example-com.bin");
+    static RESPONSE_REFERRAL: &[u8] =
+        include_bytes!("../../etc/fixtures/response-referral-example-official.bin");
+
+    fn entry(name: &str, type_of_value: &str, value: &str, priority: Option<u16>) -> DNSEntry {
+        let data =
+            RData::from_zone_str(type_of_value, value, priority, &Domain::new_empty()).unwrap();
+        DNSEntry::new(Domain::new(name.to_string()), 86400, data)
+    }
+
+    fn message(
+        message_id: u16,
+        flags: Flags,
+        response_code: Option<ResponseCode>,
+        query: (&str, QueryType),
+        response_values: Option<Vec<DNSEntry>>,
+        authorities_values: Option<Vec<DNSEntry>>,
+        extra_values: Option<Vec<DNSEntry>>,
+    ) -> DNSMessage {
+        DNSMessage {
+            header: DNSMessageHeaders {
+                message_id,
+                flags,
+                response_code,
+                number_of_values: section_count(&response_values),
+                number_of_authorities: section_count(&authorities_values),
+                number_of_extra_values: section_count(&extra_values),
+            },
+            data: DNSMessageData {
+                query_info: DNSQueryInfo {
+                    name: Domain::new(query.0.to_string()),
+                    type_of_value: query.1,
+                },
+                response_values,
+                authorities_values,
+                extra_values,
+            },
+        }
+    }
+
+    fn assert_round_trip(fixture: &[u8], expected: DNSMessage) {
+        let decoded = decode(fixture).unwrap();
+        assert_eq!(decoded, expected);
+        assert_eq!(encode(&decoded).unwrap(), fixture);
+    }
+
+    #[test]
+    fn test_query_round_trip() {
+        let expected = message(
+            0x1e5a,
+            Flags::QUERY.with(Flags::RECURSIVE).unwrap(),
+            None,
+            ("www.example.com.", QueryType::A),
+            None,
+            None,
+            None,
+        );
+        assert_round_trip(QUERY_WWW_A, expected);
+    }
+
+    #[test]
+    fn test_answer_round_trip() {
+        let expected = message(
+            0x1e5a,
+            Flags::RECURSIVE.with(Flags::AUTHORITATIVE).unwrap(),
+            Some(ResponseCode::NoError),
+            ("www.example.com.", QueryType::A),
+            Some(vec![entry("www.example.com.", "A", "10.3.3.1", None)]),
+            Some(vec![entry("example.com.", "NS", "ns1.example.com.", None)]),
+            Some(vec![entry("ns1.example.com.", "A", "10.2.2.2", None)]),
+        );
+        assert_round_trip(RESPONSE_WWW_A, expected);
+    }
+
+    #[test]
+    fn test_mx_round_trip() {
+        let expected = message(
+            0xbeef,
+            Flags::AUTHORITATIVE,
+            Some(ResponseCode::NoError),
+            ("example.com.", QueryType::MX),
+            Some(vec![
+                entry("example.com.", "MX", "mx1.example.com.", Some(10)),
+                entry("example.com.", "MX", "mx2.example.com.", Some(20)),
+            ]),
+            None,
+            Some(vec![
+                entry("mx1.example.com.", "A", "193.136.130.200", None),
+                entry("mx2.example.com.", "A", "193.136.130.201", None),
+            ]),
+        );
+        assert_round_trip(RESPONSE_MX, expected);
+    }
+
+    #[test]
+    fn test_nxdomain_round_trip() {
+        let expected = message(
+            0x0042,
+            Flags::AUTHORITATIVE,
+            Some(ResponseCode::NXDomain),
+            ("nothere.example.com.", QueryType::A),
+            None,
+            None,
+            None,
+        );
+        assert_round_trip(RESPONSE_NXDOMAIN, expected);
+    }
+
+    #[test]
+    fn test_referral_round_trip() {
+        let expected = message(
+            0x7001,
+            Flags::empty(),
+            Some(ResponseCode::NonAuthoritative),
+            ("www.example.official.", QueryType::A),
+            None,
+            Some(vec![entry(
+                "example.official.",
+                "NS",
+                "golfinho.example.official.",
+                None,
+            )]),
+            Some(vec![entry(
+                "golfinho.example.official.",
+                "A",
+                "10.3.3.2",
+                None,
+            )]),
+        );
+        assert_round_trip(RESPONSE_REFERRAL, expected);
+    }
+
+    #[test]
+    fn test_query_header_id() {
+        assert_eq!(query_header_id(QUERY_WWW_A), Some(0x1e5a));
+        assert_eq!(query_header_id(RESPONSE_WWW_A), None);
+        assert_eq!(query_header_id(&QUERY_WWW_A[..HEADER_LEN - 1]), None);
+
+        let error = encode_format_error(0x1e5a);
+        assert_eq!(error.len(), HEADER_LEN);
+        assert_eq!(decode(&error), Err(WireError::QuestionCount(0)));
+    }
+
+    #[test]
+    fn test_query_with_response_flags() {
+        for bit in [AA_BIT, TC_BIT] {
+            let mut packet = QUERY_WWW_A.to_vec();
+            packet[2] |= (bit >> 8) as u8;
+            assert_eq!(decode(&packet), Err(WireError::InvalidFlags));
+        }
+
+        // AD numa pergunta e valido, pede ao resolver que valide a resposta (RFC 6840)
+        let mut packet = QUERY_WWW_A.to_vec();
+        packet[3] |= AD_BIT as u8;
+        let query = decode(&packet).unwrap();
+        assert!(query.header.flags.contains(Flags::AUTHENTICATED));
+        assert!(query.header.flags.contains(Flags::QUERY));
+    }
+
+    #[test]
+    fn test_truncated_packets() {
+        for len in 0..RESPONSE_WWW_A.len() {
+            assert_eq!(decode(&RESPONSE_WWW_A[..len]), Err(WireError::Truncated));
+        }
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let mut packet = QUERY_WWW_A.to_vec();
+        packet.push(0);
+        assert_eq!(decode(&packet), Err(WireError::TrailingBytes));
+    }
+
+    #[test]
+    fn test_pointer_loop() {
+        let mut packet = QUERY_WWW_A[..HEADER_LEN].to_vec();
+        // O nome da pergunta aponta para si proprio
+        packet.extend_from_slice(&[0xC0, HEADER_LEN as u8, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(decode(&packet), Err(WireError::PointerLoop));
+    }
+
+    #[test]
+    fn test_bad_label_type() {
+        let mut packet = QUERY_WWW_A[..HEADER_LEN].to_vec();
+        packet.extend_from_slice(&[0x40, 0x00, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(decode(&packet), Err(WireError::BadLabelType(0x40)));
+    }
+
+    #[test]
+    fn test_invalid_label() {
+        let mut packet = QUERY_WWW_A[..HEADER_LEN].to_vec();
+        packet.extend_from_slice(&[0x02, 0xC3, 0xFF, 0x00, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(decode(&packet), Err(WireError::InvalidLabel));
+    }
+
+    #[test]
+    fn test_bad_rdata_length() {
+        let mut packet = RESPONSE_NXDOMAIN[..HEADER_LEN].to_vec();
+        packet[7] = 1;
+        packet.extend_from_slice(&RESPONSE_NXDOMAIN[HEADER_LEN..]);
+        // A record com rdlength 3
+        packet.extend_from_slice(&[0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01]);
+        packet.extend_from_slice(&[0x00, 0x00, 0x00, 0x3C, 0x00, 0x03, 10, 0, 0]);
+        assert_eq!(decode(&packet), Err(WireError::BadRDataLength));
+    }
+
+    #[test]
+    fn test_soa_round_trip() {
+        let mut dns_message = decode(RESPONSE_NXDOMAIN).unwrap();
+        let soa = DNSEntry::new(
+            Domain::new("example.com.".to_string()),
+            86400,
+            RData::SOA {
+                mname: Domain::new("ns1.example.com.".to_string()),
+                rname: Domain::new("admin.example.com.".to_string()),
+                serial: 117102022,
+                refresh: 14400,
+                retry: 3600,
+                expire: 604800,
+                minimum: 86400,
+            },
+        );
+        dns_message.data.authorities_values = Some(vec![soa]);
+        dns_message.header.number_of_authorities = Some(1);
+        let bytes = encode(&dns_message).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), dns_message);
+    }
+
+    #[test]
+    fn test_aaaa_round_trip() {
+        let mut dns_message = decode(RESPONSE_WWW_A).unwrap();
+        dns_message.data.query_info.type_of_value = QueryType::AAAA;
+        dns_message.data.response_values = Some(vec![entry(
+            "www.example.com.",
+            "AAAA",
+            "2001:db8::80",
+            None,
+        )]);
+        let bytes = encode(&dns_message).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), dns_message);
+    }
+
+    #[test]
+    fn test_txt_round_trip() {
+        let mut dns_message = decode(RESPONSE_WWW_A).unwrap();
+        dns_message.data.query_info.type_of_value = QueryType::TXT;
+        dns_message.data.response_values = Some(vec![entry(
+            "www.example.com.",
+            "TXT",
+            r#""v=spf1 mx -all" "say \"hi\"" "" "\255\000""#,
+            None,
+        )]);
+        let bytes = encode(&dns_message).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), dns_message);
+
+        dns_message.data.response_values = Some(vec![DNSEntry::new(
+            Domain::new("www.example.com.".to_string()),
+            60,
+            RData::TXT(vec![vec![b'a'; 256]]),
+        )]);
+        assert_eq!(encode(&dns_message), Err(WireError::StringTooLong));
+    }
+
+    #[test]
+    fn test_srv_round_trip() {
+        let mut dns_message = decode(RESPONSE_WWW_A).unwrap();
+        dns_message.data.query_info.name = Domain::new("_sip._tcp.example.com.".to_string());
+        dns_message.data.query_info.type_of_value = QueryType::SRV;
+        dns_message.data.response_values = Some(vec![entry(
+            "_sip._tcp.example.com.",
+            "SRV",
+            "10 60 5060 www.example.com.",
+            None,
+        )]);
+        dns_message.data.authorities_values = None;
+        dns_message.data.extra_values = None;
+        dns_message.header.number_of_authorities = None;
+        dns_message.header.number_of_extra_values = None;
+        let bytes = encode(&dns_message).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), dns_message);
+        // O target vai por extenso, sem ponteiros de compressao
+        let target = b"\x03www\x07example\x03com\x00";
+        assert!(bytes.ends_with(target));
+    }
+
+    #[test]
+    fn test_dnssec_round_trip() {
+        let mut dns_message = decode(RESPONSE_WWW_A).unwrap();
+        dns_message.data.response_values = Some(vec![
+            entry("example.com.", "DNSKEY", "257 3 13 AQIDBA==", None),
+            entry(
+                "www.example.com.",
+                "RRSIG",
+                "A 13 3 3600 1700000000 1690000000 12345 example.com. AAECAwQ=",
+                None,
+            ),
+            entry(
+                "www.example.com.",
+                "NSEC",
+                "example.com. A RRSIG NSEC TYPE1234",
+                None,
+            ),
+            entry("sub.example.com.", "DS", "12345 13 2 0A1B2C3D", None),
+        ]);
+        dns_message.header.number_of_values = Some(4);
+        let bytes = encode(&dns_message).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), dns_message);
+        // Mapa do NSEC: janela 0 com A, RRSIG e NSEC e janela 4 com o tipo 1234
+        let bitmap = [
+            0x00, 0x06, 0x40, 0x00, 0x00, 0x00, 0x00, 0x03, 0x04, 0x1B, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20,
+        ];
+        assert!(bytes
+            .windows(bitmap.len())
+            .any(|window| window == bitmap));
+    }
+
+    #[test]
+    fn test_canonical_rdata() {
+        let data = RData::MX {
+            preference: 10,
+            exchange: Domain::new("Mail.Example.COM.".to_string()),
+        };
+        assert_eq!(
+            canonical_rdata(&data).unwrap(),
+            b"\x00\x0a\x04mail\x07example\x03com\x00"
+        );
+    }
+
+    #[test]
+    fn test_truncated_round_trip() {
+        let truncated = decode(RESPONSE_WWW_A).unwrap().truncated();
+        let bytes = encode(&truncated).unwrap();
+        assert_eq!(u16::from_be_bytes([bytes[2], bytes[3]]) & TC_BIT, TC_BIT);
+        // Apenas o cabecalho e a pergunta
+        assert_eq!(bytes.len(), QUERY_WWW_A.len());
+        assert_eq!(decode(&bytes).unwrap(), truncated);
+    }
+
+    #[test]
+    fn test_edns_round_trip() {
+        let mut query = decode(QUERY_WWW_A).unwrap();
+        let mut edns = Edns::new(1232);
+        edns.dnssec_ok = true;
+        edns.options.push(EdnsOption {
+            code: 10,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        });
+        query.set_edns(edns);
+        let bytes = encode(&query).unwrap();
+        // OPT: raiz, tipo 41, classe 1232, TTL com o DO, rdlength 12
+        assert!(bytes.ends_with(&[
+            0, 0, 41, 0x04, 0xD0, 0, 0, 0x80, 0, 0, 12, 0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8
+        ]));
+        assert_eq!(decode(&bytes).unwrap(), query);
+    }
+
+    #[test]
+    fn test_extended_response_code() {
+        let mut response = decode(RESPONSE_NXDOMAIN).unwrap();
+        response.header.response_code = Some(ResponseCode::BadVersion);
+        assert_eq!(encode(&response), Err(WireError::BadOpt));
+
+        response.set_edns(Edns::new(1232));
+        let bytes = encode(&response).unwrap();
+        // RCODE 16: 0 no cabecalho e 1 no OPT
+        assert_eq!(bytes[3] & 0x0F, 0);
+        assert_eq!(decode(&bytes).unwrap(), response);
+    }
+
+    #[test]
+    fn test_opt_outside_extra_values() {
+        let mut response = decode(RESPONSE_NXDOMAIN).unwrap();
+        response.data.authorities_values = Some(vec![DNSEntry::new(
+            Domain::new_empty(),
+            0,
+            RData::OPT(Edns::new(512)),
+        )]);
+        response.header.number_of_authorities = Some(1);
+        let bytes = encode(&response).unwrap();
+        assert_eq!(decode(&bytes), Err(WireError::BadOpt));
+    }
+
+    #[test]
+    fn test_unsupported_type() {
+        let mut packet = QUERY_WWW_A.to_vec();
+        let len = packet.len();
+        // QTYPE 255 (ANY)
+        packet[len - 3] = 0xFF;
+        assert_eq!(decode(&packet), Err(WireError::UnsupportedType(255)));
+    }
+}
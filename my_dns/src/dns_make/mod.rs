@@ -1,2 +1,8 @@
+pub mod dns_dnssec;
+pub mod dns_https;
+pub mod dns_idna;
 pub mod dns_recv;
 pub mod dns_send;
+pub mod dns_tls;
+pub mod dns_tsig;
+pub mod dns_wire;
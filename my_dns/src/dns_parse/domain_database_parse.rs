@@ -1,5 +1,6 @@
 use crate::dns_structs::dns_domain_name::Domain;
-use crate::dns_structs::dns_message::{DNSEntry, QueryType};
+use crate::dns_structs::dns_message::DNSEntry;
+use crate::dns_structs::dns_resource_data::RData;
 use crate::dns_structs::domain_database_struct::DomainDatabase;
 use core::panic;
 use std::net::SocketAddr;
@@ -42,18 +43,27 @@ pub fn get(file_path: String) -> Result<DomainDatabase, &'static str> {
 
 pub fn parse_from_str(read: String) -> Result<DomainDatabase, &'static str> {
     let regex_variables =
-        Regex::new(r"(?m)^([@A-Za-z.0-9-]+) +DEFAULT +([A-Za-z.0-9\\.-]+)").unwrap();
+        Regex::new(r"(?m)^([@\w.-]+) +DEFAULT +([\w\\.-]+)").unwrap();
 
     let regex_soa = Regex::new(
-        r"(?m)^([@A-Za-z.0-9-]+) +(SOA[A-Z]+) +([A-Za-z.0-9\\.-]+) +([A-Z0-9]+) *([A-Z0-9]+)?",
+        r"(?m)^([@\w.-]+) +(SOA[A-Z]+) +([\w\\.-]+) +([A-Z0-9]+)",
     )
     .unwrap();
 
+    // O valor de um TXT pode ser uma sequencia de strings entre aspas, com espacos e escapes,
+    // e o de um SRV tem o formato "priority weight port target".
+    // Os nomes podem ter labels comecadas por "_" (ex: _sip._tcp), labels Unicode (ex: café),
+    // guardadas na forma ASCII, e o "*" de um wildcard
     let regex_entry = Regex::new(
-        r"(?m)^([@A-Za-z.0-9-]+) +(NS|A|CNAME|MX|PTR) +([A-Za-z.0-9\\.-]+) +([A-Z0-9]+) *([A-Z0-9]+)?",
+        r#"(?m)^([@*\w.-]+) +(NS|AAAA|A|CNAME|MX|PTR|TXT|SRV) +((?:"(?:[^"\\\n]|\\.)*" *)+|[0-9]+ +[0-9]+ +[0-9]+ +[\w.-]+|[\w\\.:-]+) +([A-Z0-9]+) *([A-Z0-9]+)?"#,
     )
     .unwrap();
 
+    // Records DNSSEC, com o valor ate ao TTL no fim da linha, tal como sao escritos na
+    // transferencia de zona
+    let regex_dnssec =
+        Regex::new(r"(?m)^([@*\w.-]+) +(DNSKEY|RRSIG|NSEC|DS) +(.+?) +([0-9]+) *$").unwrap();
+
     // Deste modo, os comentario ficam todos ignorados visto que as expressoes capturam apenas as expressoes no inicio da linha
 
     // HashMaps onde vamos guardar os valores para dar return
@@ -66,45 +76,59 @@ pub fn parse_from_str(read: String) -> Result<DomainDatabase, &'static str> {
     }
     debug!("EV @ variable-regex-capture");
 
-    // Mapa que vai conter todas as SOAs entries tendo o tipo de SOA como key (aka SOAADMIN,SOAEXPIRE, etc)
+    // Dominio da base de dados, usado para completar nomes relativos nos valores das entries
+    let origin = match variables.get("@") {
+        Some(value) => parse_domain(value)?,
+        None => Domain::new_empty(),
+    };
+
     let mut domain_database = DomainDatabase::new();
-    // Capturar todas as SOAs entries
+    let mut soa_minimum: Option<u32> = None;
+    // Capturar todas as SOAs entries (SOASP, SOAADMIN, SOASERIAL, etc)
     for cap in regex_soa.captures_iter(&read) {
-        // Podemos fazer error check nesta seccao do codigo
         let mut name: String = cap[1].to_string();
-        let type_of_value: String = cap[2].to_string();
         let value: String = cap[3].to_string();
         let mut temp_ttl: String = cap[4].to_string();
-        let priority: Option<u16> = match cap.get(5) {
-            Some(p) => Some(p.as_str().parse::<u16>().unwrap()),
-            _ => None,
-        };
         for (variable, value) in variables.iter() {
             name = name.replace(variable, value);
-            temp_ttl = temp_ttl.replace(variable, value).parse().unwrap();
+            temp_ttl = temp_ttl.replace(variable, value);
         }
 
-        let ttl: u32 = temp_ttl.parse().unwrap();
-        let entry = DNSEntry {
-            domain_name: Domain::new(name),
-            type_of_value,
-            value,
-            ttl,
-            priority,
+        let ttl: u32 = match temp_ttl.parse() {
+            Ok(ttl) => ttl,
+            Err(_) => {
+                debug!("FL @ invalid-soa-ttl {} {}", name, temp_ttl);
+                return Err("Invalid TTL found in SOA entry");
+            }
+        };
+        let soa = &mut domain_database.soa_entries;
+        let parse_number = |value: &str| match value.parse::<u32>() {
+            Ok(number) => Ok(number),
+            Err(_) => {
+                debug!("FL @ invalid-soa-value {} {}", name, value);
+                Err("Invalid number found in SOA entry")
+            }
         };
         match &cap[2] {
-            "SOASP" => domain_database.soa_entries.primary_ns = entry,
-            "SOAADMIN" => domain_database.soa_entries.contact_email = entry,
-            "SOASERIAL" => domain_database.soa_entries.serial = entry,
-            "SOAREFRESH" => domain_database.soa_entries.refresh = entry,
-            "SOARETRY" => domain_database.soa_entries.retry = entry,
-            "SOAEXPIRE" => domain_database.soa_entries.expire = entry,
+            "SOASP" => {
+                soa.domain_name = parse_domain(&name)?;
+                soa.ttl = ttl;
+                soa.primary_ns = parse_relative_domain(&value, &origin)?;
+            }
+            "SOAADMIN" => soa.contact_email = parse_relative_domain(&value, &origin)?,
+            "SOASERIAL" => soa.serial = parse_number(&value)?,
+            "SOAREFRESH" => soa.refresh = parse_number(&value)?,
+            "SOARETRY" => soa.retry = parse_number(&value)?,
+            "SOAEXPIRE" => soa.expire = parse_number(&value)?,
+            "SOAMINIMUM" => soa_minimum = Some(parse_number(&value)?),
             _ => {
                 debug!("FL @ invalid-soa-entry");
                 return Err("SOA type does not exist");
             }
         }
     }
+    // Sem SOAMINIMUM, as respostas negativas usam o TTL do proprio SOA
+    domain_database.soa_entries.minimum = soa_minimum.unwrap_or(domain_database.soa_entries.ttl);
 
     // Capturar todas as entries
     for cap in regex_entry.captures_iter(&read) {
@@ -122,7 +146,7 @@ pub fn parse_from_str(read: String) -> Result<DomainDatabase, &'static str> {
         };
         for (variable, value) in variables.iter() {
             name = name.replace(variable, value);
-            temp_ttl = temp_ttl.replace(variable, value).parse().unwrap();
+            temp_ttl = temp_ttl.replace(variable, value);
         }
 
         if !name.ends_with(".") {
@@ -138,24 +162,56 @@ pub fn parse_from_str(read: String) -> Result<DomainDatabase, &'static str> {
             name = name.add(".").add(main_domain);
         }
 
-        let ttl: u32 = temp_ttl.parse().unwrap();
+        let ttl: u32 = match temp_ttl.parse() {
+            Ok(ttl) => ttl,
+            Err(_) => {
+                debug!("FL @ invalid-entry-ttl {} {}", name, temp_ttl);
+                return Err("Invalid TTL found in entry");
+            }
+        };
 
-        let temp_entry: DNSEntry = DNSEntry {
-            domain_name: Domain::new(name.to_owned()),
-            type_of_value: type_of_value.to_owned(),
-            value,
+        // O valor e validado de acordo com o tipo da entry
+        let data = match RData::from_zone_str(&type_of_value, &value, priority, &origin) {
+            Ok(data) => data,
+            Err(err) => {
+                debug!("FL @ invalid-entry-value {} {}", name, err);
+                return Err("Invalid value found in entry");
+            }
+        };
+        let priority = match data {
+            RData::MX { .. } | RData::SRV { .. } => None,
+            _ => priority,
+        };
+
+        domain_database.add_entry(DNSEntry {
+            domain_name: parse_domain(&name)?,
             ttl,
             priority,
-        };
+            data,
+        });
+    }
 
-        match type_of_value.as_str() {
-            "NS" => domain_database.add_ns_record(Domain::new(name), temp_entry),
-            "A" => domain_database.add_a_record(temp_entry),
-            "CNAME" => domain_database.add_cname_record(temp_entry),
-            "MX" => domain_database.add_mx_record(temp_entry),
-            "PTR" => domain_database.add_ptr_record(temp_entry),
-            _ => continue,
+    for cap in regex_dnssec.captures_iter(&read) {
+        let mut name: String = cap[1].to_string();
+        for (variable, value) in variables.iter() {
+            name = name.replace(variable, value);
         }
+        let domain_name = parse_relative_domain(&name, &origin)?;
+        let ttl: u32 = match cap[4].parse() {
+            Ok(ttl) => ttl,
+            Err(_) => {
+                debug!("FL @ invalid-entry-ttl {} {}", name, &cap[4]);
+                return Err("Invalid TTL found in entry");
+            }
+        };
+        let data = match RData::from_zone_str(&cap[2], &cap[3], None, &origin) {
+            Ok(data) => data,
+            Err(err) => {
+                debug!("FL @ invalid-entry-value {} {}", name, err);
+                return Err("Invalid value found in entry");
+            }
+        };
+        domain_database.add_entry(DNSEntry::new(domain_name, ttl, data));
     }
 
     domain_database.authority = true; 
@@ -163,6 +219,14 @@ pub fn parse_from_str(read: String) -> Result<DomainDatabase, &'static str> {
 }
 
 
+fn parse_domain(name: &str) -> Result<Domain, &'static str> {
+    Domain::parse(name).inspect_err(|err| debug!("FL @ invalid-domain-name {} {}", name, err))
+}
+
+fn parse_relative_domain(name: &str, origin: &Domain) -> Result<Domain, &'static str> {
+    Domain::parse_relative(name, origin).inspect_err(|err| debug!("FL @ invalid-domain-name {} {}", name, err))
+}
+
 pub fn parse_root_servers(root_servers_path: String) -> Result<Vec<SocketAddr>, std::io::Error> {
     let mut file = match File::open(root_servers_path.to_owned()) {
         Ok(file) => file,
@@ -185,9 +249,67 @@ pub fn parse_root_servers(root_servers_path: String) -> Result<Vec<SocketAddr>,
     for root in read.lines() {
         let root_ip: SocketAddr = match root.parse() {
             Ok(ip) => ip,
-            Err(_err) => panic!("Malformed root server IP at {}", root_servers_path),
+            Err(_err) => {
+                let message = format!("Malformed root server IP {}", root);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+            }
         };
         root_vec.push(root_ip);
     }
     Ok(root_vec)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_structs::dns_message::QueryType;
+
+    static ZONE: &str = "@ DEFAULT example.com.
+@ SOASP ns1.example.com. 3600
+@ SOAADMIN dns\\.admin.example.com. 3600
+@ SOASERIAL 1 3600
+@ NS ns1.example.com. 3600
+ns1 A 10.0.0.1 3600
+";
+
+    #[test]
+    fn test_parse_escaped_admin() {
+        let db = parse_from_str(ZONE.to_string()).unwrap();
+        let soa = db.get_soa_records();
+        assert_eq!(soa.contact_email.getparts(), &vec!["dns.admin", "example", "com"]);
+        assert_eq!(soa.contact_email.to_string(), "dns\\.admin.example.com.");
+    }
+
+    #[test]
+    fn test_parse_unicode_names() {
+        let zone = format!("{}Café A 10.0.0.2 3600\nwww CNAME café 3600\n", ZONE);
+        let db = parse_from_str(zone).unwrap();
+        let cafe = Domain::parse("xn--caf-dma.example.com.").unwrap();
+        let answers = db.get_domain_query(QueryType::A, &cafe).unwrap();
+        assert_eq!(answers[0].domain_name.getparts(), cafe.getparts());
+        let www = Domain::parse("www.example.com.").unwrap();
+        let answers = db.get_domain_query(QueryType::CNAME, &www).unwrap();
+        assert_eq!(answers[0].data, RData::CNAME(cafe));
+    }
+
+    #[test]
+    fn test_parse_invalid_names() {
+        let zones = [
+            (format!("{}www..example.com. A 10.0.0.2 3600\n", ZONE), "Empty label in domain name"),
+            (
+                format!("{}{} A 10.0.0.2 3600\n", ZONE, "a".repeat(64)),
+                "Domain label longer than 63 bytes",
+            ),
+            (
+                ZONE.replace("@ DEFAULT example.com.", "@ DEFAULT example..com."),
+                "Empty label in domain name",
+            ),
+        ];
+        for (zone, err) in zones {
+            assert_eq!(parse_from_str(zone).err(), Some(err));
+        }
+
+        let zone = format!("{}www CNAME a..b 3600\n", ZONE);
+        assert_eq!(parse_from_str(zone).err(), Some("Invalid value found in entry"));
+    }
+}
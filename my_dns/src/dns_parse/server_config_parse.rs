@@ -1,10 +1,11 @@
 use log::{debug, error};
-use regex::Regex;
+use regex::{Captures, Regex};
 use std::{fs::File, io::Read, ops::Add};
 
 use crate::dns_structs::{dns_domain_name::Domain, server_config::ServerConfig};
 
-pub fn get(file_path: String) -> Result<ServerConfig, &'static str> {
+// Os erros indicam a linha do ficheiro em que estao
+pub fn get(file_path: String) -> Result<ServerConfig, String> {
     let mut file = match File::open(file_path.to_owned()) {
         Ok(file) => {
             debug!("EV @ config-file-opened {}", file_path);
@@ -12,7 +13,7 @@ pub fn get(file_path: String) -> Result<ServerConfig, &'static str> {
         }
         Err(_err) => {
             error!("SP @ incorrect-config-file-path {}", file_path);
-            return Err("Failed to open file");
+            return Err("Failed to open file".to_string());
         }
     };
 
@@ -23,39 +24,64 @@ pub fn get(file_path: String) -> Result<ServerConfig, &'static str> {
         Ok(_) => debug!("EV @ read-config-file"),
         Err(_err) => {
             error!("SP @ unable-to-read-config-file");
-            return Err("Couldn't read to String");
+            return Err("Couldn't read to String".to_string());
         }
     };
 
     debug!("EV @ capturing-regex-variables");
-    let regex_variables = Regex::new(r"(?m)^([a-z.0-9-]+) +(DB|SS|DD|LG|ST|SP) +(.*)").unwrap();
+    let regex_variables = Regex::new(
+        r"(?m)^([A-Za-z.0-9_\\-]+) +(DB|SS|DD|LG|ST|SP|TC|TK|TP|HP|HS|KY|KT|KQ|KS|ZS|TA|CR) +(.*)",
+    )
+    .unwrap();
 
     let mut server_config = ServerConfig::new();
 
     for cap in regex_variables.captures_iter(&read) {
-        let name: String;
-        if !cap[1].ends_with(".") && &cap[1] != "all" && &cap[1] != "root" {
-            name = cap[1].to_string().add(".")
-        } else {
-            name = cap[1].to_string();
-        }
-        match &cap[2] {
-            "DB" => server_config.add_domain_db(Domain::new(name), cap[3].to_string()),
-            "SS" => server_config.add_domain_ss(Domain::new(name), cap[3].to_string()),
-            "SP" => server_config.set_domain_sp(Domain::new(name), cap[3].to_string()),
-            "DD" => server_config.add_server_dd(Domain::new(name), cap[3].to_string()),
-            "LG" => match &cap[1] {
-                "all" => server_config.set_all_log(cap[3].to_string()),
-                _ => server_config.set_domain_log(Domain::new(name), cap[3].to_string()),
-            },
-            "ST" => server_config.set_st_db(cap[3].to_string()),
-            _ => (),
+        let line = read[..cap.get(0).unwrap().start()].matches('\n').count() + 1;
+        if let Err(err) = parse_line(&mut server_config, &cap) {
+            error!("SP @ invalid-config-line {} {}", line, err);
+            return Err(format!("line {}: {}", line, err));
         }
     }
     debug!("EV @ config-file-parsed");
     Ok(server_config)
 }
 
+fn parse_line(server_config: &mut ServerConfig, cap: &Captures) -> Result<(), &'static str> {
+    let name: String;
+    if !cap[1].ends_with(".") && &cap[1] != "all" && &cap[1] != "root" {
+        name = cap[1].to_string().add(".")
+    } else {
+        name = cap[1].to_string();
+    }
+    let domain = Domain::parse(&name)?;
+    match &cap[2] {
+        "DB" => server_config.add_domain_db(domain, cap[3].to_string()),
+        "SS" => server_config.add_domain_ss(domain, cap[3].to_string()),
+        "SP" => server_config.set_domain_sp(domain, cap[3].to_string()),
+        "DD" => server_config.add_server_dd(domain, cap[3].to_string()),
+        "LG" => match &cap[1] {
+            "all" => server_config.set_all_log(cap[3].to_string()),
+            _ => server_config.set_domain_log(domain, cap[3].to_string()),
+        },
+        "ST" => server_config.set_st_db(cap[3].to_string()),
+        "TC" => server_config.set_tls_cert(cap[3].to_string()),
+        "TK" => server_config.set_tls_key(cap[3].to_string()),
+        "TP" => server_config.set_tls_port(cap[3].to_string())?,
+        "HP" => server_config.set_http_port(cap[3].to_string())?,
+        "HS" => server_config.set_https_port(cap[3].to_string())?,
+        "KY" => server_config.add_tsig_key(domain, cap[3].trim().to_string())?,
+        "KT" => server_config.set_domain_transfer_key(domain, cap[3].to_string())?,
+        "KQ" => server_config.set_domain_query_key(domain, cap[3].to_string())?,
+        "KS" => server_config.set_domain_ksk(domain, cap[3].to_string()),
+        "ZS" => server_config.set_domain_zsk(domain, cap[3].to_string()),
+        "TA" => server_config.set_trust_anchor(cap[3].to_string()),
+        "CR" => server_config.set_randomize_case(cap[3].to_string())?,
+        _ => (),
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::dns_structs::dns_domain_name::Domain;
@@ -92,7 +118,98 @@ mod tests {
         );
         server_config.set_all_log("logs/all.log".to_owned());
         server_config.set_st_db("etc/rootservers.db".to_owned());
+        server_config.set_trust_anchor("etc/root-anchor.db".to_owned());
+        server_config.set_randomize_case("on".to_owned()).unwrap();
+        server_config.set_tls_cert("etc/dot-cert.pem".to_owned());
+        server_config.set_tls_key("etc/dot-key.pem".to_owned());
+        server_config.set_tls_port("8853".to_owned()).unwrap();
+        server_config.set_http_port("8080".to_owned()).unwrap();
+        server_config.set_https_port("8443".to_owned()).unwrap();
+        server_config
+            .add_tsig_key(
+                Domain::new("transfer-key".to_owned()),
+                "c2VncmVkbyBkYXMgdHJhbnNmZXJlbmNpYXM=".to_owned(),
+            )
+            .unwrap();
+        server_config
+            .set_domain_transfer_key(
+                Domain::new("example.com.".to_owned()),
+                "transfer-key".to_owned(),
+            )
+            .unwrap();
+        server_config
+            .set_domain_query_key(
+                Domain::new("private.example.com.".to_owned()),
+                "transfer-key".to_owned(),
+            )
+            .unwrap();
+
+        server_config.set_domain_ksk(
+            Domain::new("example.com.".to_owned()),
+            "etc/example-com-ksk.pk8".to_owned(),
+        );
+        server_config.set_domain_zsk(
+            Domain::new("example.com.".to_owned()),
+            "etc/example-com-zsk.pk8".to_owned(),
+        );
+
+        assert_eq!(
+            parsed_config.get_transfer_key_name(&Domain::new("example.com".to_owned())),
+            Some(Domain::new("transfer-key".to_owned()))
+        );
+        assert!(parsed_config
+            .get_transfer_key(&Domain::new("example.com".to_owned()))
+            .is_some());
+        assert_eq!(
+            parsed_config.get_query_key_name(&Domain::new("WWW.Private.example.com".to_owned())),
+            Some(Domain::new("transfer-key".to_owned()))
+        );
+        assert_eq!(
+            parsed_config.get_query_key_name(&Domain::new("www.example.com".to_owned())),
+            None
+        );
 
+        assert!(parsed_config.get_randomize_case());
         assert!(parsed_config == server_config);
     }
+
+    #[test]
+    fn test_config_parse_invalid_domain() {
+        let path = std::env::temp_dir().join(format!("invalid-domain-{}.conf", std::process::id()));
+        std::fs::write(&path, "example..com DB etc/example-com.db\n").unwrap();
+        let result = super::get(path.to_string_lossy().to_string());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result.err(),
+            Some("line 1: Empty label in domain name".to_string())
+        );
+    }
+
+    #[test]
+    fn test_config_parse_invalid_values() {
+        let path = std::env::temp_dir().join(format!("invalid-value-{}.conf", std::process::id()));
+        for (config, error) in [
+            (
+                "all CR maybe\n",
+                "line 1: Case randomization must be on or off",
+            ),
+            (
+                "all LG logs/all.log\nkey KY not-base64!\n",
+                "line 2: Could not parse the secret of the TSIG key",
+            ),
+            (
+                "example.com KT bad..key\n",
+                "line 1: Empty label in domain name",
+            ),
+            (
+                "all TP dns\n",
+                "line 1: Could not parse the Server's TLS port",
+            ),
+        ] {
+            std::fs::write(&path, config).unwrap();
+            let result = super::get(path.to_string_lossy().to_string());
+            assert_eq!(result.err(), Some(error.to_string()), "{}", config);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,16 +1,106 @@
+use crate::dns_make::dns_idna;
 use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+// As labels guardam as maiusculas com que o nome foi escrito, mas a comparacao, o hash e a ordem
+// nao as distinguem das minusculas (RFC 4343)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "DomainLabels")]
 pub struct Domain {
     parts: Vec<String>,
 }
 
+// Forma serializada de um Domain, a mesma que o bincode sempre usou. Ao ler, as labels sao
+// validadas por `Domain::parse` como qualquer outro nome que chega de fora
+#[derive(Deserialize)]
+struct DomainLabels {
+    parts: Vec<String>,
+}
+
+impl TryFrom<DomainLabels> for Domain {
+    type Error = &'static str;
+
+    fn try_from(labels: DomainLabels) -> Result<Domain, &'static str> {
+        if labels.parts.is_empty() {
+            return Ok(Domain::new_empty());
+        }
+        let mut text = String::new();
+        for label in &labels.parts {
+            text.push_str(&escape_label(label));
+            text.push('.');
+        }
+        Domain::parse(&text)
+    }
+}
+
+impl PartialEq for Domain {
+    fn eq(&self, other: &Self) -> bool {
+        self.parts.len() == other.parts.len()
+            && self
+                .parts
+                .iter()
+                .zip(other.parts.iter())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for Domain {}
+
+impl Hash for Domain {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.parts.len().hash(state);
+        for label in &self.parts {
+            label.len().hash(state);
+            for byte in label.bytes() {
+                byte.to_ascii_lowercase().hash(state);
+            }
+        }
+    }
+}
+
+// Ordem canonica dos nomes (RFC 4034 6.1): labels comparadas da direita para a esquerda, em
+// minusculas, com o nome mais curto primeiro
+impl Ord for Domain {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Compara byte a byte sem alocar, porque e usada nas pesquisas dos indices das zonas
+        for (label, other_label) in self.parts.iter().rev().zip(other.parts.iter().rev()) {
+            let ordering = label
+                .bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(other_label.bytes().map(|byte| byte.to_ascii_lowercase()));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        self.parts.len().cmp(&other.parts.len())
+    }
+}
+
+impl PartialOrd for Domain {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Limites da RFC 1035 2.3.4, em bytes: cada label e o nome completo no formato da rede
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+
 impl Domain {
     pub fn new_empty() -> Domain{
         Domain { parts: Vec::new()}
     }
 
+    // Nao valida o nome: serve apenas para testes e nomes escritos no codigo. O texto vindo de
+    // ficheiros, do utilizador ou da rede passa por `Domain::parse`
     pub fn new(input: String) -> Domain {
+        if let Ok(domain) = Domain::parse(&input) {
+            return domain;
+        }
+
         // If the input string is empty or consists of only a `"."` character,
         // create a `parts` vector that is empty.
         let parts: Vec<String>;
@@ -29,6 +119,32 @@ impl Domain {
         Domain { parts }
     }
 
+    // Le um nome em texto, com os limites de tamanho das labels e do nome. Um "." precedido de
+    // "\" faz parte da label (ex: dns\.admin no SOAADMIN), tal como "\DDD" representa um byte.
+    // As labels Unicode sao guardadas na forma ASCII (ex: café passa a xn--caf-dma)
+    pub fn parse(input: &str) -> Result<Domain, &'static str> {
+        let (parts, _absolute) = split_labels(input)?;
+        Domain::from_labels(parts)
+    }
+
+    // Nomes que nao terminam em "." sao relativos a `origin`, como nos ficheiros de base de dados
+    pub fn parse_relative(input: &str, origin: &Domain) -> Result<Domain, &'static str> {
+        let (mut parts, absolute) = split_labels(input)?;
+        if !absolute {
+            parts.extend(origin.parts.iter().cloned());
+        }
+        Domain::from_labels(parts)
+    }
+
+    fn from_labels(parts: Vec<String>) -> Result<Domain, &'static str> {
+        // Cada label ocupa o seu tamanho mais um byte, e o nome acaba com a label vazia da raiz
+        let wire_len: usize = parts.iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+        if wire_len > MAX_NAME_LEN {
+            return Err("Domain name longer than 255 bytes");
+        }
+        Ok(Domain { parts })
+    }
+
     pub fn is_subdomain_of(&self, other: &Domain) -> bool {
         // If `other` is the root domain, then `self` is always considered
         // to be a subdomain of `other`, regardless of the number of parts
@@ -49,7 +165,14 @@ impl Domain {
             .iter()
             .rev()
             .zip(other.parts.iter().rev())
-            .all(|(a, b)| a == b)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    // O proprio nome e os seus ancestrais, do mais especifico ate a raiz
+    pub fn ancestors(&self) -> impl Iterator<Item = Domain> + '_ {
+        (0..=self.parts.len()).map(|i| Domain {
+            parts: self.parts[i..].to_vec(),
+        })
     }
 
     pub fn is_root(&self) -> bool {
@@ -64,12 +187,27 @@ impl Domain {
         } else {
             // Otherwise, join the parts of the domain with `.` characters and
             // return the resulting string.
-            let mut st = self.parts.join(".");
-            st.push('.');
+            let mut st = String::new();
+            for label in &self.parts {
+                st.push_str(&escape_label(label));
+                st.push('.');
+            }
             st
         }
     }
 
+    // Nome para mostrar ao utilizador, com as labels "xn--" na forma Unicode
+    pub fn to_unicode(&self) -> String {
+        if self.is_root() {
+            return ".".to_string();
+        }
+        let mut st = String::new();
+        for label in &self.parts {
+            st.push_str(&escape_label(&dns_idna::to_unicode(label)));
+            st.push('.');
+        }
+        st
+    }
 
     pub fn getparts(&self) -> &Vec<String> {
         &self.parts
@@ -80,6 +218,79 @@ impl Domain {
     }
 }
 
+// Parte o texto nas labels, resolvendo os escapes e convertendo as labels Unicode. Indica tambem
+// se o nome e absoluto, ou seja, se acaba num "." sem escape
+fn split_labels(input: &str) -> Result<(Vec<String>, bool), &'static str> {
+    if input == "." {
+        return Ok((Vec::new(), true));
+    }
+    if input.is_empty() {
+        return Err("Empty domain name");
+    }
+
+    let mut parts = Vec::new();
+    let mut label = String::new();
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => {
+                if label.is_empty() {
+                    return Err("Empty label in domain name");
+                }
+                parts.push(ascii_label(&std::mem::take(&mut label))?);
+            }
+            '\\' => match chars.next() {
+                Some(digit) if digit.is_ascii_digit() => {
+                    let digits: String = [Some(digit), chars.next(), chars.next()]
+                        .iter()
+                        .flatten()
+                        .collect();
+                    match digits.parse::<u8>() {
+                        Ok(byte) if digits.len() == 3 && byte.is_ascii() => label.push(byte as char),
+                        _ => return Err("Invalid escape in domain name"),
+                    }
+                }
+                Some(escaped) if escaped.is_ascii() => label.push(escaped),
+                _ => return Err("Invalid escape in domain name"),
+            },
+            c if c.is_ascii_graphic() || !c.is_ascii() => label.push(c),
+            _ => return Err("Invalid character in domain name"),
+        }
+    }
+
+    let absolute = label.is_empty();
+    if !absolute {
+        parts.push(ascii_label(&label)?);
+    }
+    Ok((parts, absolute))
+}
+
+fn ascii_label(label: &str) -> Result<String, &'static str> {
+    let label = dns_idna::to_ascii(label)?;
+    if label.len() > MAX_LABEL_LEN {
+        return Err("Domain label longer than 63 bytes");
+    }
+    Ok(label)
+}
+
+// Escreve a label com escapes para o "." e a "\" dentro dela e para os caracteres invisiveis
+fn escape_label(label: &str) -> String {
+    let mut escaped = String::new();
+    for c in label.chars() {
+        match c {
+            '.' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_ascii() && !c.is_ascii_graphic() => {
+                escaped.push_str(&format!("\\{:03}", c as u8))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 #[test]
 fn test_new_with_empty_string() {
     let domain = Domain::new("".to_string());
@@ -133,6 +344,16 @@ fn test_is_root() {
     assert!(!domain.is_root());
 }
 
+#[test]
+fn test_ancestors() {
+    let names: Vec<String> = Domain::new("www.Example.com.".to_string())
+        .ancestors()
+        .map(|name| name.to_string())
+        .collect();
+    assert_eq!(names, vec!["www.Example.com.", "Example.com.", "com.", "."]);
+    assert_eq!(Domain::new_empty().ancestors().count(), 1);
+}
+
 #[test]
 fn test_to_string() {
     let mut domain = Domain::new("".to_string());
@@ -144,3 +365,154 @@ fn test_to_string() {
     domain.set_parts(vec![]);
     assert_eq!(domain.to_string(), ".");
 }
+
+#[test]
+fn test_case_insensitive_eq() {
+    use std::collections::HashMap;
+
+    let lower = Domain::new("www.example.com".to_string());
+    let mixed = Domain::new("WWW.Example.COM.".to_string());
+    assert_eq!(lower, mixed);
+    assert_ne!(lower, Domain::new("www.example.org".to_string()));
+    assert_ne!(lower, Domain::new("www.example.com.br".to_string()));
+    // A grafia original mantem-se
+    assert_eq!(mixed.to_string(), "WWW.Example.COM.");
+
+    let mut map = HashMap::new();
+    map.insert(lower.to_owned(), 1);
+    assert_eq!(map.get(&mixed), Some(&1));
+    map.insert(mixed, 2);
+    assert_eq!(map.len(), 1);
+
+    assert!(Domain::new("foo.EXAMPLE.com".to_string())
+        .is_subdomain_of(&Domain::new("Example.COM".to_string())));
+}
+
+#[test]
+fn test_canonical_order() {
+    // Exemplo da RFC 4034 6.1
+    let ordered: Vec<Domain> = [
+        "example.",
+        "a.example.",
+        "yljkjljk.a.example.",
+        "Z.a.example.",
+        "zABC.a.EXAMPLE.",
+        "z.example.",
+        "*.z.example.",
+    ]
+    .iter()
+    .map(|name| Domain::new(name.to_string()))
+    .collect();
+    for pair in ordered.windows(2) {
+        assert!(pair[0] < pair[1], "{:?} < {:?}", pair[0], pair[1]);
+    }
+    let mut shuffled = ordered.to_owned();
+    shuffled.reverse();
+    shuffled.sort();
+    assert_eq!(shuffled, ordered);
+    assert!(Domain::new(".".to_string()) < Domain::new("com".to_string()));
+    assert_eq!(
+        Domain::new("Z.A.example".to_string()).cmp(&Domain::new("z.a.EXAMPLE".to_string())),
+        Ordering::Equal
+    );
+}
+
+#[test]
+fn test_parse() {
+    let domain = Domain::parse("www.example.com.").unwrap();
+    assert_eq!(domain.getparts(), &vec!["www", "example", "com"]);
+    assert!(Domain::parse(".").unwrap().is_root());
+    assert_eq!(Domain::parse("example.com").unwrap(), Domain::new("example.com.".to_string()));
+
+    // O "." com escape pertence a label, e o nome volta a ser escrito com o escape
+    let admin = Domain::parse("dns\\.admin.example.com.").unwrap();
+    assert_eq!(admin.getparts(), &vec!["dns.admin", "example", "com"]);
+    assert_eq!(admin.to_string(), "dns\\.admin.example.com.");
+    assert_eq!(Domain::parse(&admin.to_string()).unwrap(), admin);
+    assert_eq!(Domain::new(admin.to_string()), admin);
+    assert_eq!(
+        Domain::parse("a\\032b.example.").unwrap().getparts(),
+        &vec!["a b", "example"]
+    );
+
+    let label = "a".repeat(63);
+    assert!(Domain::parse(&format!("{}.example.com.", label)).is_ok());
+    // Tres labels de 63 bytes e uma de 61 ocupam 3 * 64 + 62 + 1 = 255 bytes, o maximo
+    let long_name = format!("{0}.{0}.{0}.{1}.", label, "a".repeat(61));
+    assert!(Domain::parse(&long_name).is_ok());
+
+    let origin = Domain::new("example.com.".to_string());
+    let relative = Domain::parse_relative("www", &origin).unwrap();
+    assert_eq!(relative, Domain::new("www.example.com.".to_string()));
+    let absolute = Domain::parse_relative("www.example.org.", &origin).unwrap();
+    assert_eq!(absolute, Domain::new("www.example.org.".to_string()));
+    let admin = Domain::parse_relative("dns\\.admin", &origin).unwrap();
+    assert_eq!(admin.getparts(), &vec!["dns.admin", "example", "com"]);
+}
+
+#[test]
+fn test_parse_errors() {
+    let label = "a".repeat(64);
+    let cases = [
+        ("", "Empty domain name"),
+        ("a..b", "Empty label in domain name"),
+        (".example.com", "Empty label in domain name"),
+        ("example.com..", "Empty label in domain name"),
+        ("www example.com", "Invalid character in domain name"),
+        ("www\texample.com", "Invalid character in domain name"),
+        ("caf\u{e9} cr\u{e8}me.example", "Invalid character in domain name"),
+        ("example\\", "Invalid escape in domain name"),
+        ("a\\25.example", "Invalid escape in domain name"),
+        ("a\\200.example", "Invalid escape in domain name"),
+        (&format!("{}.example.com", label), "Domain label longer than 63 bytes"),
+    ];
+    for (input, err) in cases {
+        assert_eq!(Domain::parse(input), Err(err), "{:?}", input);
+    }
+
+    let long_name = format!("{0}.{0}.{0}.{0}.", "a".repeat(63));
+    assert_eq!(Domain::parse(&long_name), Err("Domain name longer than 255 bytes"));
+    let origin = Domain::parse(&format!("{0}.{0}.{0}.", "a".repeat(63))).unwrap();
+    assert_eq!(
+        Domain::parse_relative(&"a".repeat(63), &origin),
+        Err("Domain name longer than 255 bytes")
+    );
+}
+
+#[test]
+fn test_parse_unicode() {
+    let domain = Domain::parse("Café.example.official.").unwrap();
+    assert_eq!(domain.getparts(), &vec!["xn--caf-dma", "example", "official"]);
+    assert_eq!(domain.to_string(), "xn--caf-dma.example.official.");
+    assert_eq!(domain.to_unicode(), "café.example.official.");
+    assert_eq!(Domain::parse("xn--caf-dma.example.official").unwrap(), domain);
+    assert_eq!(Domain::parse(&domain.to_unicode()).unwrap(), domain);
+
+    // O limite de 63 bytes aplica-se a forma ASCII: 59 bytes em Unicode passam a 65
+    let label = format!("{}é", "a".repeat(57));
+    assert_eq!(Domain::parse(&label), Err("Domain label longer than 63 bytes"));
+}
+
+#[test]
+fn test_deserialize() {
+    let names = [
+        Domain::new(".".to_string()),
+        Domain::new("www.Example.com.".to_string()),
+        Domain::parse("dns\\.admin.example.com.").unwrap(),
+    ];
+    for name in names {
+        let bytes = bincode::serialize(&name).unwrap();
+        let decoded: Domain = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.getparts(), name.getparts());
+    }
+
+    // Nomes que `Domain::new` aceita sem validar sao rejeitados ao ler
+    let invalid = [
+        Domain::new("a..b".to_string()),
+        Domain::new(format!("{}.example.com", "a".repeat(64))),
+    ];
+    for name in invalid {
+        let bytes = bincode::serialize(&name).unwrap();
+        assert!(bincode::deserialize::<Domain>(&bytes).is_err());
+    }
+}
@@ -1,8 +1,11 @@
-use log::{error, debug};
+use log::debug;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr, ops::Add};
+use std::net::{IpAddr, SocketAddr};
 
-use super::{dns_domain_name::Domain, domain_database_struct::DomainDatabase};
+use super::{
+    dns_domain_name::Domain,
+    dns_resource_data::{Edns, RData},
+};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct DNSMessage {
@@ -13,8 +16,8 @@ pub struct DNSMessage {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct DNSMessageHeaders {
     pub message_id: u16,
-    pub flags: u8,
-    pub response_code: Option<u8>,
+    pub flags: Flags,
+    pub response_code: Option<ResponseCode>,
     pub number_of_values: Option<u8>,
     pub number_of_authorities: Option<u8>,
     pub number_of_extra_values: Option<u8>,
@@ -37,10 +40,44 @@ pub struct DNSQueryInfo {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct DNSEntry {
     pub domain_name: Domain,
-    pub type_of_value: String,
-    pub value: String,
     pub ttl: u32,
     pub priority: Option<u16>,
+    pub data: RData,
+}
+
+// O sistema de flags funciona em binario em que se soma o valor de todas as flags
+// A   => 0 0 1 = 1
+// R   => 0 1 0 = 2
+// Q   => 1 0 0 = 4
+// TC  => resposta truncada por nao caber no limite do UDP = 8
+// AD  => resposta validada com DNSSEC pelo resolver = 16
+// A e TC so existem em respostas, por isso nao podem ser combinadas com Q. As restantes
+// combinacoes destes 5 bits sao validas (AD numa pergunta pede a validacao ao resolver, RFC 6840)
+// e qualquer outro bit e rejeitado. Nenhum construtor aceita uma combinacao invalida
+// Na serializacao com bincode continua a ser um u8, compativel com o formato antigo
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Flags {
+    bits: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum ResponseCode {
+    // Resposta valida
+    NoError = 0,
+    // O dominio existe mas a resposta nao veio de um servidor de autoridade (referral)
+    NonAuthoritative = 1,
+    // O dominio nao existe
+    NXDomain = 2,
+    // Mensagem mal formada
+    Malformed = 3,
+    ServerFailure = 4,
+    Refused = 5,
+    // Versao do EDNS nao suportada (RCODE estendido BADVERS)
+    BadVersion = 6,
+    // Mensagem sem a assinatura TSIG pedida ou com uma assinatura invalida (NOTAUTH)
+    NotAuth = 7,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -50,6 +87,14 @@ pub enum QueryType {
     CNAME,
     MX,
     PTR,
+    AAAA,
+    TXT,
+    SRV,
+    SOA,
+    DNSKEY,
+    RRSIG,
+    NSEC,
+    DS,
 }
 
 impl DNSMessage {
@@ -67,57 +112,139 @@ impl DNSMessage {
         res
     }
 
+    // Resposta sem valores e com a flag TC, enviada quando a resposta completa nao cabe num
+    // datagrama UDP. O cliente deve repetir a pergunta por TCP
+    pub fn truncated(&self) -> DNSMessage {
+        let mut header = self.header;
+        // Sem a flag Q a flag TC e sempre aceite, por isso o insert nao pode falhar
+        header.flags.remove(Flags::QUERY);
+        let _ = header.flags.insert(Flags::TRUNCATED);
+        header.number_of_values = None;
+        header.number_of_authorities = None;
+        header.number_of_extra_values = None;
+        let mut truncated = DNSMessage {
+            header,
+            data: DNSMessageData {
+                query_info: self.data.query_info.to_owned(),
+                response_values: None,
+                authorities_values: None,
+                extra_values: None,
+            },
+        };
+        // O OPT continua na resposta truncada
+        if let Some(edns) = self.get_edns() {
+            truncated.set_edns(edns.to_owned());
+        }
+        truncated
+    }
+
+    pub fn get_edns(&self) -> Option<&Edns> {
+        self.data
+            .extra_values
+            .as_ref()?
+            .iter()
+            .find_map(|entry| match entry.data {
+                RData::OPT(ref edns) => Some(edns),
+                _ => None,
+            })
+    }
+
+    // Substitui o OPT da mensagem, que fica no fim dos valores extra
+    pub fn set_edns(&mut self, edns: Edns) {
+        self.take_edns();
+        let mut extra_values = self.data.extra_values.take().unwrap_or_default();
+        extra_values.push(DNSEntry::new(Domain::new_empty(), 0, RData::OPT(edns)));
+        self.header.number_of_extra_values = extra_values.len().try_into().ok();
+        self.data.extra_values = Some(extra_values);
+    }
+
+    // Retira o OPT da mensagem. O EDNS e negociado em cada salto, por isso o OPT recebido
+    // nunca e reencaminhado nem guardado
+    pub fn take_edns(&mut self) -> Option<Edns> {
+        let extra_values = self.data.extra_values.as_mut()?;
+        let position = extra_values
+            .iter()
+            .position(|entry| matches!(entry.data, RData::OPT(_)))?;
+        let edns = match extra_values.remove(position).data {
+            RData::OPT(edns) => edns,
+            _ => return None,
+        };
+        if extra_values.is_empty() {
+            self.data.extra_values = None;
+            self.header.number_of_extra_values = None;
+        } else {
+            self.header.number_of_extra_values = extra_values.len().try_into().ok();
+        }
+        Some(edns)
+    }
+
+    // Destino da cadeia de CNAMEs da resposta que ainda nao foi respondido: segue os CNAMEs a
+    // partir do nome pedido e devolve o ultimo nome se nao houver records do tipo pedido nesse
+    // nome. Uma cadeia em ciclo nunca chega a um destino e devolve None
+    pub fn get_cname_target(&self) -> Option<Domain> {
+        let query_type = self.data.query_info.type_of_value;
+        if query_type == QueryType::CNAME {
+            return None;
+        }
+        let answers = self.data.response_values.as_ref()?;
+        let mut visited = vec![self.data.query_info.name.to_owned()];
+        loop {
+            let current = visited.last()?;
+            let target = answers.iter().find_map(|entry| match entry.data {
+                RData::CNAME(ref target) if entry.domain_name == *current => Some(target),
+                _ => None,
+            });
+            match target {
+                Some(target) if visited.contains(target) => return None,
+                Some(target) => visited.push(target.to_owned()),
+                None => break,
+            }
+        }
+        let target = visited.pop()?;
+        let answered = answers
+            .iter()
+            .any(|entry| entry.domain_name == target && entry.data.is_type(query_type));
+        match visited.is_empty() || answered {
+            true => None,
+            false => Some(target),
+        }
+    }
+
     pub fn get_authorities_ip(&self, entries: Option<Vec<DNSEntry>>,queried_domain:Domain,list_of_authorities:Vec<DNSEntry>) -> Option<Vec<SocketAddr>> {
         let mut ip_vec: Vec<SocketAddr> = Vec::new();
-        let mut new_ip;
+        let mut ipv6_vec: Vec<SocketAddr> = Vec::new();
         for val in list_of_authorities{
-            // Verificar se o valor do servidor de autoridade é um IP ou um nome
-            if !val
-                .value
-                .chars()
-                .all(|c| vec!['1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '.'].contains(&c))
-            {
-                // Procurar o IP do servidor de autoridade na lista de valores extra
-                new_ip =
-                    match entries {
-                        // Procurar na lista de valores extra o IP do servidor de autoridade
-                        Some(ref extra_values) => {
-                            match extra_values.iter().clone().find(|extra| {
-                                extra.domain_name == Domain::new(val.value.to_string())
-                            }) {
-                                Some(ns) => ns.value.to_owned(),
-                                None => continue,
-                            }
-                        }
-                        // Nao foi encontrado nenhum valor extra
-                        None => "No A records found to translate".to_string(),
-                    };
-            } else {
-                new_ip = val.value.to_owned();
-            }
-            let addr_vec = new_ip.split(':').collect::<Vec<_>>();
-            let new_ip_address = match addr_vec.len() {
+            let ns_name = match val.data {
+                RData::NS(ref ns_name) => ns_name,
+                _ => continue,
+            };
+            // Procurar na lista de valores extra os IPs (A e AAAA) do servidor de autoridade
+            let extra_values = match entries {
+                Some(ref extra_values) => extra_values,
+                // Nao foi encontrado nenhum valor extra
+                None => continue,
+            };
+            for extra in extra_values.iter().filter(|extra| &extra.domain_name == ns_name) {
                 // Formar novo IP com o IP do servidor de autoridade e a porta 5353
-                1 => addr_vec[0].to_string().add(":").add("5353"),
-                // Formar novo IP com o IP obtido dos extra values e a porta recebida
-                2 => new_ip,
-                // Nao foi encontrado um IP valido
-                _ => {
-                    error!(
-                        "FL @ malformed-ip {} {}",
-                        val.domain_name.to_string(),
-                        queried_domain.to_string()
-                    );
-                    panic!("Malformed IP on {}", val.domain_name.to_string());
+                let new_ip_address = match extra.data {
+                    RData::A(ip) => SocketAddr::new(IpAddr::V4(ip), 5353),
+                    RData::AAAA(ip) => SocketAddr::new(IpAddr::V6(ip), 5353),
+                    _ => continue,
+                };
+                debug!(
+                    "EV @ ns-ip-found {} {}",
+                    new_ip_address,
+                    queried_domain.to_string()
+                );
+                if new_ip_address.is_ipv4() {
+                    ip_vec.push(new_ip_address);
+                } else {
+                    ipv6_vec.push(new_ip_address);
                 }
-            };
-            debug!(
-                "EV @ ns-ip-found {} {}",
-                new_ip_address,
-                queried_domain.to_string()
-            );
-            ip_vec.push(new_ip_address.parse().unwrap());
+            }
         }
+        // Os enderecos IPv4 sao tentados primeiro
+        ip_vec.append(&mut ipv6_vec);
         if ip_vec.is_empty() {return None} else {return Some(ip_vec)}
     }
 }
@@ -126,7 +253,7 @@ impl DNSMessageHeaders {
     pub fn new() -> Self {
         DNSMessageHeaders {
             message_id: rand::random(),
-            flags: 0,
+            flags: Flags::empty(),
             response_code: None,
             number_of_values: None,
             number_of_authorities: None,
@@ -134,24 +261,6 @@ impl DNSMessageHeaders {
         }
     }
 
-    // O sistema de flags funciona em binario em que se soma o valor de todas as flags
-    // A   => 0 0 1 = 1
-    // R   => 0 1 0 = 2
-    // Q   => 1 0 0 = 4
-    // R+A => 0 1 1 = 3
-    // Q+R => 1 1 0 = 6
-    pub fn decode_flags(&self) -> Result<&str, &'static str> {
-        match self.flags {
-            0 => Ok(""),
-            1 => Ok("A"),
-            2 => Ok("R"),
-            4 => Ok("Q"),
-            3 => Ok("R+A"),
-            6 => Ok("Q+R"),
-            _ => Err("Flag value does not match any combination of flags"),
-        }
-    }
-
     pub fn get_string(&self) -> String {
         let mut rc: u8 = 0;
         let mut nov: u8 = 0;
@@ -159,7 +268,7 @@ impl DNSMessageHeaders {
         let mut noev: u8 = 0;
 
         if let Some(i) = self.response_code {
-            rc = i
+            rc = i.into()
         };
         if let Some(i) = self.number_of_values {
             nov = i
@@ -171,10 +280,7 @@ impl DNSMessageHeaders {
             noev = i
         };
 
-        let flags = match self.decode_flags() {
-            Ok(flag) => flag,
-            Err(err) => panic!("{err}"),
-        };
+        let flags = self.flags.get_string();
 
         format!(
             "{},{},{},{},{},{};",
@@ -268,28 +374,31 @@ impl DNSQueryInfo {
 }
 
 impl DNSEntry {
-    pub fn new() -> Self {
+    pub fn new(domain_name: Domain, ttl: u32, data: RData) -> Self {
         DNSEntry {
-            domain_name: Domain::new_empty(),
-            type_of_value: String::new(),
-            value: String::new(),
-            ttl: 0,
+            domain_name,
+            ttl,
             priority: None,
+            data,
         }
     }
 
     pub fn get_value(&self) -> String {
-        self.value.to_owned()
+        self.data.get_value()
     }
 
-    // falta a priority
+    // Formato de uma linha do ficheiro de base de dados, usado tambem na transferencia de zona
     pub fn get_string(&self) -> String {
-        if let Some(priority) = self.priority {
+        let priority = match self.data {
+            RData::MX { preference, .. } => Some(preference),
+            _ => self.priority,
+        };
+        if let Some(priority) = priority {
             format!(
                 "{} {} {} {} {}",
                 self.domain_name.to_string(),
-                self.type_of_value,
-                self.value,
+                self.data.get_str(),
+                self.get_value(),
                 self.ttl,
                 priority
             )
@@ -297,14 +406,114 @@ impl DNSEntry {
             format!(
                 "{} {} {} {}",
                 self.domain_name.to_string(),
-                self.type_of_value,
-                self.value,
+                self.data.get_str(),
+                self.get_value(),
                 self.ttl
             )
         }
     }
 }
 
+impl Flags {
+    pub const AUTHORITATIVE: Flags = Flags { bits: 1 };
+    pub const RECURSIVE: Flags = Flags { bits: 2 };
+    pub const QUERY: Flags = Flags { bits: 4 };
+    pub const TRUNCATED: Flags = Flags { bits: 8 };
+    pub const AUTHENTICATED: Flags = Flags { bits: 16 };
+    const ALL_BITS: u8 = 31;
+
+    pub const fn empty() -> Flags {
+        Flags { bits: 0 }
+    }
+
+    pub fn from_bits(bits: u8) -> Option<Flags> {
+        let response_only = Flags::AUTHORITATIVE.bits | Flags::TRUNCATED.bits;
+        if bits & !Flags::ALL_BITS != 0 {
+            None
+        } else if bits & Flags::QUERY.bits != 0 && bits & response_only != 0 {
+            None
+        } else {
+            Some(Flags { bits })
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    pub fn contains(&self, other: Flags) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    // Junta as flags de other, falhando se a combinacao resultante nao for valida
+    pub fn with(self, other: Flags) -> Result<Flags, &'static str> {
+        Flags::try_from(self.bits | other.bits)
+    }
+
+    pub fn insert(&mut self, other: Flags) -> Result<(), &'static str> {
+        *self = self.with(other)?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, other: Flags) {
+        self.bits &= !other.bits;
+    }
+
+    // Flags ativas separadas por "+", pela ordem Q, R, A, TC, AD (ex: "Q+R")
+    pub fn get_string(&self) -> String {
+        [
+            (Flags::QUERY, "Q"),
+            (Flags::RECURSIVE, "R"),
+            (Flags::AUTHORITATIVE, "A"),
+            (Flags::TRUNCATED, "TC"),
+            (Flags::AUTHENTICATED, "AD"),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>()
+        .join("+")
+    }
+}
+
+impl TryFrom<u8> for Flags {
+    type Error = &'static str;
+
+    fn try_from(bits: u8) -> Result<Flags, &'static str> {
+        Flags::from_bits(bits).ok_or("Flag value does not match any combination of flags")
+    }
+}
+
+impl From<Flags> for u8 {
+    fn from(flags: Flags) -> u8 {
+        flags.bits
+    }
+}
+
+impl TryFrom<u8> for ResponseCode {
+    type Error = &'static str;
+
+    fn try_from(code: u8) -> Result<ResponseCode, &'static str> {
+        match code {
+            0 => Ok(ResponseCode::NoError),
+            1 => Ok(ResponseCode::NonAuthoritative),
+            2 => Ok(ResponseCode::NXDomain),
+            3 => Ok(ResponseCode::Malformed),
+            4 => Ok(ResponseCode::ServerFailure),
+            5 => Ok(ResponseCode::Refused),
+            6 => Ok(ResponseCode::BadVersion),
+            7 => Ok(ResponseCode::NotAuth),
+            _ => Err("Response code does not exist"),
+        }
+    }
+}
+
+impl From<ResponseCode> for u8 {
+    fn from(code: ResponseCode) -> u8 {
+        code as u8
+    }
+}
+
 impl QueryType {
     pub fn get_str(&self) -> &'static str {
         match self {
@@ -313,6 +522,14 @@ impl QueryType {
             QueryType::CNAME => "CNAME",
             QueryType::MX => "MX",
             QueryType::PTR => "PTR",
+            QueryType::AAAA => "AAAA",
+            QueryType::TXT => "TXT",
+            QueryType::SRV => "SRV",
+            QueryType::SOA => "SOA",
+            QueryType::DNSKEY => "DNSKEY",
+            QueryType::RRSIG => "RRSIG",
+            QueryType::NSEC => "NSEC",
+            QueryType::DS => "DS",
         }
     }
 
@@ -323,7 +540,141 @@ impl QueryType {
             "CNAME" => Ok(QueryType::CNAME),
             "MX" => Ok(QueryType::MX),
             "PTR" => Ok(QueryType::PTR),
+            "AAAA" => Ok(QueryType::AAAA),
+            "TXT" => Ok(QueryType::TXT),
+            "SRV" => Ok(QueryType::SRV),
+            "SOA" => Ok(QueryType::SOA),
+            "DNSKEY" => Ok(QueryType::DNSKEY),
+            "RRSIG" => Ok(QueryType::RRSIG),
+            "NSEC" => Ok(QueryType::NSEC),
+            "DS" => Ok(QueryType::DS),
             _ => Err(format!("Cannot find QueryType of {}", query_type)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_combinations() {
+        for bits in 0..32 {
+            // Q com A ou TC e a unica combinacao invalida dos 5 bits
+            let valid = bits & 4 == 0 || bits & (1 | 8) == 0;
+            assert_eq!(
+                Flags::from_bits(bits).map(|flags| flags.bits()),
+                valid.then_some(bits)
+            );
+        }
+        assert_eq!(Flags::from_bits(32), None);
+        assert!(Flags::try_from(255).is_err());
+        assert!(Flags::try_from(5).is_err());
+        assert!(bincode::deserialize::<Flags>(&[12]).is_err());
+
+        let mut flags = Flags::QUERY.with(Flags::RECURSIVE).unwrap();
+        assert_eq!(flags.get_string(), "Q+R");
+        assert!(flags.insert(Flags::AUTHORITATIVE).is_err());
+        assert!(flags.with(Flags::TRUNCATED).is_err());
+        // Uma insercao rejeitada nao altera as flags
+        assert_eq!(flags.get_string(), "Q+R");
+        flags.insert(Flags::AUTHENTICATED).unwrap();
+        assert_eq!(flags.get_string(), "Q+R+AD");
+        flags.remove(Flags::RECURSIVE);
+        // Remover uma flag que nao esta ativa nao altera as restantes
+        flags.remove(Flags::RECURSIVE);
+        assert_eq!(flags.bits(), 20);
+        assert_eq!(Flags::empty().get_string(), "");
+        assert_eq!(
+            Flags::AUTHORITATIVE.with(Flags::TRUNCATED).unwrap().get_string(),
+            "A+TC"
+        );
+        assert_eq!(
+            Flags::RECURSIVE.with(Flags::AUTHENTICATED).unwrap().get_string(),
+            "R+AD"
+        );
+    }
+
+    #[test]
+    fn test_edns_set_and_take() {
+        let mut message = DNSMessage::new();
+        assert_eq!(message.take_edns(), None);
+
+        message.set_edns(Edns::new(1232));
+        message.set_edns(Edns::new(4096));
+        assert_eq!(message.header.number_of_extra_values, Some(1));
+        assert_eq!(
+            message.get_edns().map(|edns| edns.udp_payload_size),
+            Some(4096)
+        );
+        assert_eq!(message.truncated().get_edns(), message.get_edns());
+
+        assert_eq!(message.take_edns(), Some(Edns::new(4096)));
+        assert_eq!(message.data.extra_values, None);
+        assert_eq!(message.header.number_of_extra_values, None);
+    }
+
+    #[test]
+    fn test_cname_target() {
+        let name = |name: &str| Domain::new(name.to_string());
+        let cname = |owner: &str, target: &str| {
+            DNSEntry::new(name(owner), 300, RData::CNAME(name(target)))
+        };
+        let mut message = DNSMessage::new();
+        message.data.query_info = DNSQueryInfo {
+            name: name("mail1.example.com."),
+            type_of_value: QueryType::A,
+        };
+        assert_eq!(message.get_cname_target(), None);
+
+        // A cadeia sai da zona sem chegar a um A
+        message.data.response_values = Some(vec![
+            cname("mx1.example.com.", "mx.example.org."),
+            cname("Mail1.example.com.", "mx1.example.com."),
+        ]);
+        assert_eq!(message.get_cname_target(), Some(name("mx.example.org.")));
+
+        // O destino ja tem o tipo pedido
+        let mut answered = message.clone();
+        answered.data.response_values.as_mut().unwrap().push(DNSEntry::new(
+            name("mx.example.org."),
+            300,
+            RData::A("10.0.0.1".parse().unwrap()),
+        ));
+        assert_eq!(answered.get_cname_target(), None);
+
+        // Pedidos de CNAME sao respondidos pelo proprio CNAME
+        let mut cname_query = message.clone();
+        cname_query.data.query_info.type_of_value = QueryType::CNAME;
+        assert_eq!(cname_query.get_cname_target(), None);
+
+        // Uma cadeia em ciclo nao tem destino
+        message.data.response_values = Some(vec![
+            cname("mail1.example.com.", "mx1.example.com."),
+            cname("mx1.example.com.", "mail1.example.com."),
+        ]);
+        assert_eq!(message.get_cname_target(), None);
+    }
+
+    #[test]
+    fn test_header_serialization() {
+        let mut header = DNSMessageHeaders::new();
+        header.flags = Flags::QUERY.with(Flags::RECURSIVE).unwrap();
+        header.response_code = Some(ResponseCode::Refused);
+        let bytes = bincode::serialize(&header).unwrap();
+        assert_eq!(
+            bincode::deserialize::<DNSMessageHeaders>(&bytes).unwrap(),
+            header
+        );
+
+        // flags e response_code continuam a ser um u8 (byte 2) e um Option<u8> (bytes 3 e 4)
+        assert_eq!(bytes[2], 6);
+        assert_eq!(&bytes[3..5], &[1, 5]);
+        let mut invalid = bytes.clone();
+        invalid[2] = 32;
+        assert!(bincode::deserialize::<DNSMessageHeaders>(&invalid).is_err());
+        let mut invalid = bytes;
+        invalid[4] = 8;
+        assert!(bincode::deserialize::<DNSMessageHeaders>(&invalid).is_err());
+    }
+}
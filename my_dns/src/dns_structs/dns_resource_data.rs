@@ -0,0 +1,693 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use super::{dns_domain_name::Domain, dns_message::QueryType};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+pub enum RData {
+    A(Ipv4Addr),
+    NS(Domain),
+    CNAME(Domain),
+    MX {
+        preference: u16,
+        exchange: Domain,
+    },
+    PTR(Domain),
+    AAAA(Ipv6Addr),
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Domain,
+    },
+    // Uma ou mais character-strings, cada uma com no maximo 255 bytes. Sao guardadas em bytes
+    // porque o conteudo nao tem de ser texto UTF-8
+    TXT(Vec<Vec<u8>>),
+    SOA {
+        mname: Domain,
+        rname: Domain,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    // Pseudo-record do EDNS(0), apenas na seccao de valores extra e nunca guardado na base de dados
+    OPT(Edns),
+    // Chave publica de uma zona assinada (RFC 4034 2)
+    DNSKEY {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    // Assinatura de um RRset (RFC 4034 3), com as datas em segundos desde a epoch
+    RRSIG {
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: Domain,
+        signature: Vec<u8>,
+    },
+    // Nome seguinte da zona e tipos que existem no nome, para provar que um nome ou um tipo
+    // nao existem (RFC 4034 4)
+    NSEC {
+        next_domain: Domain,
+        types: Vec<u16>,
+    },
+    // Digest da KSK de uma zona filha, guardado na zona pai para ligar as duas zonas (RFC 4034 5)
+    DS {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+}
+
+// Codigos dos tipos de record conhecidos, usados no tipo coberto por um RRSIG e nos tipos de um NSEC
+const RECORD_TYPES: [(&str, u16); 14] = [
+    ("A", 1),
+    ("NS", 2),
+    ("CNAME", 5),
+    ("SOA", 6),
+    ("PTR", 12),
+    ("MX", 15),
+    ("TXT", 16),
+    ("AAAA", 28),
+    ("SRV", 33),
+    ("DS", 43),
+    ("OPT", 41),
+    ("RRSIG", 46),
+    ("NSEC", 47),
+    ("DNSKEY", 48),
+];
+
+// Codigo de um tipo a partir da sua mnemonica ou do formato generico TYPEnnn (RFC 3597)
+pub fn type_code_of(mnemonic: &str) -> Option<u16> {
+    match RECORD_TYPES.iter().find(|(name, _)| *name == mnemonic) {
+        Some((_, code)) => Some(*code),
+        None => mnemonic.strip_prefix("TYPE")?.parse().ok(),
+    }
+}
+
+pub fn type_mnemonic(code: u16) -> String {
+    match RECORD_TYPES.iter().find(|(_, known)| *known == code) {
+        Some((name, _)) => name.to_string(),
+        None => format!("TYPE{}", code),
+    }
+}
+
+// Campos do OPT (RFC 6891). O RCODE estendido nao e guardado aqui: faz parte do
+// response_code da mensagem e e dividido entre o cabecalho e o OPT na codificacao
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Edns {
+    // Maior resposta UDP que quem envia o OPT consegue receber
+    pub udp_payload_size: u16,
+    pub version: u8,
+    // Bit DO: quem pergunta aceita records de DNSSEC
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl RData {
+    // Constroi o valor de uma entry a partir do texto de um ficheiro de base de dados.
+    // Nomes que nao terminam em "." sao relativos ao dominio da base de dados (@).
+    pub fn from_zone_str(
+        type_of_value: &str,
+        value: &str,
+        priority: Option<u16>,
+        origin: &Domain,
+    ) -> Result<RData, String> {
+        match type_of_value {
+            "A" => match value.parse::<Ipv4Addr>() {
+                Ok(ip) => Ok(RData::A(ip)),
+                Err(_) => Err(format!("Invalid IPv4 address {}", value)),
+            },
+            "AAAA" => match value.parse::<Ipv6Addr>() {
+                Ok(ip) => Ok(RData::AAAA(ip)),
+                Err(_) => Err(format!("Invalid IPv6 address {}", value)),
+            },
+            "NS" => Ok(RData::NS(parse_name(value, origin)?)),
+            "CNAME" => Ok(RData::CNAME(parse_name(value, origin)?)),
+            "MX" => match priority {
+                Some(preference) => Ok(RData::MX {
+                    preference,
+                    exchange: parse_name(value, origin)?,
+                }),
+                None => Err(format!("MX entry {} without priority", value)),
+            },
+            "PTR" => Ok(RData::PTR(parse_name(value, origin)?)),
+            "TXT" => Ok(RData::TXT(parse_character_strings(value)?)),
+            // Valor no formato "priority weight port target"
+            "SRV" => {
+                let fields: Vec<&str> = value.split_whitespace().collect();
+                let parse_field = |field: &str| match field.parse::<u16>() {
+                    Ok(number) => Ok(number),
+                    Err(_) => Err(format!("Invalid SRV field {} in {}", field, value)),
+                };
+                match fields[..] {
+                    [priority, weight, port, target] => Ok(RData::SRV {
+                        priority: parse_field(priority)?,
+                        weight: parse_field(weight)?,
+                        port: parse_field(port)?,
+                        target: parse_name(target, origin)?,
+                    }),
+                    _ => Err(format!("Invalid SRV value {}", value)),
+                }
+            }
+            // Valor no formato "flags protocol algorithm chave", com a chave em base64
+            "DNSKEY" => {
+                let fields: Vec<&str> = value.split_whitespace().collect();
+                match fields[..] {
+                    [flags, protocol, algorithm, ref public_key @ ..] if !public_key.is_empty() => {
+                        Ok(RData::DNSKEY {
+                            flags: parse_number(flags, value)?,
+                            protocol: parse_number(protocol, value)?,
+                            algorithm: parse_number(algorithm, value)?,
+                            public_key: parse_base64(&public_key.concat(), value)?,
+                        })
+                    }
+                    _ => Err(format!("Invalid DNSKEY value {}", value)),
+                }
+            }
+            // Valor no formato "tipo algorithm labels ttl expiration inception key-tag signer
+            // assinatura", com as datas em segundos desde a epoch e a assinatura em base64
+            "RRSIG" => {
+                let fields: Vec<&str> = value.split_whitespace().collect();
+                match fields[..] {
+                    [type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, ref signature @ ..]
+                        if !signature.is_empty() =>
+                    {
+                        Ok(RData::RRSIG {
+                            type_covered: match type_code_of(type_covered) {
+                                Some(code) => code,
+                                None => return Err(format!("Invalid RRSIG type {}", value)),
+                            },
+                            algorithm: parse_number(algorithm, value)?,
+                            labels: parse_number(labels, value)?,
+                            original_ttl: parse_number(original_ttl, value)?,
+                            expiration: parse_number(expiration, value)?,
+                            inception: parse_number(inception, value)?,
+                            key_tag: parse_number(key_tag, value)?,
+                            signer: parse_name(signer, origin)?,
+                            signature: parse_base64(&signature.concat(), value)?,
+                        })
+                    }
+                    _ => Err(format!("Invalid RRSIG value {}", value)),
+                }
+            }
+            // Valor no formato "key-tag algorithm digest-type digest", com o digest em hexadecimal
+            "DS" => {
+                let fields: Vec<&str> = value.split_whitespace().collect();
+                match fields[..] {
+                    [key_tag, algorithm, digest_type, ref digest @ ..] if !digest.is_empty() => {
+                        Ok(RData::DS {
+                            key_tag: parse_number(key_tag, value)?,
+                            algorithm: parse_number(algorithm, value)?,
+                            digest_type: parse_number(digest_type, value)?,
+                            digest: parse_hex(&digest.concat(), value)?,
+                        })
+                    }
+                    _ => Err(format!("Invalid DS value {}", value)),
+                }
+            }
+            // Valor no formato "nome-seguinte tipo tipo ..."
+            "NSEC" => {
+                let mut fields = value.split_whitespace();
+                let next_domain = match fields.next() {
+                    Some(next_domain) => parse_name(next_domain, origin)?,
+                    None => return Err(format!("Invalid NSEC value {}", value)),
+                };
+                let mut types = Vec::new();
+                for mnemonic in fields {
+                    match type_code_of(mnemonic) {
+                        Some(code) => types.push(code),
+                        None => return Err(format!("Invalid NSEC type {} in {}", mnemonic, value)),
+                    }
+                }
+                types.sort_unstable();
+                types.dedup();
+                Ok(RData::NSEC { next_domain, types })
+            }
+            _ => Err(format!("Cannot find QueryType of {}", type_of_value)),
+        }
+    }
+
+    pub fn get_str(&self) -> &'static str {
+        match self {
+            RData::A(_) => "A",
+            RData::NS(_) => "NS",
+            RData::CNAME(_) => "CNAME",
+            RData::MX { .. } => "MX",
+            RData::PTR(_) => "PTR",
+            RData::AAAA(_) => "AAAA",
+            RData::TXT(_) => "TXT",
+            RData::SRV { .. } => "SRV",
+            RData::OPT(_) => "OPT",
+            RData::SOA { .. } => "SOA",
+            RData::DNSKEY { .. } => "DNSKEY",
+            RData::RRSIG { .. } => "RRSIG",
+            RData::NSEC { .. } => "NSEC",
+            RData::DS { .. } => "DS",
+        }
+    }
+
+    // Tipo dos dados, ou None para o OPT, que nao e um tipo que se possa pedir
+    pub fn get_query_type(&self) -> Option<QueryType> {
+        match self {
+            RData::A(_) => Some(QueryType::A),
+            RData::NS(_) => Some(QueryType::NS),
+            RData::CNAME(_) => Some(QueryType::CNAME),
+            RData::MX { .. } => Some(QueryType::MX),
+            RData::PTR(_) => Some(QueryType::PTR),
+            RData::AAAA(_) => Some(QueryType::AAAA),
+            RData::TXT(_) => Some(QueryType::TXT),
+            RData::SRV { .. } => Some(QueryType::SRV),
+            RData::OPT(_) => None,
+            RData::SOA { .. } => Some(QueryType::SOA),
+            RData::DNSKEY { .. } => Some(QueryType::DNSKEY),
+            RData::RRSIG { .. } => Some(QueryType::RRSIG),
+            RData::NSEC { .. } => Some(QueryType::NSEC),
+            RData::DS { .. } => Some(QueryType::DS),
+        }
+    }
+
+    pub fn is_type(&self, query_type: QueryType) -> bool {
+        self.get_query_type() == Some(query_type)
+    }
+
+    // Valor no formato dos ficheiros de base de dados (a preferencia do MX fica no campo
+    // da prioridade, ver DNSEntry::get_string)
+    pub fn get_value(&self) -> String {
+        match self {
+            RData::A(ip) => ip.to_string(),
+            RData::AAAA(ip) => ip.to_string(),
+            RData::NS(domain) | RData::CNAME(domain) | RData::PTR(domain) => domain.to_string(),
+            RData::MX { exchange, .. } => exchange.to_string(),
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => format!("{} {} {} {}", priority, weight, port, target.to_string()),
+            RData::OPT(edns) => format!(
+                "{} {} {}",
+                edns.udp_payload_size, edns.version, edns.dnssec_ok as u8
+            ),
+            RData::TXT(strings) => strings
+                .iter()
+                .map(|string| quote_character_string(string))
+                .collect::<Vec<String>>()
+                .join(" "),
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => format!(
+                "{} {} {} {} {} {} {}",
+                mname.to_string(),
+                rname.to_string(),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            RData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => format!(
+                "{} {} {} {}",
+                flags,
+                protocol,
+                algorithm,
+                STANDARD.encode(public_key)
+            ),
+            RData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => format!(
+                "{} {} {} {} {} {} {} {} {}",
+                type_mnemonic(*type_covered),
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer.to_string(),
+                STANDARD.encode(signature)
+            ),
+            RData::NSEC { next_domain, types } => {
+                let mut value = next_domain.to_string();
+                for code in types {
+                    value.push(' ');
+                    value.push_str(&type_mnemonic(*code));
+                }
+                value
+            }
+            RData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                let digest: String = digest.iter().map(|byte| format!("{:02X}", byte)).collect();
+                format!("{} {} {} {}", key_tag, algorithm, digest_type, digest)
+            }
+        }
+    }
+
+    // Nome de dominio para o qual o valor aponta, cujo IP pode ser adicionado aos valores extra
+    pub fn get_target_domain(&self) -> Option<&Domain> {
+        match self {
+            RData::NS(domain) | RData::CNAME(domain) | RData::PTR(domain) => Some(domain),
+            RData::MX { exchange, .. } => Some(exchange),
+            RData::SRV { target, .. } => Some(target),
+            RData::A(_)
+            | RData::AAAA(_)
+            | RData::TXT(_)
+            | RData::SOA { .. }
+            | RData::OPT(_)
+            | RData::DNSKEY { .. }
+            | RData::RRSIG { .. }
+            | RData::NSEC { .. }
+            | RData::DS { .. } => None,
+        }
+    }
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Edns {
+            udp_payload_size,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+}
+
+pub const MAX_CHARACTER_STRING_LEN: usize = 255;
+
+fn parse_name(field: &str, origin: &Domain) -> Result<Domain, String> {
+    match Domain::parse_relative(field, origin) {
+        Ok(domain) => Ok(domain),
+        Err(err) => Err(format!("{} {}", err, field)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(field: &str, value: &str) -> Result<T, String> {
+    match field.parse::<T>() {
+        Ok(number) => Ok(number),
+        Err(_) => Err(format!("Invalid field {} in {}", field, value)),
+    }
+}
+
+fn parse_hex(field: &str, value: &str) -> Result<Vec<u8>, String> {
+    if field.is_empty() || !field.len().is_multiple_of(2) {
+        return Err(format!("Invalid hexadecimal in {}", value));
+    }
+    (0..field.len())
+        .step_by(2)
+        .map(|i| match field.get(i..i + 2).map(|pair| u8::from_str_radix(pair, 16)) {
+            Some(Ok(byte)) => Ok(byte),
+            _ => Err(format!("Invalid hexadecimal in {}", value)),
+        })
+        .collect()
+}
+
+fn parse_base64(field: &str, value: &str) -> Result<Vec<u8>, String> {
+    match STANDARD.decode(field) {
+        Ok(bytes) if !bytes.is_empty() => Ok(bytes),
+        _ => Err(format!("Invalid base64 in {}", value)),
+    }
+}
+
+// Separa o valor de uma entry TXT nas suas character-strings.
+// Strings entre aspas podem ter espacos e escapes (\" \\ e \DDD em decimal),
+// strings sem aspas acabam no primeiro espaco.
+pub fn parse_character_strings(value: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut strings: Vec<Vec<u8>> = Vec::new();
+    let mut chars = value.trim().chars().peekable();
+
+    while let Some(&first) = chars.peek() {
+        if first.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let quoted = first == '"';
+        if quoted {
+            chars.next();
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut closed = !quoted;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted => {
+                    closed = true;
+                    break;
+                }
+                '"' => return Err(format!("Unexpected quote in {}", value)),
+                c if c.is_whitespace() && !quoted => break,
+                '\\' => match chars.next() {
+                    Some(d) if d.is_ascii_digit() => {
+                        let mut code = d.to_digit(10).unwrap();
+                        for _ in 0..2 {
+                            match chars.next().and_then(|d| d.to_digit(10)) {
+                                Some(digit) => code = code * 10 + digit,
+                                None => return Err(format!("Invalid escape in {}", value)),
+                            }
+                        }
+                        match u8::try_from(code) {
+                            Ok(byte) => bytes.push(byte),
+                            Err(_) => return Err(format!("Invalid escape in {}", value)),
+                        }
+                    }
+                    Some(escaped) => {
+                        let mut utf8 = [0u8; 4];
+                        bytes.extend_from_slice(escaped.encode_utf8(&mut utf8).as_bytes());
+                    }
+                    None => return Err(format!("Invalid escape in {}", value)),
+                },
+                c => {
+                    let mut utf8 = [0u8; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                }
+            }
+        }
+        if !closed {
+            return Err(format!("Unterminated string in {}", value));
+        }
+        if bytes.len() > MAX_CHARACTER_STRING_LEN {
+            return Err(format!("String longer than 255 bytes in {}", value));
+        }
+        strings.push(bytes);
+    }
+
+    if strings.is_empty() {
+        return Err("TXT entry without strings".to_string());
+    }
+    Ok(strings)
+}
+
+// Inverso de parse_character_strings: a string fica entre aspas com os caracteres especiais
+// escapados. Os bytes que nao formam UTF-8 valido sao escritos como \DDD
+pub fn quote_character_string(string: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for chunk in string.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c if c.is_ascii_control() => quoted.push_str(&format!("\\{:03}", c as u8)),
+                c => quoted.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            quoted.push_str(&format!("\\{:03}", byte));
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_zone_str() {
+        let origin = Domain::new("example.com.".to_string());
+        assert_eq!(
+            RData::from_zone_str("A", "10.3.3.1", None, &origin),
+            Ok(RData::A(Ipv4Addr::new(10, 3, 3, 1)))
+        );
+        assert_eq!(
+            RData::from_zone_str("AAAA", "2001:db8::1", None, &origin),
+            Ok(RData::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)))
+        );
+        assert_eq!(
+            RData::from_zone_str("NS", "ns1.example.com.", None, &origin),
+            Ok(RData::NS(Domain::new("ns1.example.com.".to_string())))
+        );
+        assert_eq!(
+            RData::from_zone_str("CNAME", "mx1", None, &origin),
+            Ok(RData::CNAME(Domain::new("mx1.example.com.".to_string())))
+        );
+        assert_eq!(
+            RData::from_zone_str("MX", "mx1.example.com.", Some(10), &origin),
+            Ok(RData::MX {
+                preference: 10,
+                exchange: Domain::new("mx1.example.com.".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_from_zone_str_invalid() {
+        let origin = Domain::new("example.com.".to_string());
+        assert!(RData::from_zone_str("A", "ns1.example.com.", None, &origin).is_err());
+        assert!(RData::from_zone_str("A", "10.3.3", None, &origin).is_err());
+        assert!(RData::from_zone_str("AAAA", "10.3.3.1", None, &origin).is_err());
+        assert!(RData::from_zone_str("MX", "mx1", None, &origin).is_err());
+        assert!(RData::from_zone_str("SPF", "mx1", None, &origin).is_err());
+        assert!(RData::from_zone_str("TXT", "\"v=spf1", None, &origin).is_err());
+        assert!(RData::from_zone_str("TXT", "\"\\300\"", None, &origin).is_err());
+        let long = format!("\"{}\"", "a".repeat(256));
+        assert!(RData::from_zone_str("TXT", &long, None, &origin).is_err());
+    }
+
+    #[test]
+    fn test_srv_value() {
+        let origin = Domain::new("example.com.".to_string());
+        let data = RData::from_zone_str("SRV", "10 60 5060 sip1", None, &origin).unwrap();
+        assert_eq!(
+            data,
+            RData::SRV {
+                priority: 10,
+                weight: 60,
+                port: 5060,
+                target: Domain::new("sip1.example.com.".to_string())
+            }
+        );
+        assert_eq!(data.get_value(), "10 60 5060 sip1.example.com.");
+        assert_eq!(
+            data.get_target_domain(),
+            Some(&Domain::new("sip1.example.com.".to_string()))
+        );
+        assert!(RData::from_zone_str("SRV", "10 60 sip1", None, &origin).is_err());
+        assert!(RData::from_zone_str("SRV", "10 60 70000 sip1", None, &origin).is_err());
+    }
+
+    #[test]
+    fn test_txt_strings() {
+        let origin = Domain::new("example.com.".to_string());
+        let data = RData::from_zone_str(
+            "TXT",
+            r#""v=spf1 mx -all" token "say \"hi\" \\ \065""#,
+            None,
+            &origin,
+        )
+        .unwrap();
+        assert_eq!(
+            data,
+            RData::TXT(vec![
+                b"v=spf1 mx -all".to_vec(),
+                b"token".to_vec(),
+                b"say \"hi\" \\ A".to_vec()
+            ])
+        );
+        assert_eq!(
+            data.get_value(),
+            r#""v=spf1 mx -all" "token" "say \"hi\" \\ A""#
+        );
+        assert_eq!(
+            RData::from_zone_str("TXT", &data.get_value(), None, &origin),
+            Ok(data)
+        );
+
+        // Bytes acima de 127 que nao sao UTF-8 valido passam pelo texto como \DDD
+        let data = RData::from_zone_str("TXT", r#""caf\233" "café""#, None, &origin).unwrap();
+        assert_eq!(
+            data,
+            RData::TXT(vec![b"caf\xE9".to_vec(), "café".as_bytes().to_vec()])
+        );
+        assert_eq!(data.get_value(), r#""caf\233" "café""#);
+        assert_eq!(
+            RData::from_zone_str("TXT", &data.get_value(), None, &origin),
+            Ok(data)
+        );
+    }
+
+    #[test]
+    fn test_dnssec_values() {
+        let origin = Domain::new("example.com.".to_string());
+        let rrsig = RData::from_zone_str(
+            "RRSIG",
+            "A 13 3 3600 1700000000 1690000000 12345 example.com. AAEC AwQ=",
+            None,
+            &origin,
+        )
+        .unwrap();
+        assert!(matches!(
+            rrsig,
+            RData::RRSIG {
+                type_covered: 1,
+                key_tag: 12345,
+                ..
+            }
+        ));
+        assert_eq!(
+            rrsig.get_value(),
+            "A 13 3 3600 1700000000 1690000000 12345 example.com. AAECAwQ="
+        );
+
+        let nsec =
+            RData::from_zone_str("NSEC", "www A TYPE65534 RRSIG NSEC", None, &origin).unwrap();
+        assert_eq!(nsec.get_value(), "www.example.com. A RRSIG NSEC TYPE65534");
+
+        for data in [rrsig, nsec] {
+            assert_eq!(
+                RData::from_zone_str(data.get_str(), &data.get_value(), None, &origin),
+                Ok(data)
+            );
+        }
+        assert!(RData::from_zone_str("DNSKEY", "257 3 13", None, &origin).is_err());
+        assert!(RData::from_zone_str("NSEC", "www BOGUS", None, &origin).is_err());
+
+        let ds = RData::from_zone_str("DS", "60485 13 2 d4b7d520 E7BB5F0F", None, &origin).unwrap();
+        assert_eq!(ds.get_value(), "60485 13 2 D4B7D520E7BB5F0F");
+        assert_eq!(
+            RData::from_zone_str("DS", &ds.get_value(), None, &origin),
+            Ok(ds)
+        );
+        assert!(RData::from_zone_str("DS", "60485 13 2 D4B", None, &origin).is_err());
+    }
+}
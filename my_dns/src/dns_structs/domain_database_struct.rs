@@ -1,27 +1,32 @@
 use super::dns_domain_name::Domain;
 use super::dns_message::{DNSEntry, DNSMessage, QueryType};
-use crate::dns_parse::domain_database_parse;
-use std::{collections::HashMap, ops::Add};
+use super::dns_resource_data::RData;
+use super::record_store::RecordStore;
+use std::borrow::Cow;
+use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct DomainDatabase {
     pub authority: bool,
     pub soa_entries: SOA,
     pub ns_records: HashMap<Domain, Vec<DNSEntry>>,
-    pub a_records: Option<Vec<DNSEntry>>,
-    pub cname_records: Option<Vec<DNSEntry>>,
-    pub mx_records: Option<Vec<DNSEntry>>,
-    pub ptr_records: Option<Vec<DNSEntry>>,
+    // Restantes records, indexados pelo tipo e pelo nome do dono. Inclui os records DNSSEC,
+    // gerados pelo SP ao assinar a zona ou recebidos na transferencia de zona, e os DS das zonas
+    // filhas assinadas, escritos no ficheiro da zona
+    pub records: RecordStore,
 }
 
 #[derive(Clone)]
 pub struct SOA {
-    pub primary_ns: DNSEntry,    // the primary name server for the domain
-    pub contact_email: DNSEntry, // the email address of the domain administrator
-    pub serial: DNSEntry,        // the serial number of the SOA record
-    pub refresh: DNSEntry,       // the time, in seconds, between refreshes of the zone
-    pub retry: DNSEntry,         // the time, in seconds, between retries if the refresh fails
-    pub expire: DNSEntry,        // the time, in seconds, after which the zone is considered expired
+    pub domain_name: Domain,   // the domain the SOA belongs to
+    pub ttl: u32,              // the TTL of the SOA record
+    pub primary_ns: Domain,    // the primary name server for the domain
+    pub contact_email: Domain, // the email address of the domain administrator
+    pub serial: u32,           // the serial number of the SOA record
+    pub refresh: u32,          // the time, in seconds, between refreshes of the zone
+    pub retry: u32,            // the time, in seconds, between retries if the refresh fails
+    pub expire: u32,           // the time, in seconds, after which the zone is considered expired
+    pub minimum: u32,          // the TTL, in seconds, of negative answers
 }
 
 impl DomainDatabase {
@@ -30,10 +35,7 @@ impl DomainDatabase {
             authority: false,
             soa_entries: SOA::new(),
             ns_records: HashMap::new(),
-            a_records: None,
-            cname_records: None,
-            mx_records: None,
-            ptr_records: None,
+            records: RecordStore::new(),
         }
     }
 
@@ -45,42 +47,76 @@ impl DomainDatabase {
         self.soa_entries.to_owned()
     }
 
-    pub fn get_ns_of(&self, domain: Domain) -> Option<Vec<DNSEntry>> {
-        let biggest_match = self
-            .ns_records
-            .iter()
-            .clone()
-            .filter(|(domain_name, _domain_ns_vec)| {
-                let domain1 = Domain::new(domain_name.to_string());
-                domain.is_subdomain_of(&domain1)
-            })
-            .max_by(|(dn1, _dnsvec1), (dn2, _dnsvec2)| {
-                dn1.to_string().len().cmp(&dn2.to_string().len())
-            })
-            .map(|(dn, dnsvec)| (dn.to_owned(), dnsvec.to_owned()))
-            .unzip()
-            .1;
-        biggest_match
+    // NS da delegacao mais especifica que contem o nome, procurada nos ancestrais do nome
+    pub fn get_ns_of(&self, domain: &Domain) -> Option<&[DNSEntry]> {
+        domain
+            .ancestors()
+            .find_map(|ancestor| self.ns_records.get(&ancestor))
+            .map(|records| records.as_slice())
     }
 
     pub fn get_ns_records(&self) -> HashMap<Domain, Vec<DNSEntry>> {
         self.ns_records.to_owned()
     }
 
+    // RRset do nome com o tipo pedido, sem copias. O SOA e guardado a parte, em soa_entries
+    pub fn get_rrset(&self, name: &Domain, query_type: QueryType) -> Option<&[DNSEntry]> {
+        match query_type {
+            QueryType::NS => self.ns_records.get(name).map(|records| records.as_slice()),
+            _ => self.records.get(name, query_type),
+        }
+    }
+
+    // Todos os records do tipo, emprestados e pela ordem canonica dos donos
+    pub fn records_of(&self, query_type: QueryType) -> impl Iterator<Item = &DNSEntry> {
+        self.records.of_type(query_type)
+    }
+
+    // Zona assinada, com pelo menos uma chave publica
+    pub fn is_signed(&self) -> bool {
+        self.records.has_type(QueryType::DNSKEY)
+    }
+
+    // Remove os records DNSSEC antes de voltar a assinar a zona
+    pub fn clear_dnssec_records(&mut self) {
+        for query_type in [QueryType::DNSKEY, QueryType::RRSIG, QueryType::NSEC] {
+            self.records.remove_type(query_type);
+        }
+    }
+
+    // Copias dos records de um tipo, como antes do indice. Para nao copiar, usar records_of ou
+    // get_rrset
+    fn get_records_of(&self, query_type: QueryType) -> Option<Vec<DNSEntry>> {
+        let records: Vec<DNSEntry> = self.records_of(query_type).cloned().collect();
+        (!records.is_empty()).then_some(records)
+    }
+
     pub fn get_a_records(&self) -> Option<Vec<DNSEntry>> {
-        self.a_records.to_owned()
+        self.get_records_of(QueryType::A)
     }
 
     pub fn get_cname_records(&self) -> Option<Vec<DNSEntry>> {
-        self.cname_records.to_owned()
+        self.get_records_of(QueryType::CNAME)
     }
 
     pub fn get_mx_records(&self) -> Option<Vec<DNSEntry>> {
-        self.mx_records.to_owned()
+        self.get_records_of(QueryType::MX)
     }
 
     pub fn get_ptr_records(&self) -> Option<Vec<DNSEntry>> {
-        self.ptr_records.to_owned()
+        self.get_records_of(QueryType::PTR)
+    }
+
+    // Um nome existe na zona se for dono de algum record ou se tiver descendentes (empty
+    // non-terminal). Se existir, a resposta negativa e NODATA em vez de NXDOMAIN
+    pub fn has_domain(&self, domain: &Domain) -> bool {
+        if self.authority && self.soa_entries.domain_name == *domain {
+            return true;
+        }
+        self.ns_records
+            .keys()
+            .any(|name| name.is_subdomain_of(domain))
+            || self.records.has_name_or_descendant(domain)
     }
 
     pub fn add_ns_record(&mut self, domain_name: Domain, entry: DNSEntry) {
@@ -96,127 +132,103 @@ impl DomainDatabase {
     pub fn add_dns_message(&mut self, dns_message: DNSMessage) {
         if let Some(response) = dns_message.data.response_values{
             for entry in response{
-                match entry.type_of_value.as_str() {
-                    "A" => self.add_a_record(entry) ,
-                    "NS" => self.add_ns_record(entry.domain_name.to_owned(),entry),
-                    "MX" => self.add_mx_record(entry),
-                    "CNAME" => self.add_cname_record(entry),
-                    "PTR" => self.add_ptr_record(entry),
-                    _ => panic!("Invalid Query Type")
-                }
+                self.add_entry(entry);
             }
         }
         if let Some(extras) = dns_message.data.extra_values{
             for entry in extras{
-                self.add_a_record(entry);
+                self.add_entry(entry);
             }
         }
     }
 
-    pub fn add_a_record(&mut self, entry: DNSEntry) {
-        match &mut self.a_records {
-            Some(records) => {
-                records.push(entry);
-            }
-            None => {
-                self.a_records = Some(vec![entry]);
-            }
-        };
+    pub fn add_entry(&mut self, entry: DNSEntry) {
+        match entry.data {
+            RData::NS(_) => self.add_ns_record(entry.domain_name.to_owned(), entry),
+            // O SOA da zona e guardado a parte, em soa_entries, e o OPT nunca e guardado
+            RData::SOA { .. } | RData::OPT(_) => (),
+            _ => self.records.insert(entry),
+        }
     }
-    pub fn add_cname_record(&mut self, entry: DNSEntry) {
-        match &mut self.cname_records {
-            Some(records) => {
-                records.push(entry);
-            }
-            None => {
-                self.cname_records = Some(vec![entry]);
-            }
+
+    // Indica se somos autoridade do nome: pertence a zona e nao esta abaixo de uma delegacao
+    pub fn is_authoritative_for(&self, domain: &Domain) -> bool {
+        let apex = &self.soa_entries.domain_name;
+        let delegated = match self.get_ns_of(domain) {
+            Some(ns_records) => ns_records.iter().any(|entry| entry.domain_name != *apex),
+            None => false,
         };
+        self.authority && domain.is_subdomain_of(apex) && !delegated
     }
-    pub fn add_mx_record(&mut self, entry: DNSEntry) {
-        match &mut self.mx_records {
-            Some(records) => {
-                records.push(entry);
-            }
-            None => {
-                self.mx_records = Some(vec![entry]);
-            }
-        }
-    }
-    pub fn add_ptr_record(&mut self, entry: DNSEntry) {
-        match &mut self.ptr_records {
-            Some(records) => {
-                records.push(entry);
-            }
-            None => {
-                self.ptr_records = Some(vec![entry]);
-            }
+
+    // Wildcard que sintetiza as respostas para um nome que nao existe na zona: o "*" do ancestral
+    // mais proximo que existe (closest encloser, RFC 4592 3.3.1), se esse wildcard existir. Abaixo
+    // de uma delegacao os nomes pertencem a zona filha e nao ha wildcard
+    pub fn get_wildcard_for(&self, domain: &Domain) -> Option<Domain> {
+        if self.has_domain(domain) || !self.is_authoritative_for(domain) {
+            return None;
         }
+
+        let encloser = domain
+            .ancestors()
+            .skip(1)
+            .find(|ancestor| self.has_domain(ancestor))?;
+        let mut wildcard_parts = vec!["*".to_string()];
+        wildcard_parts.extend(encloser.getparts().iter().cloned());
+        let mut wildcard = Domain::new_empty();
+        wildcard.set_parts(wildcard_parts);
+        self.has_domain(&wildcard).then_some(wildcard)
     }
 
+    // Records do nome pedido. Um nome que nao existe pode ser respondido por um wildcard, com os
+    // records do wildcard passados para o nome pedido (RFC 4592 4). So os records sintetizados a
+    // partir do wildcard e o SOA sao copiados; os restantes RRsets sao emprestados da zona
     pub fn get_domain_query(
         &self,
         query_type: QueryType,
-        queried_domain: Domain,
-    ) -> Option<Vec<DNSEntry>> {
-        let query_vec = match query_type {
-            QueryType::A => match self.get_a_records() {
-                Some(records) => Some(
-                    records
-                        .iter()
-                        .filter(|entry| entry.domain_name == queried_domain)
-                        .map(|entry| entry.to_owned())
-                        .collect::<Vec<DNSEntry>>(),
-                ),
-                None => None,
-            },
-            QueryType::NS => {
-                let records = self.get_ns_records();
-                match records.get(&queried_domain) {
-                    Some(ns_records) => Some(ns_records.to_owned()),
-                    None => None,
+        queried_domain: &Domain,
+    ) -> Option<Cow<'_, [DNSEntry]>> {
+        match self.get_exact_query(query_type, queried_domain) {
+            Some(records) => Some(records),
+            None => match query_type {
+                QueryType::NS
+                | QueryType::SOA
+                | QueryType::DNSKEY
+                | QueryType::RRSIG
+                | QueryType::NSEC
+                | QueryType::DS => None,
+                _ => {
+                    let wildcard = self.get_wildcard_for(queried_domain)?;
+                    let records = self.get_exact_query(query_type, &wildcard)?;
+                    Some(Cow::Owned(
+                        records
+                            .iter()
+                            .map(|entry| DNSEntry {
+                                domain_name: queried_domain.to_owned(),
+                                ..entry.to_owned()
+                            })
+                            .collect(),
+                    ))
                 }
-            }
-            QueryType::MX => match self.get_mx_records() {
-                Some(records) => Some(
-                    records
-                        .iter()
-                        .filter(|entry| entry.domain_name == queried_domain)
-                        .map(|entry| entry.to_owned())
-                        .collect::<Vec<DNSEntry>>(),
-                ),
-                None => None,
             },
-            QueryType::CNAME => match self.get_cname_records() {
-                Some(records) => Some(
-                    records
-                        .into_iter()
-                        .filter(|entry| entry.domain_name == queried_domain)
-                        .map(|entry| entry.to_owned())
-                        .collect::<Vec<DNSEntry>>(),
-                ),
-                None => None,
-            },
-            QueryType::PTR => match self.get_ptr_records() {
-                Some(records) => Some(
-                    records
-                        .iter()
-                        .filter(|entry| entry.domain_name == queried_domain)
-                        .map(|entry| entry.to_owned())
-                        .collect::<Vec<DNSEntry>>(),
-                ),
-                None => None,
-            },
-        };
-        match query_vec {
-            Some(vec) => {
-                if vec.is_empty() {
-                    None
+        }
+    }
+
+    fn get_exact_query(
+        &self,
+        query_type: QueryType,
+        queried_domain: &Domain,
+    ) -> Option<Cow<'_, [DNSEntry]>> {
+        match query_type {
+            // O SOA so existe nas bases de dados de que somos autoridade
+            QueryType::SOA => {
+                if self.authority && self.soa_entries.domain_name == *queried_domain {
+                    Some(Cow::Owned(vec![self.soa_entries.get_entry()]))
                 } else {
-                    Some(vec)
+                    None
                 }
             }
-            None => None,
+            _ => self.get_rrset(queried_domain, query_type).map(Cow::Borrowed),
         }
     }
 }
@@ -224,64 +236,195 @@ impl DomainDatabase {
 impl SOA {
     pub fn new() -> SOA {
         SOA {
-            primary_ns: DNSEntry::new(),
-            contact_email: DNSEntry::new(),
-            serial: DNSEntry::new(),
-            refresh: DNSEntry::new(),
-            retry: DNSEntry::new(),
-            expire: DNSEntry::new(),
+            domain_name: Domain::new_empty(),
+            ttl: 0,
+            primary_ns: Domain::new_empty(),
+            contact_email: Domain::new_empty(),
+            serial: 0,
+            refresh: 0,
+            retry: 0,
+            expire: 0,
+            minimum: 0,
         }
     }
 
-    pub fn get_primary_ns(&self) -> DNSEntry {
-        self.primary_ns.to_owned()
+    pub fn get_entry(&self) -> DNSEntry {
+        DNSEntry::new(
+            self.domain_name.to_owned(),
+            self.ttl,
+            RData::SOA {
+                mname: self.primary_ns.to_owned(),
+                rname: self.contact_email.to_owned(),
+                serial: self.serial,
+                refresh: self.refresh,
+                retry: self.retry,
+                expire: self.expire,
+                minimum: self.minimum,
+            },
+        )
     }
 
-    pub fn get_contact_email(&self) -> DNSEntry {
-        self.contact_email.to_owned()
+    // SOA usado na seccao de autoridade das respostas negativas, com o TTL limitado pelo
+    // minimum para que as caches guardem a resposta negativa durante esse tempo (RFC 2308)
+    pub fn get_negative_entry(&self) -> DNSEntry {
+        let mut entry = self.get_entry();
+        entry.ttl = self.ttl.min(self.minimum);
+        entry
     }
 
-    pub fn get_serial(&self) -> DNSEntry {
-        self.serial.to_owned()
+    // Linhas SOA* no formato do ficheiro de base de dados, usadas na transferencia de zona
+    pub fn get_zone_lines(&self) -> Vec<String> {
+        let name = self.domain_name.to_string();
+        vec![
+            format!(
+                "{} SOASP {} {}",
+                name,
+                self.primary_ns.to_string(),
+                self.ttl
+            ),
+            format!(
+                "{} SOAADMIN {} {}",
+                name,
+                self.contact_email.to_string(),
+                self.ttl
+            ),
+            format!("{} SOASERIAL {} {}", name, self.serial, self.ttl),
+            format!("{} SOAREFRESH {} {}", name, self.refresh, self.ttl),
+            format!("{} SOARETRY {} {}", name, self.retry, self.ttl),
+            format!("{} SOAEXPIRE {} {}", name, self.expire, self.ttl),
+            format!("{} SOAMINIMUM {} {}", name, self.minimum, self.ttl),
+        ]
     }
 
-    pub fn get_refresh(&self) -> DNSEntry {
-        self.refresh.to_owned()
+    pub fn get_serial_value(&self) -> u32 {
+        self.serial
     }
 
-    pub fn get_retry(&self) -> DNSEntry {
-        self.retry.to_owned()
+    pub fn get_refresh_value(&self) -> u64 {
+        self.refresh.into()
     }
 
-    pub fn get_expire(&self) -> DNSEntry {
-        self.expire.to_owned()
+    pub fn get_retry_value(&self) -> u64 {
+        self.retry.into()
     }
 
-    pub fn get_serial_value(&self) -> u32 {
-        match self.serial.get_value().parse::<u32>() {
-            Ok(serial) => serial,
-            Err(_) => 0,
-        }
+    pub fn get_expire_value(&self) -> u64 {
+        self.expire.into()
     }
+}
 
-    pub fn get_refresh_value(&self) -> u64 {
-        match self.refresh.get_value().parse::<u64>() {
-            Ok(refresh) => refresh,
-            Err(_) => 0,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_parse::domain_database_parse;
+
+    // Zona escrita com maiusculas misturadas: os nomes tem de ser encontrados com qualquer grafia
+    static ZONE: &str = "@ DEFAULT Example.COM.
+@ SOASP NS1.example.com. 3600
+@ SOAADMIN admin.example.com. 3600
+@ SOASERIAL 1 3600
+@ SOAREFRESH 14400 3600
+@ SOARETRY 3600 3600
+@ SOAEXPIRE 604800 3600
+@ SOAMINIMUM 300 3600
+@ NS ns1.example.com. 3600
+Sub NS ns.SUB.example.com. 3600
+ns1 A 10.0.0.1 3600
+WWW A 10.0.0.2 3600
+www A 10.0.0.3 3600
+";
+
+    fn name(name: &str) -> Domain {
+        Domain::new(name.to_string())
     }
 
-    pub fn get_retry_value(&self) -> u64 {
-        match self.retry.get_value().parse::<u64>() {
-            Ok(retry) => retry,
-            Err(_) => 0,
+    static WILDCARD_ZONE: &str = "@ DEFAULT example.com.
+@ SOASP ns1.example.com. 3600
+@ SOAADMIN admin.example.com. 3600
+@ SOASERIAL 1 3600
+@ NS ns1.example.com. 3600
+sub NS ns.sub.example.com. 3600
+ns1 A 10.0.0.1 3600
+www A 10.0.0.2 3600
+* A 10.0.0.9 3600
+*.wild TXT \"wild\" 3600
+host.ent A 10.0.0.5 3600
+ns.sub A 10.0.0.4 3600
+";
+
+    #[test]
+    fn test_wildcards() {
+        let db = domain_database_parse::parse_from_str(WILDCARD_ZONE.to_string()).unwrap();
+        let a_records = |queried: &str| db.get_domain_query(QueryType::A, &name(queried));
+
+        // Nomes que nao existem, a um ou mais niveis do wildcard, recebem os seus records
+        for queried in ["mail.example.com.", "a.b.example.com.", "Mail.Example.COM."] {
+            let answers = a_records(queried).unwrap();
+            assert_eq!(answers.len(), 1, "{}", queried);
+            assert_eq!(answers[0].domain_name, name(queried));
+            assert_eq!(answers[0].data, RData::A("10.0.0.9".parse().unwrap()));
         }
+        assert_eq!(
+            db.get_wildcard_for(&name("mail.example.com.")),
+            Some(name("*.example.com."))
+        );
+
+        // Os nomes que existem nao usam o wildcard, nem para outros tipos
+        assert_eq!(a_records("www.example.com.").unwrap()[0].domain_name, name("www.example.com."));
+        assert!(db.get_domain_query(QueryType::MX, &name("www.example.com.")).is_none());
+        assert!(a_records("*.example.com.").is_some());
+        assert_eq!(db.get_wildcard_for(&name("www.example.com.")), None);
+
+        // O wildcard de um ancestral que existe sem o tipo pedido resulta num NODATA
+        let txt = db.get_domain_query(QueryType::TXT, &name("x.wild.example.com.")).unwrap();
+        assert_eq!(txt[0].domain_name, name("x.wild.example.com."));
+        assert!(a_records("x.wild.example.com.").is_none());
+        assert_eq!(
+            db.get_wildcard_for(&name("x.wild.example.com.")),
+            Some(name("*.wild.example.com."))
+        );
+
+        // O ancestral mais proximo de x.ent e o empty non-terminal ent, que nao tem wildcard
+        assert!(a_records("ent.example.com.").is_none());
+        assert!(a_records("x.ent.example.com.").is_none());
+        assert_eq!(db.get_wildcard_for(&name("x.ent.example.com.")), None);
+
+        // Abaixo da delegacao os nomes pertencem a zona filha
+        assert!(a_records("host.sub.example.com.").is_none());
+        assert_eq!(db.get_wildcard_for(&name("host.sub.example.com.")), None);
+        assert!(a_records("www.example.org.").is_none());
     }
 
-    pub fn get_expire_value(&self) -> u64 {
-        match self.expire.get_value().parse::<u64>() {
-            Ok(expire) => expire,
-            Err(_) => 0,
+    #[test]
+    fn test_mixed_case_zone() {
+        let db = domain_database_parse::parse_from_str(ZONE.to_string()).unwrap();
+
+        for query in ["www.example.com.", "WWW.EXAMPLE.COM.", "wWw.ExAmPlE.cOm."] {
+            let answers = db.get_domain_query(QueryType::A, &name(query)).unwrap();
+            assert_eq!(answers.len(), 2);
         }
+        assert!(db.get_domain_query(QueryType::SOA, &name("EXAMPLE.com.")).is_some());
+        assert!(db.has_domain(&name("sub.EXAMPLE.com.")));
+        assert!(!db.has_domain(&name("mail.example.com.")));
+
+        // A delegacao mais especifica e escolhida mesmo com grafias diferentes
+        let ns = db.get_ns_of(&name("host.SUB.example.COM.")).unwrap();
+        assert_eq!(ns[0].domain_name, name("sub.example.com."));
+        let ns = db.get_ns_of(&name("NS1.example.com.")).unwrap();
+        assert_eq!(ns[0].domain_name, name("example.com."));
+        assert_eq!(db.get_ns_records().len(), 2);
+
+        // Os getters antigos copiam os records do tipo, com os donos pela ordem canonica
+        let a_records = db.get_a_records().unwrap();
+        assert_eq!(a_records.len(), 3);
+        assert_eq!(a_records[0].domain_name, name("ns1.example.com."));
+        assert!(db.get_mx_records().is_none());
+        assert!(db.get_cname_records().is_none());
+        assert!(db.get_ptr_records().is_none());
+
+        assert!(db.is_authoritative_for(&name("WWW.example.com.")));
+        assert!(!db.is_authoritative_for(&name("sub.example.com.")));
+        assert!(!db.is_authoritative_for(&name("host.Sub.example.com.")));
+        assert!(!db.is_authoritative_for(&name("www.example.org.")));
     }
 }
@@ -1,4 +1,7 @@
 pub mod dns_message;
+pub mod dns_resource_data;
 pub mod domain_database_struct;
+pub mod record_store;
+pub mod zone_tree;
 pub mod server_config;
 pub mod dns_domain_name;
@@ -0,0 +1,162 @@
+use super::dns_domain_name::Domain;
+use super::dns_message::{DNSEntry, QueryType};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+// Records de uma zona indexados pelo tipo e pelo nome do dono. Em cada tipo os nomes ficam pela
+// ordem canonica (RFC 4034 6.1), por isso os descendentes de um nome vem logo a seguir a ele e o
+// NSEC que cobre um nome e o do dono anterior. Os RRsets sao devolvidos emprestados, sem copias
+#[derive(Clone, Debug, Default)]
+pub struct RecordStore {
+    types: HashMap<QueryType, BTreeMap<Domain, Vec<DNSEntry>>>,
+}
+
+impl RecordStore {
+    pub fn new() -> Self {
+        RecordStore::default()
+    }
+
+    // O OPT nao e um record da zona e nunca e guardado
+    pub fn insert(&mut self, entry: DNSEntry) {
+        let query_type = match entry.data.get_query_type() {
+            Some(query_type) => query_type,
+            None => return,
+        };
+        self.types
+            .entry(query_type)
+            .or_default()
+            .entry(entry.domain_name.to_owned())
+            .or_default()
+            .push(entry);
+    }
+
+    // RRset do nome com o tipo pedido
+    pub fn get(&self, name: &Domain, query_type: QueryType) -> Option<&[DNSEntry]> {
+        self.types
+            .get(&query_type)?
+            .get(name)
+            .map(|records| records.as_slice())
+    }
+
+    // Todos os records do tipo, pela ordem canonica dos donos
+    pub fn of_type(&self, query_type: QueryType) -> impl Iterator<Item = &DNSEntry> {
+        self.types
+            .get(&query_type)
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, records)| records)
+    }
+
+    pub fn has_type(&self, query_type: QueryType) -> bool {
+        self.types.contains_key(&query_type)
+    }
+
+    // Indica se o nome ou algum descendente seu e dono de um record
+    pub fn has_name_or_descendant(&self, name: &Domain) -> bool {
+        self.types.values().any(|names| {
+            names
+                .range::<Domain, _>(name..)
+                .next()
+                .is_some_and(|(owner, _)| owner.is_subdomain_of(name))
+        })
+    }
+
+    // RRset do tipo com o maior dono anterior ao nome na ordem canonica
+    pub fn preceding(&self, name: &Domain, query_type: QueryType) -> Option<&[DNSEntry]> {
+        self.types
+            .get(&query_type)?
+            .range::<Domain, _>((Bound::Unbounded, Bound::Excluded(name)))
+            .next_back()
+            .map(|(_, records)| records.as_slice())
+    }
+
+    pub fn remove_type(&mut self, query_type: QueryType) {
+        self.types.remove(&query_type);
+    }
+
+    pub fn len(&self) -> usize {
+        self.types
+            .values()
+            .flat_map(|names| names.values())
+            .map(|records| records.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_structs::dns_resource_data::{Edns, RData};
+
+    fn name(name: &str) -> Domain {
+        Domain::new(name.to_string())
+    }
+
+    fn a_record(owner: &str, last: u8) -> DNSEntry {
+        DNSEntry::new(name(owner), 300, RData::A([10, 0, 0, last].into()))
+    }
+
+    #[test]
+    fn test_record_store() {
+        let mut store = RecordStore::new();
+        assert!(store.is_empty());
+        store.insert(a_record("www.example.com.", 1));
+        store.insert(a_record("WWW.example.com.", 2));
+        store.insert(a_record("a.b.example.com.", 3));
+        store.insert(DNSEntry::new(
+            name("www.example.com."),
+            300,
+            RData::TXT(vec![b"site".to_vec()]),
+        ));
+        store.insert(DNSEntry::new(
+            Domain::new_empty(),
+            0,
+            RData::OPT(Edns::new(1232)),
+        ));
+        assert_eq!(store.len(), 4);
+
+        let rrset = store.get(&name("Www.Example.com."), QueryType::A).unwrap();
+        assert_eq!(rrset.len(), 2);
+        assert_eq!(rrset[1].data, RData::A([10, 0, 0, 2].into()));
+        assert!(store
+            .get(&name("www.example.com."), QueryType::MX)
+            .is_none());
+        assert!(store.get(&name("b.example.com."), QueryType::A).is_none());
+
+        // Os records de um tipo vem pela ordem canonica dos donos
+        let owners: Vec<&Domain> = store
+            .of_type(QueryType::A)
+            .map(|entry| &entry.domain_name)
+            .collect();
+        assert_eq!(owners[0], &name("a.b.example.com."));
+        assert_eq!(owners.len(), 3);
+        assert!(store.has_type(QueryType::TXT));
+        assert!(!store.has_type(QueryType::MX));
+
+        // b.example.com so existe por ter descendentes (empty non-terminal)
+        assert!(store.has_name_or_descendant(&name("B.example.com.")));
+        assert!(store.has_name_or_descendant(&name("example.com.")));
+        assert!(!store.has_name_or_descendant(&name("c.example.com.")));
+        assert!(!store.has_name_or_descendant(&name("x.www.example.com.")));
+
+        let preceding = store
+            .preceding(&name("mail.example.com."), QueryType::A)
+            .unwrap();
+        assert_eq!(preceding[0].domain_name, name("a.b.example.com."));
+        assert!(store
+            .preceding(&name("a.b.example.com."), QueryType::A)
+            .is_none());
+        let preceding = store
+            .preceding(&name("zzz.example.com."), QueryType::A)
+            .unwrap();
+        assert_eq!(preceding[0].domain_name, name("www.example.com."));
+
+        store.remove_type(QueryType::A);
+        assert!(!store.has_type(QueryType::A));
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -1,6 +1,7 @@
 use std::{collections::HashMap, net::SocketAddr, ops::Add};
 
 use super::dns_domain_name::Domain;
+use crate::dns_make::{dns_tls::DOT_PORT, dns_tsig::TsigKey};
 
 #[derive(Clone,PartialEq)]
 pub struct ServerConfig {
@@ -8,6 +9,19 @@ pub struct ServerConfig {
     server_dds: Option<HashMap<Domain, SocketAddr>>,
     all_log: String,
     st_db: String,
+    // Certificado e chave privada (PEM) do servidor DNS sobre TLS
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_port: Option<u16>,
+    // Portas do DNS sobre HTTPS, em HTTP simples e em HTTPS (este usa o certificado do TLS)
+    http_port: Option<u16>,
+    https_port: Option<u16>,
+    // Chaves TSIG, pelo nome da chave
+    tsig_keys: HashMap<Domain, TsigKey>,
+    // Ficheiro com os DS ou DNSKEY da raiz; com ele o resolver valida as respostas com DNSSEC
+    trust_anchor: Option<String>,
+    // Perguntas do resolver com maiusculas aleatorias no nome (0x20)
+    randomize_case: bool,
 }
 #[derive(Clone,PartialEq)]
 pub struct DomainConfig {
@@ -15,6 +29,13 @@ pub struct DomainConfig {
     domain_sp: Option<SocketAddr>,
     domain_ss: Option<Vec<SocketAddr>>,
     domain_log: String,
+    // Chave TSIG que assina as transferencias de zona entre o SP e os SS
+    transfer_key: Option<Domain>,
+    // Chave TSIG sem a qual as perguntas sobre o dominio sao recusadas
+    query_key: Option<Domain>,
+    // Ficheiros (PKCS#8) das chaves DNSSEC da zona, criados se nao existirem
+    ksk_path: Option<String>,
+    zsk_path: Option<String>,
 }
 
 impl ServerConfig {
@@ -24,6 +45,14 @@ impl ServerConfig {
             server_dds: None,
             all_log: "".to_string(),
             st_db: "".to_string(),
+            tls_cert: None,
+            tls_key: None,
+            tls_port: None,
+            http_port: None,
+            https_port: None,
+            tsig_keys: HashMap::new(),
+            trust_anchor: None,
+            randomize_case: false,
         }
     }
     pub fn add_domain_db(&mut self, domain: Domain, db_path: String) {
@@ -122,6 +151,80 @@ impl ServerConfig {
     pub fn set_st_db(&mut self, path: String) {
         self.st_db = path;
     }
+    pub fn set_tls_cert(&mut self, path: String) {
+        self.tls_cert = Some(path);
+    }
+    pub fn set_tls_key(&mut self, path: String) {
+        self.tls_key = Some(path);
+    }
+    pub fn set_tls_port(&mut self, port_string: String) -> Result<(), &'static str> {
+        match port_string.parse() {
+            Ok(port) => self.tls_port = Some(port),
+            Err(_) => return Err("Could not parse the Server's TLS port"),
+        }
+        Ok(())
+    }
+    pub fn set_http_port(&mut self, port_string: String) -> Result<(), &'static str> {
+        match port_string.parse() {
+            Ok(port) => self.http_port = Some(port),
+            Err(_) => return Err("Could not parse the Server's HTTP port"),
+        }
+        Ok(())
+    }
+    pub fn set_https_port(&mut self, port_string: String) -> Result<(), &'static str> {
+        match port_string.parse() {
+            Ok(port) => self.https_port = Some(port),
+            Err(_) => return Err("Could not parse the Server's HTTPS port"),
+        }
+        Ok(())
+    }
+    pub fn set_trust_anchor(&mut self, path: String) {
+        self.trust_anchor = Some(path);
+    }
+    pub fn set_randomize_case(&mut self, value: String) -> Result<(), &'static str> {
+        match value.trim() {
+            "on" => self.randomize_case = true,
+            "off" => self.randomize_case = false,
+            _ => return Err("Case randomization must be on or off"),
+        }
+        Ok(())
+    }
+    pub fn add_tsig_key(&mut self, key_name: Domain, secret: String) -> Result<(), &'static str> {
+        match TsigKey::from_base64(key_name.to_owned(), &secret) {
+            Ok(key) => {
+                self.tsig_keys.insert(key_name, key);
+                Ok(())
+            }
+            Err(_) => Err("Could not parse the secret of the TSIG key"),
+        }
+    }
+    pub fn set_domain_transfer_key(
+        &mut self,
+        domain: Domain,
+        key_name: String,
+    ) -> Result<(), &'static str> {
+        let key_name = Domain::parse(key_name.trim())?;
+        self.domain_config_mut(domain).set_transfer_key(key_name);
+        Ok(())
+    }
+    pub fn set_domain_query_key(
+        &mut self,
+        domain: Domain,
+        key_name: String,
+    ) -> Result<(), &'static str> {
+        let key_name = Domain::parse(key_name.trim())?;
+        self.domain_config_mut(domain).set_query_key(key_name);
+        Ok(())
+    }
+    pub fn set_domain_ksk(&mut self, domain: Domain, key_path: String) {
+        self.domain_config_mut(domain).set_ksk_path(key_path);
+    }
+    pub fn set_domain_zsk(&mut self, domain: Domain, key_path: String) {
+        self.domain_config_mut(domain).set_zsk_path(key_path);
+    }
+    fn domain_config_mut(&mut self, domain: Domain) -> &mut DomainConfig {
+        self.domain_configs.entry(domain).or_insert_with(DomainConfig::new)
+    }
     pub fn get_domain_configs(&self) -> HashMap<Domain, DomainConfig> {
         self.domain_configs.to_owned()
     }
@@ -144,6 +247,48 @@ impl ServerConfig {
     pub fn get_st_db(&self) -> String {
         self.st_db.to_owned()
     }
+    // So ha servico DNS sobre TLS quando o certificado e a chave estao ambos configurados
+    pub fn get_tls_cert_and_key(&self) -> Option<(String, String)> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some((cert.to_owned(), key.to_owned())),
+            _ => None,
+        }
+    }
+    pub fn get_tls_port(&self) -> u16 {
+        self.tls_port.unwrap_or(DOT_PORT)
+    }
+    pub fn get_http_port(&self) -> Option<u16> {
+        self.http_port
+    }
+    pub fn get_https_port(&self) -> Option<u16> {
+        self.https_port
+    }
+    pub fn get_trust_anchor(&self) -> Option<String> {
+        self.trust_anchor.to_owned()
+    }
+    pub fn get_randomize_case(&self) -> bool {
+        self.randomize_case
+    }
+    pub fn get_tsig_keys(&self) -> Vec<TsigKey> {
+        self.tsig_keys.values().cloned().collect()
+    }
+    // Nome da chave das transferencias de zona do dominio, mesmo que a chave nao esteja definida,
+    // para que uma chave em falta nunca desligue a autenticacao
+    pub fn get_transfer_key_name(&self, domain: &Domain) -> Option<Domain> {
+        self.domain_configs.get(domain).and_then(|dc| dc.get_transfer_key())
+    }
+    pub fn get_transfer_key(&self, domain: &Domain) -> Option<TsigKey> {
+        self.get_transfer_key_name(domain)
+            .and_then(|key_name| self.tsig_keys.get(&key_name).cloned())
+    }
+    // Chave exigida nas perguntas sobre um nome, definida no dominio mais especifico que o contem
+    pub fn get_query_key_name(&self, name: &Domain) -> Option<Domain> {
+        self.domain_configs
+            .iter()
+            .filter(|(domain, dc)| name.is_subdomain_of(domain) && dc.get_query_key().is_some())
+            .max_by_key(|(domain, _)| domain.getparts().len())
+            .and_then(|(_, dc)| dc.get_query_key())
+    }
 
 }
 
@@ -154,6 +299,10 @@ impl DomainConfig {
             domain_sp: None,
             domain_ss: None,
             domain_log: "".to_string(),
+            transfer_key: None,
+            query_key: None,
+            ksk_path: None,
+            zsk_path: None,
         }
     }
     pub fn get_domain_db(&self) -> Option<String> {
@@ -168,6 +317,19 @@ impl DomainConfig {
     pub fn get_domain_log(&self) -> String {
         self.domain_log.to_owned()
     }
+    pub fn get_transfer_key(&self) -> Option<Domain> {
+        self.transfer_key.to_owned()
+    }
+    pub fn get_query_key(&self) -> Option<Domain> {
+        self.query_key.to_owned()
+    }
+    // A zona so e assinada quando as duas chaves estao configuradas
+    pub fn get_dnssec_key_paths(&self) -> Option<(String, String)> {
+        match (&self.ksk_path, &self.zsk_path) {
+            (Some(ksk), Some(zsk)) => Some((ksk.to_owned(), zsk.to_owned())),
+            _ => None,
+        }
+    }
 
     pub fn set_domain_db(&mut self, db_path: String) {
         self.domain_db = Some(db_path);
@@ -184,4 +346,16 @@ impl DomainConfig {
     pub fn set_domain_log(&mut self, log_path: String) {
         self.domain_log = log_path;
     }
+    pub fn set_transfer_key(&mut self, key_name: Domain) {
+        self.transfer_key = Some(key_name);
+    }
+    pub fn set_query_key(&mut self, key_name: Domain) {
+        self.query_key = Some(key_name);
+    }
+    pub fn set_ksk_path(&mut self, key_path: String) {
+        self.ksk_path = Some(key_path);
+    }
+    pub fn set_zsk_path(&mut self, key_path: String) {
+        self.zsk_path = Some(key_path);
+    }
 }
@@ -0,0 +1,192 @@
+use super::dns_domain_name::Domain;
+use super::domain_database_struct::DomainDatabase;
+use std::collections::HashMap;
+
+// Bases de dados das zonas numa arvore com um no por label, a partir da raiz. A zona que serve um
+// nome e a mais especifica no caminho das suas labels (closest enclosing zone), encontrada sem
+// percorrer nem copiar as outras zonas
+#[derive(Clone, Default)]
+pub struct ZoneTree {
+    root: ZoneNode,
+}
+
+#[derive(Clone, Default)]
+struct ZoneNode {
+    zone: Option<(Domain, DomainDatabase)>,
+    // Filhos indexados pela label em minusculas, porque os nomes nao distinguem maiusculas
+    children: HashMap<String, ZoneNode>,
+}
+
+impl ZoneTree {
+    pub fn new() -> Self {
+        ZoneTree::default()
+    }
+
+    // Labels do nome da direita para a esquerda, que e o caminho na arvore
+    fn path(name: &Domain) -> impl Iterator<Item = String> + '_ {
+        name.getparts()
+            .iter()
+            .rev()
+            .map(|label| label.to_ascii_lowercase())
+    }
+
+    fn node(&self, name: &Domain) -> Option<&ZoneNode> {
+        Self::path(name).try_fold(&self.root, |node, label| node.children.get(&label))
+    }
+
+    pub fn insert(&mut self, name: Domain, db: DomainDatabase) -> Option<DomainDatabase> {
+        let mut node = &mut self.root;
+        for label in Self::path(&name) {
+            node = node.children.entry(label).or_default();
+        }
+        node.zone.replace((name, db)).map(|(_, db)| db)
+    }
+
+    pub fn get(&self, name: &Domain) -> Option<&DomainDatabase> {
+        self.node(name)?.zone.as_ref().map(|(_, db)| db)
+    }
+
+    pub fn get_mut(&mut self, name: &Domain) -> Option<&mut DomainDatabase> {
+        let mut node = &mut self.root;
+        for label in Self::path(name) {
+            node = node.children.get_mut(&label)?;
+        }
+        node.zone.as_mut().map(|(_, db)| db)
+    }
+
+    pub fn contains_key(&self, name: &Domain) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Zona mais especifica que contem o nome, com o nome da zona
+    pub fn find_zone(&self, name: &Domain) -> Option<(&Domain, &DomainDatabase)> {
+        let mut node = &self.root;
+        let mut closest = node.zone.as_ref();
+        for label in Self::path(name) {
+            node = match node.children.get(&label) {
+                Some(child) => child,
+                None => break,
+            };
+            closest = node.zone.as_ref().or(closest);
+        }
+        closest.map(|(zone, db)| (zone, db))
+    }
+
+    // Zona mais especifica com autoridade sobre o nome. As entradas da cache e as delegacoes no
+    // caminho sao saltadas, subindo ate uma zona que responda pelo nome
+    pub fn find_authoritative_zone(&self, name: &Domain) -> Option<(&Domain, &DomainDatabase)> {
+        let mut node = &self.root;
+        let mut zones = vec![node.zone.as_ref()];
+        for label in Self::path(name) {
+            node = match node.children.get(&label) {
+                Some(child) => child,
+                None => break,
+            };
+            zones.push(node.zone.as_ref());
+        }
+        zones
+            .into_iter()
+            .rev()
+            .flatten()
+            .find(|(_, db)| db.is_authoritative_for(name))
+            .map(|(zone, db)| (zone, db))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Domain {
+        Domain::new(name.to_string())
+    }
+
+    fn zone(apex: &str) -> DomainDatabase {
+        let mut db = DomainDatabase::new();
+        db.soa_entries.domain_name = name(apex);
+        db
+    }
+
+    fn find(zones: &ZoneTree, queried: &str) -> Option<Domain> {
+        zones
+            .find_zone(&name(queried))
+            .map(|(zone, _)| zone.to_owned())
+    }
+
+    #[test]
+    fn test_find_zone() {
+        let mut zones = ZoneTree::new();
+        for apex in [
+            "reverse.",
+            "10.in-addr.reverse.",
+            "Example.COM.",
+            "sub.example.com.",
+        ] {
+            assert!(zones.insert(name(apex), zone(apex)).is_none());
+        }
+        assert_eq!(
+            find(&zones, "1.2.10.in-addr.reverse."),
+            Some(name("10.in-addr.reverse."))
+        );
+        assert_eq!(
+            find(&zones, "10.in-addr.reverse."),
+            Some(name("10.in-addr.reverse."))
+        );
+        assert_eq!(find(&zones, "in-addr.reverse."), Some(name("reverse.")));
+        assert_eq!(find(&zones, "11.in-addr.reverse."), Some(name("reverse.")));
+        assert_eq!(find(&zones, "WWW.example.com."), Some(name("example.com.")));
+        assert_eq!(
+            find(&zones, "host.Sub.example.com."),
+            Some(name("sub.example.com."))
+        );
+        assert_eq!(find(&zones, "com."), None);
+        assert_eq!(find(&zones, "example.org."), None);
+
+        // Com a zona raiz, todos os nomes tem uma zona
+        zones.insert(name("."), zone("."));
+        assert_eq!(find(&zones, "example.org."), Some(name(".")));
+        assert_eq!(find(&zones, "www.example.com."), Some(name("example.com.")));
+    }
+
+    #[test]
+    fn test_find_authoritative_zone() {
+        let mut zones = ZoneTree::new();
+        let mut parent = zone("example.com.");
+        parent.authority = true;
+        zones.insert(name("example.com."), parent);
+        // Entrada da cache, sem autoridade, mais especifica do que a zona
+        zones.insert(name("cache.example.com."), zone("cache.example.com."));
+
+        assert_eq!(
+            find(&zones, "www.cache.example.com."),
+            Some(name("cache.example.com."))
+        );
+        let found = zones
+            .find_authoritative_zone(&name("www.cache.example.com."))
+            .map(|(zone, _)| zone.to_owned());
+        assert_eq!(found, Some(name("example.com.")));
+        assert!(zones
+            .find_authoritative_zone(&name("example.org."))
+            .is_none());
+    }
+
+    #[test]
+    fn test_zone_map() {
+        let mut zones = ZoneTree::new();
+        zones.insert(name("example.com."), zone("example.com."));
+        assert!(zones.contains_key(&name("EXAMPLE.com.")));
+        assert!(!zones.contains_key(&name("com.")));
+        assert!(zones.get(&name("www.example.com.")).is_none());
+
+        zones.get_mut(&name("example.com.")).unwrap().authority = true;
+        assert!(zones.get(&name("example.com.")).unwrap().authority);
+        assert!(zones.get_mut(&name("org.")).is_none());
+
+        // Inserir de novo a mesma zona substitui a base de dados
+        let old = zones
+            .insert(name("Example.com."), zone("example.com."))
+            .unwrap();
+        assert!(old.authority);
+        assert!(!zones.get(&name("example.com.")).unwrap().authority);
+    }
+}
@@ -12,9 +12,16 @@ use log4rs::{
 };
 use my_dns::dns_structs::dns_domain_name::Domain;
 use my_dns::{
-    dns_components::sr::resolver,
+    dns_components::{
+        sr::{resolver_with, validating_resolver, KeyCache, ResolverOptions, Transport},
+        validator::TrustAnchor,
+    },
+    dns_make::{
+        dns_tls::{TlsConnector, DOT_PORT},
+        dns_tsig::TsigKey,
+    },
     dns_structs::dns_message::{
-        DNSMessage, DNSMessageData, DNSMessageHeaders, DNSQueryInfo, QueryType,
+        DNSMessage, DNSMessageData, DNSMessageHeaders, DNSQueryInfo, Flags, QueryType,
     },
 };
 use rand::random;
@@ -48,6 +55,19 @@ pub fn main() {
                 .long("server")
                 .num_args(1..)
                 .help("Server IP Address"),
+            Arg::new("tcp")
+                .action(ArgAction::SetTrue)
+                .long("tcp")
+                .help("Sends the query over TCP instead of UDP"),
+            Arg::new("tls_ca")
+                .long("tls-ca")
+                .help("Sends the query over TLS, trusting the certificates in this PEM file"),
+            Arg::new("tsig").long("tsig").help(
+                "Signs the query with TSIG (HMAC-SHA256), using a key given as name:base64-secret",
+            ),
+            Arg::new("dnssec").long("dnssec").help(
+                "Validates the answer with DNSSEC, trusting the DS/DNSKEY records in this file",
+            ),
             Arg::new("debug")
                 .action(ArgAction::SetTrue)
                 .short('b')
@@ -111,6 +131,14 @@ pub fn main() {
 
     let domain_name = arguments.get_one::<String>("domain").unwrap();
 
+    let domain = match Domain::parse(domain_name) {
+        Ok(domain) => domain,
+        Err(err) => {
+            error!("SP 127.0.0.1 invalid-domain-name {} {}", domain_name, err);
+            return;
+        }
+    };
+
     let query_type_string = arguments.get_one::<String>("query_types").unwrap();
 
     // Passar de string para a Enum QueryType
@@ -123,27 +151,33 @@ pub fn main() {
         }
     };
 
-    // O sistema de flags funciona em binario em que se soma o valor de todas as flags
-    // Q  => 1 0 0 = 4
-    // R  => 0 1 0 = 2
-    // A  => 0 0 1 = 1
-    // QR => 1 1 0 = 6
-    let flag: u8 = match arguments.get_flag("recursive") {
-        true => 6,
-        false => 4,
-    };
+    // Todas as perguntas levam a flag Q, e a flag R caso seja pedida uma resposta recursiva
+    let mut flag = Flags::QUERY;
+    if arguments.get_flag("recursive") {
+        if let Err(err) = flag.insert(Flags::RECURSIVE) {
+            error!("SP 127.0.0.1 invalid-flags {err}");
+            return;
+        }
+    }
 
     let server_ips_input: ValuesRef<String> = match arguments.get_many::<String>("server_ip") {
         Some(ips) => ips.to_owned(),
         None => panic!("No IP provided"),
     };
 
+    // Por TLS a porta por omissao e a do DNS sobre TLS
+    let tls_ca = arguments.get_one::<String>("tls_ca");
+    let default_port = match tls_ca {
+        Some(_) => DOT_PORT.to_string(),
+        None => "5353".to_string(),
+    };
+
     let mut server_ips_vec: Vec<SocketAddr> = Vec::new();
 
     for server_ip in server_ips_input.into_iter() {
         let addr_vec = server_ip.split(':').collect::<Vec<_>>();
         let new_ip_address = if addr_vec.len() == 1 {
-            addr_vec[0].to_string().add(":").add("5353")
+            addr_vec[0].to_string().add(":").add(&default_port)
         } else {
             server_ip.to_string()
         };
@@ -154,10 +188,49 @@ pub fn main() {
         server_ips_vec.push(server_ip_socket_addr)
     }
     // Construir a mensagem de DNS a ser enviada e dar serialize
-    let mut dns_message = query_builder(Domain::new(domain_name.to_string()), query_type, flag);
+    let mut dns_message = query_builder(domain, query_type, flag);
     info!("EV @ dns-msg-created");
 
-    let answer = resolver(&mut dns_message, server_ips_vec, true);
+    let transport = match tls_ca {
+        Some(ca_path) => match TlsConnector::from_ca_file(ca_path) {
+            Ok(connector) => Transport::Tls(connector),
+            Err(err) => {
+                error!("SP 127.0.0.1 invalid-tls-ca {}", err);
+                return;
+            }
+        },
+        None if arguments.get_flag("tcp") => Transport::Tcp,
+        None => Transport::Udp,
+    };
+    // A chave TSIG e dada como nome:segredo, com o segredo em base64
+    let tsig_key = match arguments.get_one::<String>("tsig") {
+        Some(tsig) => match parse_tsig_key(tsig) {
+            Ok(key) => Some(key),
+            Err(err) => {
+                error!("SP 127.0.0.1 invalid-tsig-key {} {}", tsig, err);
+                return;
+            }
+        },
+        None => None,
+    };
+    let options = ResolverOptions {
+        transport,
+        randomize_case: false,
+        tsig_key,
+        key_cache: KeyCache::new(),
+    };
+    let answer = match arguments.get_one::<String>("dnssec") {
+        Some(anchor_path) => match TrustAnchor::from_file(anchor_path) {
+            Ok(anchor) => {
+                validating_resolver(&mut dns_message, server_ips_vec, true, options, &anchor)
+            }
+            Err(err) => {
+                error!("SP 127.0.0.1 invalid-trust-anchor {}", err);
+                return;
+            }
+        },
+        None => resolver_with(&mut dns_message, server_ips_vec, true, options),
+    };
 
     let check_answer = match answer {
         Ok(acceptable) => {
@@ -167,14 +240,14 @@ pub fn main() {
         Err(err) => {
             error!("EV @ {}", err);
             error!("SP 127.0.0.1 dns-msg-receive-error");
-            panic!("Error receiving answer");
+            return;
         }
     };
 
     print_dns_message(check_answer);
 }
 
-pub fn query_builder(domain_name: Domain, query_type: QueryType, flag: u8) -> DNSMessage {
+pub fn query_builder(domain_name: Domain, query_type: QueryType, flag: Flags) -> DNSMessage {
     let dns_query_info = DNSQueryInfo {
         name: domain_name,
         type_of_value: query_type,
@@ -201,6 +274,14 @@ pub fn query_builder(domain_name: Domain, query_type: QueryType, flag: u8) -> DN
     return dns_message;
 }
 
+fn parse_tsig_key(tsig: &str) -> Result<TsigKey, &'static str> {
+    let (name, secret) = match tsig.split_once(':') {
+        Some(name_and_secret) => name_and_secret,
+        None => return Err("Missing TSIG secret"),
+    };
+    TsigKey::from_base64(Domain::parse(name)?, secret)
+}
+
 fn print_dns_message(message: DNSMessage) {
     println!("DNS Message:");
 
@@ -219,83 +300,43 @@ fn print_dns_message(message: DNSMessage) {
         None => 0, // provide a default value for number_of_extra_values
     };
 
+    let response_code: u8 = match message.header.response_code {
+        Some(code) => code.into(),
+        None => 0,
+    };
+
     println!(
         "{} {} {} {} {}, {}",
         message.header.message_id,
-        message.header.flags,
-        message.header.response_code.unwrap(),
+        message.header.flags.get_string(),
+        response_code,
         number_of_values,
         number_of_authorities,
         number_of_extra_values
     );
 
+    // Um nome internacionalizado e mostrado tambem na forma Unicode
+    let name = &message.data.query_info.name;
+    if name.to_unicode() != name.to_string() {
+        println!("\tQuery Name: {} ({})", name.to_unicode(), name.to_string());
+    }
+
     println!("\tResponse Values:");
     if let Some(response_values) = message.data.response_values {
         for value in response_values {
-            if value.priority.is_some() {
-                println!(
-                    "\t\t{} {} {} {} {}",
-                    value.domain_name.to_string(),
-                    value.type_of_value,
-                    value.value,
-                    value.ttl,
-                    value.priority.unwrap()
-                )
-            } else {
-                println!(
-                    "\t\t{} {} {} {}",
-                    value.domain_name.to_string(),
-                    value.type_of_value,
-                    value.value,
-                    value.ttl
-                )
-            };
+            println!("\t\t{}", value.get_string());
         }
     }
     println!("\tAuthorities Values:");
     if let Some(authorities_values) = message.data.authorities_values {
         for value in authorities_values {
-            if value.priority.is_some() {
-                println!(
-                    "\t\t{} {} {} {} {}",
-                    value.domain_name.to_string(),
-                    value.type_of_value,
-                    value.value,
-                    value.ttl,
-                    value.priority.unwrap()
-                )
-            } else {
-                println!(
-                    "\t\t{} {} {} {}",
-                    value.domain_name.to_string(),
-                    value.type_of_value,
-                    value.value,
-                    value.ttl
-                )
-            };
+            println!("\t\t{}", value.get_string());
         }
     }
     println!("\tExtra Values:");
     if let Some(extra_values) = message.data.extra_values {
         for value in extra_values {
-            if value.priority.is_some() {
-                println!(
-                    "\t\t{} {} {} {} {}",
-                    value.domain_name.to_string(),
-                    value.type_of_value,
-                    value.value,
-                    value.ttl,
-                    value.priority.unwrap()
-                )
-            } else {
-                println!(
-                    "\t\t{} {} {} {}",
-                    value.domain_name.to_string(),
-                    value.type_of_value,
-                    value.value,
-                    value.ttl
-                )
-            };
+            println!("\t\t{}", value.get_string());
         }
     }
 }
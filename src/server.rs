@@ -11,34 +11,63 @@ use log4rs::{
     filter::threshold::ThresholdFilter,
 };
 use my_dns::{
-    dns_components::{sp::db_sync_listener, sr::resolver, ss::db_sync},
+    dns_components::{
+        sp::{db_sync_listener, zone_signer},
+        sr::{
+            resolver_with, servfail_answer, validating_resolver, KeyCache, ResolverOptions,
+            Transport, MAX_CNAME_CHAIN,
+        },
+        ss::db_sync,
+        validator::TrustAnchor,
+    },
     dns_parse::domain_database_parse::parse_root_servers,
-    dns_structs::{dns_domain_name::Domain, server_config::DomainConfig},
+    dns_structs::{dns_domain_name::Domain, server_config::DomainConfig, zone_tree::ZoneTree},
 };
 use my_dns::{
-    dns_make::dns_send,
+    dns_make::{
+        dns_dnssec::{self, ZoneKey, SEP_FLAG, ZONE_KEY_FLAG},
+        dns_https::{self, HttpError},
+        dns_recv::{self, RecvError},
+        dns_send::{self, EDNS_UDP_PAYLOAD, UDP_PAYLOAD_LIMIT},
+        dns_tls::{self, TlsServerConfig, TlsServerStream},
+        dns_tsig::{self, TsigContext},
+        dns_wire::{self, PduFormat, WireError},
+    },
     dns_parse::{domain_database_parse, server_config_parse},
     dns_structs::{
-        dns_message::{DNSEntry, DNSMessage, QueryType},
+        dns_message::{DNSEntry, DNSMessage, Flags, QueryType, ResponseCode},
+        dns_resource_data::{Edns, RData},
         domain_database_struct::DomainDatabase,
         server_config::ServerConfig,
     },
 };
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fs::File,
-    io::Write,
-    net::{SocketAddr, UdpSocket},
+    io::{self, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
     ops::Add,
     os::linux::fs,
     path::Path,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Sender,
+        Arc, Condvar, Mutex,
+    },
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
 static DEFAULT_PORT: u16 = 5353;
 static DEFAULT_TIMEOUT: u16 = 20000;
 static LOG_PATTERN: &str = "[{d(%Y-%m-%d %H:%M:%S %Z)(utc)}] {m}{n}";
+// Tempo sem perguntas ao fim do qual uma ligacao TCP e fechada
+static TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+// Numero maximo de ligacoes TCP abertas ao mesmo tempo
+static MAX_TCP_CONNECTIONS: usize = 64;
+// Numero maximo de perguntas pendentes numa ligacao TCP
+static MAX_PIPELINED_QUERIES: usize = 16;
 
 pub fn main() {
     // Argumentos de input da CLI para definir quais e quantos servidores inicializar
@@ -109,7 +138,7 @@ pub fn main() {
             );
             config
         }
-        Err(_err) => panic!("Server config path not found!"),
+        Err(err) => panic!("Invalid server config: {err}"),
     };
 
     let all_log_path = config.get_all_log();
@@ -118,22 +147,69 @@ pub fn main() {
     start_server(config, port, supports_recursive, false);
 }
 
+// Enderecos em que o servidor ficou a escutar. Com a porta 0 na linha de comandos ou na config
+// so se sabem depois de abrir as sockets
+#[derive(Clone, Copy, Debug)]
+pub struct ListenAddrs {
+    // UDP e TCP, na mesma porta
+    pub dns: SocketAddr,
+    pub tls: Option<SocketAddr>,
+    pub http: Option<SocketAddr>,
+    pub https: Option<SocketAddr>,
+}
+
 pub fn start_server(config: ServerConfig, port: u16, supports_recursive: bool, once: bool) {
+    serve(config, port, supports_recursive, once, None)
+}
+
+// Como start_server, mas envia por `ready` os enderecos de escuta quando o servidor ja aceita
+// perguntas
+pub fn start_server_notify(
+    config: ServerConfig,
+    port: u16,
+    supports_recursive: bool,
+    once: bool,
+    ready: Sender<ListenAddrs>,
+) {
+    serve(config, port, supports_recursive, once, Some(ready))
+}
+
+fn serve(
+    config: ServerConfig,
+    port: u16,
+    supports_recursive: bool,
+    once: bool,
+    ready: Option<Sender<ListenAddrs>>,
+) {
     //Global variables
-    let mut database: HashMap<Domain, DomainDatabase>;
+    let mut database: ZoneTree;
     let domain_configs: HashMap<Domain, DomainConfig>;
 
-    database = HashMap::new();
+    database = ZoneTree::new();
 
     domain_configs = config.get_domain_configs();
 
     //Add SP's to DB
+    let mut signed_zones: Vec<(Domain, String, ZoneKey, ZoneKey)> = Vec::new();
     for (domain_name, domain_config) in domain_configs.clone().iter() {
         if let Some(db) = domain_config.get_domain_db() {
             match domain_database_parse::get(db.to_owned()) {
-                Ok(db_parsed) => {
+                Ok(mut db_parsed) => {
                     info!("EV @ db-file-read {}", db);
-                    database.insert(Domain::new(domain_name.to_string()), db_parsed);
+                    // Zonas com chaves DNSSEC sao assinadas antes de comecar a responder
+                    if let Some((ksk_path, zsk_path)) = domain_config.get_dnssec_key_paths() {
+                        match sign_loaded_zone(&mut db_parsed, &ksk_path, &zsk_path) {
+                            Ok((ksk, zsk)) => {
+                                info!("EV @ zone-signed {}", domain_name.to_string());
+                                signed_zones.push((domain_name.to_owned(), db, ksk, zsk));
+                            }
+                            Err(err) => {
+                                error!("SP @ {} {}", err, domain_name.to_string());
+                                return;
+                            }
+                        }
+                    }
+                    database.insert(domain_name.to_owned(), db_parsed);
                 }
                 Err(_err) => {
                     error!("SP @ db-file-read-fail {}", domain_name.to_string());
@@ -143,14 +219,29 @@ pub fn start_server(config: ServerConfig, port: u16, supports_recursive: bool, o
         }
     }
 
+    // Os servidores de topo e a ancora de confianca sao lidos antes de comecar a responder
+    let root_resolver = match RootResolver::load(&config) {
+        Ok(root_resolver) => Arc::new(root_resolver),
+        Err(err) => {
+            error!("SP @ {}", err);
+            return;
+        }
+    };
+
+    let mut handle_vec: Vec<JoinHandle<()>> = Vec::new();
+    let mutable_db: Arc<Mutex<ZoneTree>> = Arc::new(Mutex::new(database));
+
     //START SP LISTENER
     let config_clone = config.clone();
-    let db_clone = database.clone();
+    let db_clone = Arc::clone(&mutable_db);
     debug!("EV @ initalizing-db-sync-listener");
     thread::spawn(move || db_sync_listener(db_clone, config_clone));
 
-    let mut handle_vec: Vec<JoinHandle<()>> = Vec::new();
-    let mutable_db: Arc<Mutex<HashMap<Domain, DomainDatabase>>> = Arc::new(Mutex::new(database));
+    //START ZONE SIGNERS
+    for (domain_name, db_path, ksk, zsk) in signed_zones {
+        let db_clone = Arc::clone(&mutable_db);
+        thread::spawn(move || zone_signer(domain_name, db_path, ksk, zsk, db_clone));
+    }
 
     //Add SS to DB
     for (domain_name, domain_config) in domain_configs.iter() {
@@ -158,22 +249,126 @@ pub fn start_server(config: ServerConfig, port: u16, supports_recursive: bool, o
             let mutable_db_copy = Arc::clone(&mutable_db);
             debug!("EV @ initializing-ss-thread {}", domain_name.to_string());
             let dn = domain_name.clone();
-            let handler = thread::spawn(move || db_sync(dn, sp_addr, mutable_db_copy));
+            let transfer_key = config.get_transfer_key(domain_name);
+            if transfer_key.is_none() && config.get_transfer_key_name(domain_name).is_some() {
+                error!("SP @ tsig-key-not-found {}", domain_name.to_string());
+            }
+            let handler =
+                thread::spawn(move || db_sync(dn, sp_addr, mutable_db_copy, transfer_key));
             handle_vec.push(handler);
         }
     }
-    let socket = match UdpSocket::bind(format!("0.0.0.0:{port}",)) {
-        Ok(socket) => socket,
+    // A socket UDP e o listener TCP das perguntas sao abertos na mesma porta
+    let (socket, tcp_listener) = match bind_dns_sockets(port) {
+        Ok(sockets) => sockets,
+        Err(_) => {
+            error!("SP @ udp-listen-socket-fail");
+            panic!("Could not bind socket")
+        }
+    };
+    let dns_addr = match socket.local_addr() {
+        Ok(addr) => addr,
         Err(_) => {
             error!("SP @ udp-listen-socket-fail");
             panic!("Could not bind socket")
         }
     };
 
-    let mut buf = [0; 1000];
+    //START TCP QUERY LISTENER
+    let config_clone = config.clone();
+    let db_clone = mutable_db.clone();
+    let root_resolver_clone = root_resolver.clone();
+    serve_listener(tcp_listener, "tcp", move |stream| {
+        tcp_client_handler(
+            stream,
+            &config_clone,
+            supports_recursive,
+            &db_clone,
+            &root_resolver_clone,
+        )
+    });
+
+    //START DNS OVER TLS AND DNS OVER HTTPS LISTENERS
+    let tls_config = match config.get_tls_cert_and_key() {
+        Some((cert_path, key_path)) => match dns_tls::load_server_config(&cert_path, &key_path) {
+            Ok(tls_config) => Some(tls_config),
+            Err(err) => {
+                error!("SP @ tls-config-fail {}", err);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut tls_addr = None;
+    if let Some(ref tls_config) = tls_config {
+        let tls_config_clone = tls_config.clone();
+        let config_clone = config.clone();
+        let db_clone = mutable_db.clone();
+        let root_resolver_clone = root_resolver.clone();
+        tls_addr = start_listener(config.get_tls_port(), "tls", move |stream| {
+            tls_client_handler(
+                stream,
+                &tls_config_clone,
+                &config_clone,
+                supports_recursive,
+                &db_clone,
+                &root_resolver_clone,
+            )
+        });
+    }
+    let mut http_addr = None;
+    if let Some(http_port) = config.get_http_port() {
+        let config_clone = config.clone();
+        let db_clone = mutable_db.clone();
+        let root_resolver_clone = root_resolver.clone();
+        http_addr = start_listener(http_port, "http", move |stream| {
+            doh_client_handler(
+                stream,
+                None,
+                &config_clone,
+                supports_recursive,
+                &db_clone,
+                &root_resolver_clone,
+            )
+        });
+    }
+    let mut https_addr = None;
+    if let Some(https_port) = config.get_https_port() {
+        match tls_config {
+            Some(tls_config) => {
+                let config_clone = config.clone();
+                let db_clone = mutable_db.clone();
+                let root_resolver_clone = root_resolver.clone();
+                https_addr = start_listener(https_port, "https", move |stream| {
+                    doh_client_handler(
+                        stream,
+                        Some(&tls_config),
+                        &config_clone,
+                        supports_recursive,
+                        &db_clone,
+                        &root_resolver_clone,
+                    )
+                });
+            }
+            None => error!("SP @ https-without-tls-certificate"),
+        }
+    }
+
+    // Com as sockets abertas as perguntas ficam em espera ate serem lidas
+    if let Some(ready) = ready {
+        let _ = ready.send(ListenAddrs {
+            dns: dns_addr,
+            tls: tls_addr,
+            http: http_addr,
+            https: https_addr,
+        });
+    }
+
+    // Com EDNS(0) um datagrama pode ter ate 65535 bytes
+    let mut buf = vec![0; 65535];
 
     loop {
-        let (_, src_addr) = match socket.recv_from(&mut buf) {
+        let (size, src_addr) = match socket.recv_from(&mut buf) {
             Ok(size_and_addr) => size_and_addr,
             Err(_) => {
                 error!("SP @ udp-socket-receive-fail");
@@ -181,14 +376,26 @@ pub fn start_server(config: ServerConfig, port: u16, supports_recursive: bool, o
             }
         };
         let new_db = mutable_db.clone();
+        let root_resolver_clone = root_resolver.clone();
         let config_clone = config.clone();
+        // As respostas saem pela socket de escuta para que o porto de origem seja o esperado
+        let reply_socket = match socket.try_clone() {
+            Ok(reply_socket) => reply_socket,
+            Err(_) => {
+                error!("SP @ udp-socket-clone-fail");
+                continue;
+            }
+        };
+        let pdu = buf[..size].to_vec();
         let _handler = thread::spawn(move || {
             client_handler(
-                buf.to_vec(),
+                pdu,
+                reply_socket,
                 src_addr,
                 config_clone,
                 supports_recursive,
                 new_db,
+                root_resolver_clone,
             )
         });
         if once {
@@ -197,67 +404,318 @@ pub fn start_server(config: ServerConfig, port: u16, supports_recursive: bool, o
     }
 }
 
+// Carrega (ou cria) a KSK e a ZSK da zona e assina-a
+fn sign_loaded_zone(
+    db: &mut DomainDatabase,
+    ksk_path: &str,
+    zsk_path: &str,
+) -> Result<(ZoneKey, ZoneKey), &'static str> {
+    let ksk = ZoneKey::load_or_generate(ksk_path, ZONE_KEY_FLAG | SEP_FLAG)?;
+    let zsk = ZoneKey::load_or_generate(zsk_path, ZONE_KEY_FLAG)?;
+    dns_dnssec::sign_zone(db, &ksk, &zsk, dns_dnssec::now())?;
+    // DS da KSK no formato da base de dados, para a zona pai ou para uma ancora de confianca
+    let soa = db.get_soa_records();
+    if let Ok(ds) = dns_dnssec::ds_for(&soa.domain_name, &ksk.get_dnskey()) {
+        info!(
+            "EV @ zone-ds {} DS {} {}",
+            soa.domain_name.to_string(),
+            ds.get_value(),
+            soa.ttl
+        );
+    }
+    Ok((ksk, zsk))
+}
+
+// O que e preciso para resolver a partir da raiz o que nao esta na base de dados, preparado uma
+// vez no arranque. As opcoes do resolver guardam as chaves DNSSEC validadas entre perguntas
+struct RootResolver {
+    root_servers: Vec<SocketAddr>,
+    trust_anchor: Option<TrustAnchor>,
+    options: ResolverOptions,
+}
+
+impl RootResolver {
+    fn load(config: &ServerConfig) -> Result<Self, String> {
+        let root_servers = match parse_root_servers(config.get_st_db()) {
+            Ok(root_servers) => root_servers,
+            Err(err) => {
+                return Err(format!(
+                    "root-servers-read-fail {} {}",
+                    config.get_st_db(),
+                    err
+                ))
+            }
+        };
+        let trust_anchor = match config.get_trust_anchor() {
+            Some(path) => match TrustAnchor::from_file(&path) {
+                Ok(anchor) => Some(anchor),
+                Err(err) => return Err(format!("trust-anchor-read-fail {} {}", path, err)),
+            },
+            None => None,
+        };
+        Ok(RootResolver {
+            root_servers,
+            trust_anchor,
+            options: ResolverOptions {
+                transport: Transport::Udp,
+                randomize_case: config.get_randomize_case(),
+                tsig_key: None,
+                key_cache: KeyCache::new(),
+            },
+        })
+    }
+}
+
+// Resposta a uma pergunta, ainda por enviar pelo transporte em que a pergunta chegou
+enum Answer {
+    // Mensagem, formato, tamanho maximo da resposta por UDP e o TSIG da pergunta, se vinha assinada
+    Message(DNSMessage, PduFormat, usize, Option<Box<TsigContext>>),
+    // Resposta RFC 1035 ja codificada, para pacotes em que nem a pergunta foi possivel ler
+    Raw(Vec<u8>),
+}
+
 fn client_handler(
     buf: Vec<u8>,
+    socket: UdpSocket,
     src_addr: SocketAddr,
     config: ServerConfig,
     supports_recursive: bool,
-    database_mutex: Arc<Mutex<HashMap<Domain, DomainDatabase>>>,
+    database_mutex: Arc<Mutex<ZoneTree>>,
+    root_resolver: Arc<RootResolver>,
 ) {
-    let mut dns_message: DNSMessage = match bincode::deserialize::<DNSMessage>(&buf) {
-        Ok(message) => message,
+    match handle_query(
+        &buf,
+        src_addr,
+        &config,
+        supports_recursive,
+        &database_mutex,
+        &root_resolver,
+    ) {
+        Some(Answer::Message(dns_message, format, payload_limit, tsig)) => send_answer(
+            dns_message,
+            &socket,
+            src_addr,
+            format,
+            payload_limit,
+            tsig.as_deref(),
+        ),
+        Some(Answer::Raw(pdu)) => match socket.send_to(&pdu, src_addr) {
+            Ok(_num_bytes) => (),
+            Err(_err) => debug!("EV @ send-message-fail"),
+        },
+        None => (),
+    }
+}
+
+// Procura a resposta a uma pergunta, independentemente do transporte (UDP ou TCP)
+fn handle_query(
+    buf: &[u8],
+    src_addr: SocketAddr,
+    config: &ServerConfig,
+    supports_recursive: bool,
+    database_mutex: &Arc<Mutex<ZoneTree>>,
+    root_resolver: &Arc<RootResolver>,
+) -> Option<Answer> {
+    // O TSIG e verificado antes de descodificar a pergunta, porque o MAC cobre os bytes recebidos
+    let (buf, tsig) =
+        match dns_tsig::verify_message(buf, &config.get_tsig_keys(), None, dns_tsig::now()) {
+            Ok(Some((message, context))) => (message, Some(Box::new(context))),
+            Ok(None) => (buf.to_vec(), None),
+            // O TSIG esta mal formado ou nao e o ultimo record, a pergunta nao e aceite
+            Err(WireError::BadTsig) => {
+                debug!("EZ {} SP invalid-request", src_addr);
+                return dns_wire::query_header_id(buf)
+                    .map(|message_id| Answer::Raw(dns_wire::encode_format_error(message_id)));
+            }
+            // Nao e uma mensagem RFC 1035 valida (ou vem em bincode), o que se ve a descodificar
+            Err(_) => (buf.to_vec(), None),
+        };
+    let buf = buf.as_slice();
+
+    // O formato da pergunta (RFC 1035 ou bincode) define o formato da resposta
+    let (mut dns_message, format) = match dns_recv::decode_pdu(buf) {
+        Ok(message_and_format) => message_and_format,
+        Err(RecvError::WireError(err)) => {
+            error!("ER pdu-decode-fail {} {:?}", src_addr.ip(), err);
+            return dns_wire::query_header_id(buf)
+                .map(|message_id| Answer::Raw(dns_wire::encode_format_error(message_id)));
+        }
         Err(_) => {
             error!("ER pdu-deserialize-fail {}", src_addr.ip());
             let mut response = DNSMessage::new();
-            response.header.response_code = Some(3);
-            // enviar dns message com codigo 3 !!!!!!!!
-            send_answer(response, src_addr);
-            return;
+            response.header.response_code = Some(ResponseCode::Malformed);
+            return Some(Answer::Message(
+                response,
+                PduFormat::Legacy,
+                UDP_PAYLOAD_LIMIT,
+                None,
+            ));
         }
     };
 
-    let queried_timestamp = format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S %Z"));
-    let queried_message_string = dns_message.get_string();
-    let queried_domain: Domain = dns_message.data.query_info.name.to_owned();
+    // O OPT do cliente define o tamanho da resposta por UDP e nao entra na procura
+    let client_edns = dns_message.take_edns();
+    let payload_limit = match client_edns {
+        Some(ref edns) => {
+            (edns.udp_payload_size as usize).clamp(UDP_PAYLOAD_LIMIT, EDNS_UDP_PAYLOAD as usize)
+        }
+        None => UDP_PAYLOAD_LIMIT,
+    };
+    let mut server_edns = Edns::new(EDNS_UDP_PAYLOAD);
+    if let Some(ref edns) = client_edns {
+        server_edns.dnssec_ok = edns.dnssec_ok;
+        // So a versao 0 do EDNS e suportada (RFC 6891 6.1.3)
+        if edns.version != 0 {
+            error!(
+                "ER unsupported-edns-version {} {}",
+                src_addr.ip(),
+                edns.version
+            );
+            dns_message.header.flags = Flags::empty();
+            dns_message.header.response_code = Some(ResponseCode::BadVersion);
+            dns_message.set_edns(server_edns);
+            return Some(Answer::Message(dns_message, format, payload_limit, tsig));
+        }
+    }
 
+    // Uma pergunta com um TSIG invalido recebe NOTAUTH, com o erro no TSIG da resposta
+    if let Some(err) = tsig.as_ref().and_then(|context| context.get_error()) {
+        error!(
+            "EZ {} SP {} {}",
+            src_addr.ip(),
+            err.event(),
+            dns_message.data.query_info.name.to_string()
+        );
+        dns_message.header.flags = Flags::empty();
+        dns_message.header.response_code = Some(ResponseCode::NotAuth);
+        if client_edns.is_some() {
+            dns_message.set_edns(server_edns);
+        }
+        return Some(Answer::Message(dns_message, format, payload_limit, tsig));
+    }
+    // Dominios com chave de perguntas so respondem a perguntas assinadas com essa chave
+    if let Some(key_name) = config.get_query_key_name(&dns_message.data.query_info.name) {
+        let signed_with_key = tsig
+            .as_ref()
+            .and_then(|context| context.verified_key())
+            .is_some_and(|key| key.is_named(&key_name));
+        if !signed_with_key {
+            error!(
+                "EZ {} SP tsig-required {}",
+                src_addr.ip(),
+                dns_message.data.query_info.name.to_string()
+            );
+            dns_message.header.flags = Flags::empty();
+            dns_message.header.response_code = Some(ResponseCode::Refused);
+            if client_edns.is_some() {
+                dns_message.set_edns(server_edns);
+            }
+            return Some(Answer::Message(dns_message, format, payload_limit, tsig));
+        }
+    }
+
+    let dnssec_ok = client_edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+    let mut dns_response = answer_query(
+        dns_message,
+        src_addr,
+        config,
+        supports_recursive,
+        dnssec_ok,
+        database_mutex,
+        root_resolver,
+    );
+    if client_edns.is_some() {
+        dns_response.set_edns(server_edns);
+    }
+    Some(Answer::Message(dns_response, format, payload_limit, tsig))
+}
+
+// Resultado da procura na base de dados: a resposta ja esta na mensagem ou tem de ser pedida a
+// outros servidores, depois de libertar o lock da base de dados
+enum Lookup {
+    Answered,
+    Referral(Vec<SocketAddr>),
+    FromRoot,
+}
+
+// Procura a resposta a uma pergunta ja descodificada, na base de dados ou pelo resolver.
+// Com o bit DO, as respostas autoritativas de zonas assinadas levam os RRSIG e NSEC. O resolver
+// corre sem o lock da base de dados e, se falhar, a resposta e um SERVFAIL
+fn answer_query(
+    mut dns_message: DNSMessage,
+    src_addr: SocketAddr,
+    config: &ServerConfig,
+    supports_recursive: bool,
+    dnssec_ok: bool,
+    database_mutex: &Arc<Mutex<ZoneTree>>,
+    root_resolver: &RootResolver,
+) -> DNSMessage {
     info!("QR {} {}", src_addr.ip(), dns_message.get_string());
 
-    //Get list of root servers
-    let root_servers_path: String = config.get_st_db();
-    let root_servers: Vec<SocketAddr> = match parse_root_servers(root_servers_path) {
-        Ok(roots) => roots,
-        Err(err) => panic!("{err}"),
+    let lookup = lookup_query(
+        &mut dns_message,
+        src_addr,
+        config,
+        dnssec_ok,
+        &database_mutex.lock().unwrap(),
+    );
+    let dns_response = match lookup {
+        Lookup::Answered => return dns_message,
+        Lookup::Referral(ip_vec) => resolver_with(
+            &mut dns_message,
+            ip_vec,
+            supports_recursive,
+            root_resolver.options.to_owned(),
+        ),
+        Lookup::FromRoot => resolve_from_root(&mut dns_message, root_resolver, supports_recursive),
+    };
+    let dns_response = match dns_response {
+        Ok(message) => message,
+        Err(err) => return servfail_answer(&dns_message, err),
     };
 
-    // Acquire a lock on the database
+    // A resposta fica guardada na cache
     let mut database_map = database_mutex.lock().unwrap();
+    match database_map.get_mut(&dns_response.data.query_info.name) {
+        Some(db) => db.add_dns_message(dns_response.clone()),
+        None => {
+            let mut db = DomainDatabase::new();
+            db.add_dns_message(dns_response.clone());
+            database_map.insert(dns_response.data.query_info.name.clone(), db);
+        }
+    };
+    dns_response
+}
 
-    //Check if there is any parent domain of the queried domain on our database
-    let is_parent_cached = database_map
-        .iter()
-        .any(|(dn, _)| queried_domain.is_subdomain_of(dn));
+// Responde a pergunta com as zonas da base de dados. Se a resposta tiver de vir de outros
+// servidores, devolve a quem perguntar
+fn lookup_query(
+    dns_message: &mut DNSMessage,
+    src_addr: SocketAddr,
+    config: &ServerConfig,
+    dnssec_ok: bool,
+    database_map: &ZoneTree,
+) -> Lookup {
+    let queried_timestamp = format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S %Z"));
+    let queried_message_string = dns_message.get_string();
+    let queried_domain: Domain = dns_message.data.query_info.name.to_owned();
 
-    if is_parent_cached {
+    //Find the database for the most specific parent domain of the queried domain, if any
+    if let Some((parent_domain_name, parent_db)) = database_map.find_zone(&queried_domain) {
         //Parent Domain is in our database
         debug!(
             "EV @ parent-domain-is-cached {}",
             queried_domain.to_string()
         );
-        //Find the database for the highest level parent domain
-        let db_clone = database_map.clone();
-        let (parent_domain_name, parent_db) = db_clone
-            .iter()
-            .clone()
-            .filter(|(dn, _domain_db)| queried_domain.is_subdomain_of(dn.to_owned()))
-            .max_by(|(domain_name1, _domain_db1), (domain_name2, _domain_db2)| {
-                domain_name1.to_string().cmp(&domain_name2.to_string())
-            })
-            .unwrap();
         // Get the type of query being made
         let query_type = dns_message.data.query_info.type_of_value.clone();
 
         // Check if we have an answer for the queried domain in our parent domain's database
-        let response_vec = parent_db.get_domain_query(query_type, queried_domain.to_owned());
+        // If the name is an alias, answer with the CNAME chain followed inside our zones
+        let response_vec = parent_db
+            .get_domain_query(query_type, &queried_domain)
+            .map(Cow::into_owned)
+            .or_else(|| chase_cname(database_map, parent_db, query_type, &queried_domain));
 
         let parent_db_has_answer = response_vec.is_some();
 
@@ -282,7 +740,7 @@ fn client_handler(
                 None => None,
             };
             //Set flags and response code
-            dns_message.header.response_code = Some(0);
+            dns_message.header.response_code = Some(ResponseCode::NoError);
         } else {
             debug!(
                 "EV @ parent-db-doesnt-have-answer {}",
@@ -292,7 +750,7 @@ fn client_handler(
 
             //Check if any of the parents domain subdomains can be/know the authority of the queried domain
             //The returned value is the lowest possible parent domain. It can be ourselves.
-            let queried_domain_ns = parent_db.get_ns_of(queried_domain.to_owned());
+            let queried_domain_ns = parent_db.get_ns_of(&queried_domain).map(<[_]>::to_vec);
 
             //Check if the value returned is ourselves
             //If it is ourselves, then the query values doesn't exist.
@@ -315,47 +773,45 @@ fn client_handler(
             if lower_authority_exists {
                 debug!("EV @ lower-authority-exists {}", queried_domain.to_string());
                 //This means that we don't know if the entry exists on some other authority lower
-                //than us. In this case, we set the response_code to 1.
+                //than us. In this case, the response is non authoritative.
 
-                dns_message.header.response_code = Some(1);
+                dns_message.header.response_code = Some(ResponseCode::NonAuthoritative);
                 //Check if the query received is recursive and if so get the answer with the SR;
                 //Call SR with serverlist IP being the auth values.
-                if dns_message.header.flags == 6 {
+                let flags = dns_message.header.flags;
+                if flags.contains(Flags::QUERY) && flags.contains(Flags::RECURSIVE) {
                     //Recursive
                     //Call SR
                     match queried_domain_ns {
                         Some(ns_vec) => {
-                            let ip_vec = match DNSMessage::get_authorities_ip(
-                                &dns_message,
-                                parent_db.get_a_records(),
+                            // Enderecos (glue) so dos servidores da delegacao
+                            let glue: Vec<DNSEntry> = ns_vec
+                                .iter()
+                                .filter_map(|entry| entry.data.get_target_domain())
+                                .flat_map(|target| {
+                                    [QueryType::A, QueryType::AAAA].into_iter().flat_map(
+                                        |query_type| {
+                                            parent_db
+                                                .get_rrset(target, query_type)
+                                                .unwrap_or_default()
+                                        },
+                                    )
+                                })
+                                .cloned()
+                                .collect();
+                            return match DNSMessage::get_authorities_ip(
+                                dns_message,
+                                Some(glue),
                                 queried_domain,
                                 ns_vec,
                             ) {
-                                Some(vec) => vec,
+                                Some(ip_vec) => Lookup::Referral(ip_vec),
                                 None => {
-                                    panic!("No NS found for the queried domain, cannot get answer")
+                                    *dns_message =
+                                        servfail_answer(dns_message, "no-ns-address-found");
+                                    Lookup::Answered
                                 }
                             };
-                            let dns_response =
-                                match resolver(&mut dns_message, ip_vec, supports_recursive) {
-                                    Ok(message) => message,
-                                    Err(err) => panic!("{err}"),
-                                };
-                            match database_map.contains_key(&dns_response.data.query_info.name) {
-                                true => database_map
-                                    .get_mut(&dns_response.data.query_info.name)
-                                    .unwrap()
-                                    .add_dns_message(dns_response.clone()),
-                                false => {
-                                    let mut db = DomainDatabase::new();
-                                    db.add_dns_message(dns_response.clone());
-                                    database_map
-                                        .insert(dns_response.data.query_info.name.clone(), db);
-                                }
-                            };
-                            send_answer(dns_response, src_addr);
-                            return;
-                            //Return
                         }
                         _ => (),
                     };
@@ -369,42 +825,36 @@ fn client_handler(
                 //our cache, that means that it doesn't exist. We know that because we are the
                 //authority.
                 if am_parent_authority {
-                    dns_message.header.response_code = Some(2);
-                } else {
-                    let dns_response =
-                        match resolver(&mut dns_message, root_servers, supports_recursive) {
-                            Ok(message) => message,
-                            Err(err) => panic!("{err}"),
-                        };
-                    match database_map.contains_key(&dns_response.data.query_info.name) {
-                        true => database_map
-                            .get_mut(&dns_response.data.query_info.name)
-                            .unwrap()
-                            .add_dns_message(dns_response.clone()),
-                        false => {
-                            let mut db = DomainDatabase::new();
-                            db.add_dns_message(dns_response.clone());
-                            database_map.insert(dns_response.data.query_info.name.clone(), db);
-                        }
+                    //If the name exists with other types it's a NODATA answer, otherwise NXDOMAIN.
+                    //A wildcard that matches the name also makes it exist.
+                    //Both carry the zone's SOA in the authority section for negative caching.
+                    let name_exists = parent_db.has_domain(&queried_domain)
+                        || parent_db.get_wildcard_for(&queried_domain).is_some();
+                    dns_message.header.response_code = if name_exists {
+                        Some(ResponseCode::NoError)
+                    } else {
+                        Some(ResponseCode::NXDomain)
                     };
-                    send_answer(dns_response, src_addr);
-                    return;
+                    dns_message.data.authorities_values =
+                        Some(vec![parent_db.get_soa_records().get_negative_entry()]);
+                    dns_message.header.number_of_authorities = Some(1);
+                } else {
+                    return Lookup::FromRoot;
                 }
             }
         }
 
         // If we are the authority, set the authority flag on the response message
         // Otherwise, clear the authority flag
-        dns_message.header.flags = if am_parent_authority { 1 } else { 0 };
+        dns_message.header.flags = if am_parent_authority {
+            Flags::AUTHORITATIVE
+        } else {
+            Flags::empty()
+        };
 
         //Translate all values to IPs and add it to extra values
 
-        //Get all A records
         let mut extra_values = Vec::new();
-        let a_records = match parent_db.get_a_records() {
-            Some(records) => records,
-            None => panic!("No A records found, cannot get IP of an NS entry"),
-        };
 
         //Get all response values
         let response_vals = match dns_message.data.response_values {
@@ -422,7 +872,7 @@ fn client_handler(
             let mut auth_copy = auth_vals.to_owned();
             let mut no_a_records = response_vals
                 .into_iter()
-                .filter(|entry| entry.type_of_value != "A")
+                .filter(|entry| !matches!(entry.data, RData::A(_) | RData::AAAA(_)))
                 .to_owned()
                 .collect::<Vec<DNSEntry>>();
             no_a_records.append(&mut auth_copy);
@@ -430,20 +880,13 @@ fn client_handler(
         };
 
         //Translate all values
-        for entry in to_translate {
-            let a_record: DNSEntry;
-            if let Some(record) = a_records
-                .iter()
-                .find(|a_entry| a_entry.domain_name == Domain::new(entry.value.to_string()))
-            {
-                a_record = record.to_owned();
-                extra_values.push(DNSEntry {
-                    domain_name: a_record.domain_name,
-                    type_of_value: a_record.type_of_value,
-                    value: a_record.value,
-                    ttl: a_record.ttl,
-                    priority: a_record.priority,
-                })
+        for target in to_translate
+            .iter()
+            .filter_map(|entry| entry.data.get_target_domain())
+        {
+            for query_type in [QueryType::A, QueryType::AAAA] {
+                let records = parent_db.get_rrset(target, query_type).unwrap_or_default();
+                extra_values.extend(records.iter().cloned());
             }
         }
         //Add translated values to extra_values field in response message
@@ -453,6 +896,10 @@ fn client_handler(
             Err(_err) => None,
         };
 
+        if am_parent_authority && dnssec_ok && parent_db.is_signed() {
+            dns_dnssec::add_dnssec_records(dns_message, parent_db);
+        }
+
         if am_parent_authority {
             if let Some(domain_config) = config.get_domain_configs().get(parent_domain_name) {
                 let path = &domain_config.get_domain_log().to_string();
@@ -478,50 +925,480 @@ fn client_handler(
         }
     } else {
         //Answer is not cached
-        let dns_response = match resolver(&mut dns_message, root_servers, supports_recursive) {
-            Ok(message) => message,
-            Err(err) => panic!("{err}"),
+        return Lookup::FromRoot;
+    };
+
+    Lookup::Answered
+}
+
+// Segue a cadeia de CNAMEs a partir do nome pedido dentro das zonas de que somos autoridade, ate
+// encontrar o tipo pedido, sair das nossas zonas ou nao haver mais CNAMEs. O resto da cadeia fica
+// para o resolver. Numa zona assinada a cadeia nao sai da zona, para todos os records levarem RRSIG
+fn chase_cname(
+    databases: &ZoneTree,
+    zone_db: &DomainDatabase,
+    query_type: QueryType,
+    queried_domain: &Domain,
+) -> Option<Vec<DNSEntry>> {
+    if query_type == QueryType::CNAME || !zone_db.is_authoritative_for(queried_domain) {
+        return None;
+    }
+    let mut answers = zone_db
+        .get_domain_query(QueryType::CNAME, queried_domain)?
+        .into_owned();
+    let mut visited = vec![queried_domain.to_owned()];
+    while let Some(RData::CNAME(target)) = answers.last().map(|entry| entry.data.to_owned()) {
+        if visited.contains(&target) {
+            error!("ER cname-loop {}", queried_domain.to_string());
+            break;
+        }
+        if visited.len() > MAX_CNAME_CHAIN {
+            error!("ER cname-chain-too-long {}", queried_domain.to_string());
+            break;
+        }
+        let target_db = if zone_db.is_signed() {
+            Some(zone_db)
+        } else {
+            databases.find_authoritative_zone(&target).map(|(_, db)| db)
         };
-        match database_map.contains_key(&dns_response.data.query_info.name) {
-            true => database_map
-                .get_mut(&dns_response.data.query_info.name)
-                .unwrap()
-                .add_dns_message(dns_response.clone()),
-            false => {
-                let mut db = DomainDatabase::new();
-                db.add_dns_message(dns_response.clone());
-                database_map.insert(dns_response.data.query_info.name.clone(), db);
-            }
+        let target_db = match target_db {
+            Some(db) if db.is_authoritative_for(&target) => db,
+            _ => break,
         };
-        dns_message = dns_response;
+        debug!("EV @ following-cname {}", target.to_string());
+        visited.push(target.to_owned());
+        match target_db.get_domain_query(query_type, &target) {
+            Some(records) => answers.extend(records.iter().cloned()),
+            None => match target_db.get_domain_query(QueryType::CNAME, &target) {
+                Some(records) => answers.extend(records.iter().cloned()),
+                None => break,
+            },
+        }
+    }
+    Some(answers)
+}
+
+// Com uma ancora de confianca, o que e resolvido a partir da raiz e validado com DNSSEC
+fn resolve_from_root(
+    dns_message: &mut DNSMessage,
+    root_resolver: &RootResolver,
+    supports_recursive: bool,
+) -> Result<DNSMessage, &'static str> {
+    let root_servers = root_resolver.root_servers.to_owned();
+    let options = root_resolver.options.to_owned();
+    match &root_resolver.trust_anchor {
+        Some(anchor) => validating_resolver(
+            dns_message,
+            root_servers,
+            supports_recursive,
+            options,
+            anchor,
+        ),
+        None => resolver_with(dns_message, root_servers, supports_recursive, options),
+    }
+}
+
+fn send_answer(
+    dns_message: DNSMessage,
+    send_socket: &UdpSocket,
+    destination: SocketAddr,
+    format: PduFormat,
+    payload_limit: usize,
+    tsig: Option<&TsigContext>,
+) {
+    // O TSIG conta para o tamanho da resposta, por isso so se trunca depois de assinar
+    let answer = match encode_answer(&dns_message, format, tsig) {
+        Ok(answer) if answer.len() > payload_limit => {
+            encode_answer(&dns_message.truncated(), format, tsig)
+        }
+        answer => answer,
     };
+    match answer.and_then(|answer| send_socket.send_to(&answer, destination)) {
+        Ok(_num_bytes) => info!("RP {} {}", destination, dns_message.get_string()),
+        Err(_err) => debug!("EV @ send-message-fail"),
+    }
+}
 
-    send_answer(dns_message, src_addr);
-    return;
+// Codifica a resposta e, se a pergunta vinha assinada, acrescenta-lhe o TSIG
+fn encode_answer(
+    dns_message: &DNSMessage,
+    format: PduFormat,
+    tsig: Option<&TsigContext>,
+) -> Result<Vec<u8>, io::Error> {
+    let mut answer = dns_send::encode_pdu(dns_message, format)?;
+    if let Some(context) = tsig {
+        if let Err(err) = context.sign_response(&mut answer, dns_tsig::now()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?}", err),
+            ));
+        }
+    }
+    Ok(answer)
 }
 
-fn send_answer(dns_message: DNSMessage, destination: SocketAddr) {
-    let addr = destination.ip();
-    let port = destination.port();
-    let send_socket = match UdpSocket::bind("0.0.0.0:0") {
-        Ok(socket) => socket,
+// Escuta numa porta TCP (DNS sobre TCP, TLS ou HTTPS) e trata cada ligacao na sua thread
+// Socket UDP e listener TCP das perguntas na mesma porta. Com a porta 0 o sistema escolhe uma
+// porta livre em UDP, e a escolha e repetida enquanto essa porta estiver ocupada em TCP. Com uma
+// porta fixa ocupada em TCP o servidor responde so por UDP
+fn bind_dns_sockets(port: u16) -> Result<(UdpSocket, Option<TcpListener>), io::Error> {
+    loop {
+        let socket = UdpSocket::bind(format!("0.0.0.0:{port}"))?;
+        let bound_port = socket.local_addr()?.port();
+        match TcpListener::bind(format!("0.0.0.0:{bound_port}")) {
+            Ok(listener) => return Ok((socket, Some(listener))),
+            Err(_) if port == 0 => continue,
+            Err(_) => {
+                error!("SP @ tcp-listen-socket-fail");
+                return Ok((socket, None));
+            }
+        }
+    }
+}
+
+// Abre o listener na porta e devolve o endereco em que ficou a escutar
+fn start_listener<F>(port: u16, protocol: &'static str, handle_connection: F) -> Option<SocketAddr>
+where
+    F: Fn(TcpStream) + Send + Sync + 'static,
+{
+    match TcpListener::bind(format!("0.0.0.0:{port}")) {
+        Ok(listener) => serve_listener(Some(listener), protocol, handle_connection),
         Err(_) => {
-            debug!("FL @ client-handler-socket-fail");
-            return;
+            error!("SP @ {}-listen-socket-fail", protocol);
+            None
+        }
+    }
+}
+
+fn serve_listener<F>(
+    listener: Option<TcpListener>,
+    protocol: &'static str,
+    handle_connection: F,
+) -> Option<SocketAddr>
+where
+    F: Fn(TcpStream) + Send + Sync + 'static,
+{
+    let listener = listener?;
+    let local_addr = listener.local_addr().ok()?;
+    debug!(
+        "EV @ initializing-{}-listener {}",
+        protocol,
+        local_addr.port()
+    );
+    thread::spawn(move || connection_listener(listener, protocol, handle_connection));
+    Some(local_addr)
+}
+
+fn connection_listener<F>(listener: TcpListener, protocol: &'static str, handle_connection: F)
+where
+    F: Fn(TcpStream) + Send + Sync + 'static,
+{
+    let handle_connection = Arc::new(handle_connection);
+    let open_connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                // Acima do limite a ligacao e fechada logo, sem ler nenhuma pergunta
+                if open_connections.fetch_add(1, Ordering::SeqCst) >= MAX_TCP_CONNECTIONS {
+                    open_connections.fetch_sub(1, Ordering::SeqCst);
+                    error!("SP @ {}-connection-limit-reached", protocol);
+                    continue;
+                }
+                let handler = handle_connection.clone();
+                let connections = open_connections.clone();
+                thread::spawn(move || {
+                    handler(stream);
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(_) => debug!("EV @ {}-accept-fail", protocol),
         }
+    }
+}
+
+// Perguntas por TCP na mesma porta do UDP, usadas pelos clientes quando a resposta vem truncada
+fn tcp_client_handler(
+    mut stream: TcpStream,
+    config: &ServerConfig,
+    supports_recursive: bool,
+    database_mutex: &Arc<Mutex<ZoneTree>>,
+    root_resolver: &Arc<RootResolver>,
+) {
+    let src_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+    // As respostas de perguntas tratadas em paralelo partilham a escrita na ligacao
+    let writer = match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(_) => return,
+    };
+    if stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err() {
+        return;
+    }
+    // Cada mensagem vem precedida do seu tamanho; a ligacao termina quando o cliente a fecha ou
+    // fica sem perguntas durante TCP_IDLE_TIMEOUT. Varias perguntas podem estar pendentes ao
+    // mesmo tempo e as respostas saem pela ordem em que ficam prontas, identificadas pelo id.
+    // Com MAX_PIPELINED_QUERIES pendentes a ligacao so volta a ser lida quando uma delas acabar
+    let in_flight = Arc::new((Mutex::new(0usize), Condvar::new()));
+    let mut pending: Vec<JoinHandle<()>> = Vec::new();
+    loop {
+        {
+            let (count, slot_freed) = &*in_flight;
+            let mut count = count.lock().unwrap();
+            while *count >= MAX_PIPELINED_QUERIES {
+                count = slot_freed.wait(count).unwrap();
+            }
+        }
+        let pdu = match dns_recv::read_tcp_pdu(&mut stream) {
+            Ok(pdu) => pdu,
+            Err(_) => break,
+        };
+        *in_flight.0.lock().unwrap() += 1;
+        let config_clone = config.clone();
+        let new_db = database_mutex.clone();
+        let root_resolver_clone = root_resolver.clone();
+        let writer_clone = writer.clone();
+        let in_flight_clone = in_flight.clone();
+        pending.push(thread::spawn(move || {
+            tcp_query_handler(
+                pdu,
+                writer_clone,
+                src_addr,
+                config_clone,
+                supports_recursive,
+                new_db,
+                root_resolver_clone,
+            );
+            let (count, slot_freed) = &*in_flight_clone;
+            *count.lock().unwrap() -= 1;
+            slot_freed.notify_one();
+        }));
+        pending.retain(|handle| !handle.is_finished());
+    }
+    debug!("EV @ tcp-connection-closed {}", src_addr);
+    for handle in pending {
+        let _ = handle.join();
+    }
+}
+
+fn tcp_query_handler(
+    pdu: Vec<u8>,
+    writer: Arc<Mutex<TcpStream>>,
+    src_addr: SocketAddr,
+    config: ServerConfig,
+    supports_recursive: bool,
+    database_mutex: Arc<Mutex<ZoneTree>>,
+    root_resolver: Arc<RootResolver>,
+) {
+    let answer = match stream_answer(
+        &pdu,
+        src_addr,
+        &config,
+        supports_recursive,
+        &database_mutex,
+        &root_resolver,
+    ) {
+        Some(answer) => answer,
+        None => return,
     };
-    let destination = format!("{}:{}", addr, port);
-    let _num_sent_bytes =
-        match dns_send::send(dns_message.to_owned(), &send_socket, destination.to_owned()) {
-            Ok(num_bytes) => {
-                info!("RP {} {}", destination, dns_message.get_string());
-                num_bytes
+    let mut stream = writer.lock().unwrap();
+    if dns_send::write_tcp_pdu(&mut *stream, &answer).is_err() {
+        debug!("EV @ send-message-fail");
+    }
+}
+
+// Resposta ja codificada a uma pergunta recebida por TCP ou TLS, onde nao ha limite de tamanho
+fn stream_answer(
+    pdu: &[u8],
+    src_addr: SocketAddr,
+    config: &ServerConfig,
+    supports_recursive: bool,
+    database_mutex: &Arc<Mutex<ZoneTree>>,
+    root_resolver: &Arc<RootResolver>,
+) -> Option<Vec<u8>> {
+    match handle_query(
+        pdu,
+        src_addr,
+        config,
+        supports_recursive,
+        database_mutex,
+        root_resolver,
+    ) {
+        Some(Answer::Message(dns_message, format, _payload_limit, tsig)) => {
+            match encode_answer(&dns_message, format, tsig.as_deref()) {
+                Ok(answer) => {
+                    info!("RP {} {}", src_addr, dns_message.get_string());
+                    Some(answer)
+                }
+                Err(_err) => {
+                    debug!("EV @ send-message-fail");
+                    None
+                }
             }
-            Err(_err) => {
-                debug!("EV @ send-message-fail");
+        }
+        Some(Answer::Raw(pdu)) => Some(pdu),
+        None => None,
+    }
+}
+
+// Perguntas por DNS sobre TLS, com o mesmo enquadramento e limite de inatividade do TCP
+fn tls_client_handler(
+    stream: TcpStream,
+    tls_config: &TlsServerConfig,
+    config: &ServerConfig,
+    supports_recursive: bool,
+    database_mutex: &Arc<Mutex<ZoneTree>>,
+    root_resolver: &Arc<RootResolver>,
+) {
+    let src_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+    if stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err() {
+        return;
+    }
+    let mut tls_stream: TlsServerStream = match dns_tls::accept(tls_config, stream) {
+        Ok(tls_stream) => tls_stream,
+        Err(err) => {
+            error!("ER {} {}", src_addr.ip(), err);
+            return;
+        }
+    };
+    // A sessao TLS nao pode ser partilhada entre threads, por isso as perguntas de uma ligacao
+    // sao respondidas pela ordem em que chegam
+    while let Ok(pdu) = dns_recv::read_tcp_pdu(&mut tls_stream) {
+        let answer = match stream_answer(
+            &pdu,
+            src_addr,
+            config,
+            supports_recursive,
+            database_mutex,
+            root_resolver,
+        ) {
+            Some(answer) => answer,
+            None => continue,
+        };
+        if dns_send::write_tcp_pdu(&mut tls_stream, &answer).is_err() {
+            debug!("EV @ send-message-fail");
+            return;
+        }
+    }
+    debug!("EV @ tls-connection-closed {}", src_addr);
+    dns_tls::close(&mut tls_stream);
+}
+
+// Perguntas por DNS sobre HTTPS (RFC 8484), em HTTP simples ou sobre TLS
+fn doh_client_handler(
+    stream: TcpStream,
+    tls_config: Option<&TlsServerConfig>,
+    config: &ServerConfig,
+    supports_recursive: bool,
+    database_mutex: &Arc<Mutex<ZoneTree>>,
+    root_resolver: &Arc<RootResolver>,
+) {
+    let src_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+    if stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err() {
+        return;
+    }
+    match tls_config {
+        Some(tls_config) => {
+            let mut tls_stream = match dns_tls::accept(tls_config, stream) {
+                Ok(tls_stream) => tls_stream,
+                Err(err) => {
+                    error!("ER {} {}", src_addr.ip(), err);
+                    return;
+                }
+            };
+            doh_session(
+                &mut tls_stream,
+                src_addr,
+                config,
+                supports_recursive,
+                database_mutex,
+                root_resolver,
+            );
+            dns_tls::close(&mut tls_stream);
+        }
+        None => {
+            let mut stream = stream;
+            doh_session(
+                &mut stream,
+                src_addr,
+                config,
+                supports_recursive,
+                database_mutex,
+                root_resolver,
+            );
+        }
+    }
+    debug!("EV @ http-connection-closed {}", src_addr);
+}
+
+// Responde aos pedidos HTTP de uma ligacao ate o cliente a fechar ou pedir Connection: close
+fn doh_session(
+    stream: &mut (impl Read + Write),
+    src_addr: SocketAddr,
+    config: &ServerConfig,
+    supports_recursive: bool,
+    database_mutex: &Arc<Mutex<ZoneTree>>,
+    root_resolver: &Arc<RootResolver>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let request = match dns_https::read_doh_request(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) | Err(HttpError::IOError(_)) => return,
+            Err(err) => {
+                error!("ER http-request-fail {} {}", src_addr.ip(), err.status());
+                let _ = dns_https::write_http_error(reader.get_mut(), &err);
                 return;
             }
         };
+        // Por HTTP so se aceitam mensagens no formato RFC 1035 (RFC 8484 4.1)
+        if let Ok((_, PduFormat::Legacy)) = dns_recv::decode_pdu(&request.pdu) {
+            error!("ER http-request-fail {} legacy-pdu", src_addr.ip());
+            let _ = dns_https::write_http_error(reader.get_mut(), &HttpError::BadRequest);
+            return;
+        }
+        let (answer, max_age) = match handle_query(
+            &request.pdu,
+            src_addr,
+            config,
+            supports_recursive,
+            database_mutex,
+            root_resolver,
+        ) {
+            Some(Answer::Message(dns_message, _format, _payload_limit, tsig)) => {
+                match encode_answer(&dns_message, PduFormat::Standard, tsig.as_deref()) {
+                    Ok(answer) => {
+                        info!("RP {} {}", src_addr, dns_message.get_string());
+                        (answer, dns_https::max_age(&dns_message))
+                    }
+                    Err(_err) => {
+                        debug!("EV @ send-message-fail");
+                        let _ =
+                            dns_https::write_http_error(reader.get_mut(), &HttpError::BadRequest);
+                        return;
+                    }
+                }
+            }
+            Some(Answer::Raw(pdu)) => (pdu, 0),
+            None => return,
+        };
+        if dns_https::write_doh_response(reader.get_mut(), &answer, max_age, request.keep_alive)
+            .is_err()
+        {
+            debug!("EV @ send-message-fail");
+            return;
+        }
+        if !request.keep_alive {
+            return;
+        }
+    }
 }
 
 fn create_logger_config(log_path: String, level: LevelFilter) -> log4rs::Config {
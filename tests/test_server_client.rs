@@ -1,29 +1,101 @@
 extern crate rustcc;
 
 use my_dns::{
-    dns_components::sr::resolver,
+    dns_components::{
+        sr::{
+            resolver, resolver_over, resolver_with, validating_resolver, KeyCache,
+            ResolverOptions, Transport,
+        },
+        validator::TrustAnchor,
+    },
+    dns_make::{
+        dns_dnssec::{self, ZoneKey, SEP_FLAG, ZONE_KEY_FLAG},
+        dns_recv, dns_send,
+        dns_tls::TlsConnector,
+        dns_tsig::{self, TsigKey},
+        dns_wire,
+    },
     dns_parse::server_config_parse,
     dns_structs::{
         dns_domain_name::Domain,
         dns_message::{
-            DNSEntry, DNSMessage, DNSMessageData, DNSMessageHeaders, DNSQueryInfo, QueryType,
+            DNSEntry, DNSMessage, DNSMessageData, DNSMessageHeaders, DNSQueryInfo, Flags, QueryType,
+            ResponseCode,
         },
+        dns_resource_data::{Edns, RData},
     },
 };
-use rustcc::{client, server};
-use std::{net::SocketAddr, thread, time::Duration, str::FromStr};
+use rustcc::{client, server::{self, ListenAddrs}};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// Configuracao de teste com a zona example.com, a que os testes juntam as suas linhas
+fn example_com_config() -> String {
+    fs::read_to_string("etc/test-example-com.conf").unwrap()
+}
+
+// Escreve a configuracao num ficheiro temporario e arranca o servidor em portas livres (porta 0).
+// Devolve os enderecos em 127.0.0.1 so depois de o servidor aceitar perguntas. Com `once` o
+// servidor termina depois da primeira pergunta UDP
+fn start_test_server(config: &str, once: bool) -> (ListenAddrs, JoinHandle<()>) {
+    static NEXT_CONFIG: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join("rustcc-tests");
+    fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join(format!(
+        "{}-{}.conf",
+        std::process::id(),
+        NEXT_CONFIG.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::write(&config_path, config).unwrap();
+    let config = server_config_parse::get(config_path.to_string_lossy().to_string()).unwrap();
+
+    let (ready, listening) = mpsc::channel();
+    let server =
+        thread::spawn(move || server::start_server_notify(config, 0, true, once, ready));
+    let addrs = listening
+        .recv_timeout(Duration::new(30, 0))
+        .expect("server did not start");
+    let localhost = |addr: SocketAddr| SocketAddr::from((Ipv4Addr::LOCALHOST, addr.port()));
+    let addrs = ListenAddrs {
+        dns: localhost(addrs.dns),
+        tls: addrs.tls.map(localhost),
+        http: addrs.http.map(localhost),
+        https: addrs.https.map(localhost),
+    };
+    (addrs, server)
+}
+
+// Arranca o servidor com a configuracao e devolve todos os enderecos de escuta
+fn spawn_server_addrs(config: &str) -> ListenAddrs {
+    start_test_server(config, false).0
+}
+
+// Arranca o servidor com a configuracao e devolve o endereco de DNS (UDP e TCP)
+fn spawn_server(config: &str) -> SocketAddr {
+    spawn_server_addrs(config).dns
+}
 
 #[test]
 fn test_client_server() {
-    let server = thread::spawn(move || {
-        let config = server_config_parse::get("etc/test-example-com.conf".to_string()).unwrap();
-        server::start_server(config, 5454, true, true)
-    });
-    thread::sleep(Duration::new(1, 0));
+    let (addrs, server) = start_test_server(&example_com_config(), true);
     let client = thread::spawn(move || {
-        let server_list :Vec<SocketAddr> = vec![SocketAddr::from_str(&"0.0.0.0:5454".to_string()).unwrap()];
-        let mut query =
-            client::query_builder(Domain::new("www.example.com".to_string()), QueryType::A, 4);
+        let server_list :Vec<SocketAddr> = vec![SocketAddr::from_str(&format!("0.0.0.0:{}", addrs.dns.port())).unwrap()];
+        let mut query = client::query_builder(
+            Domain::new("www.example.com".to_string()),
+            QueryType::A,
+            Flags::QUERY,
+        );
         resolver(
             &mut query,
             server_list,
@@ -40,28 +112,26 @@ fn test_client_server() {
                         type_of_value: QueryType::A,
                     };
                     //Fill in response_values
+                    //The priority of an A record is not sent in the RFC 1035 format
                     let response_values = vec![DNSEntry {
                         domain_name: Domain::new("www.example.com.".to_string()),
-                        type_of_value: "A".to_string(),
-                        value: "10.3.3.1".to_string(),
                         ttl: 86400,
-                        priority: Some(200),
+                        priority: None,
+                        data: RData::A(Ipv4Addr::new(10, 3, 3, 1)),
                     }];
                     //Fill in authorities_values
                     let authorities_values = vec![DNSEntry {
                         domain_name: Domain::new("example.com.".to_string()),
-                        type_of_value: "NS".to_string(),
-                        value: "ns1.example.com.".to_string(),
                         ttl: 86400,
                         priority: None,
+                        data: RData::NS(Domain::new("ns1.example.com.".to_string())),
                     }];
                     //Fill in extra_values
                     let extra_values = vec![DNSEntry {
                         domain_name: Domain::new("ns1.example.com.".to_string()),
-                        type_of_value: "A".to_string(),
-                        value: "10.2.2.2".to_string(),
                         ttl: 86400,
                         priority: None,
+                        data: RData::A(Ipv4Addr::new(10, 2, 2, 2)),
                     }];
                     //Create dns_message_data
                     let dns_message_data = DNSMessageData {
@@ -73,8 +143,8 @@ fn test_client_server() {
                     //Create dns_message_header
                     let dns_message_header = DNSMessageHeaders {
                         message_id: response.header.message_id,
-                        flags: 1,
-                        response_code: Some(0),
+                        flags: Flags::AUTHORITATIVE,
+                        response_code: Some(ResponseCode::NoError),
                         number_of_values: Some(1),
                         number_of_authorities: Some(1),
                         number_of_extra_values: Some(1),
@@ -95,3 +165,1011 @@ fn test_client_server() {
     };
     server.join().unwrap();
 }
+
+#[test]
+fn test_standard_dns_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // Pergunta no formato RFC 1035, tal como enviada pelo dig
+    let query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    socket
+        .send_to(&dns_wire::encode(&query).unwrap(), server_addr)
+        .unwrap();
+
+    let mut buf = [0u8; 512];
+    let (size, src_addr) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(src_addr.port(), server_addr.port());
+
+    let response = dns_wire::decode(&buf[..size]).unwrap();
+    assert_eq!(response.header.message_id, query.header.message_id);
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    let response_values = response.data.response_values.unwrap();
+    assert_eq!(response_values.len(), 1);
+    assert_eq!(response_values[0].data, RData::A(Ipv4Addr::new(10, 3, 3, 1)));
+}
+
+#[test]
+fn test_aaaa_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::AAAA,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(
+        response.data.response_values,
+        Some(vec![DNSEntry {
+            domain_name: Domain::new("www.example.com.".to_string()),
+            ttl: 86400,
+            priority: None,
+            data: RData::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 3, 3, 1)),
+        }])
+    );
+}
+
+#[test]
+fn test_txt_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::TXT,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(
+        response.data.response_values,
+        Some(vec![DNSEntry {
+            domain_name: Domain::new("www.example.com.".to_string()),
+            ttl: 86400,
+            priority: None,
+            data: RData::TXT(vec![
+                b"site-verification=3f2a9c".to_vec(),
+                b"say \"hello world\"".to_vec()
+            ]),
+        }])
+    );
+}
+
+#[test]
+fn test_srv_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("_sip._tcp.example.com".to_string()),
+        QueryType::SRV, Flags::QUERY);
+    let response = resolver(&mut query, server_list, true).unwrap();
+
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(
+        response.data.response_values,
+        Some(vec![DNSEntry {
+            domain_name: Domain::new("_sip._tcp.example.com.".to_string()),
+            ttl: 86400,
+            priority: None,
+            data: RData::SRV {
+                priority: 10,
+                weight: 60,
+                port: 5060,
+                target: Domain::new("www.example.com.".to_string()),
+            },
+        }])
+    );
+    // Os enderecos do target vao nos valores extra, sem a prioridade do A, que o formato RFC 1035
+    // nao tem
+    let extra_values = response.data.extra_values.unwrap();
+    assert!(extra_values.contains(&DNSEntry {
+        domain_name: Domain::new("www.example.com.".to_string()),
+        ttl: 86400,
+        priority: None,
+        data: RData::A(Ipv4Addr::new(10, 3, 3, 1)),
+    }));
+    assert!(extra_values.contains(&DNSEntry {
+        domain_name: Domain::new("www.example.com.".to_string()),
+        ttl: 86400,
+        priority: None,
+        data: RData::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 3, 3, 1)),
+    }));
+}
+
+fn example_com_soa() -> DNSEntry {
+    DNSEntry {
+        domain_name: Domain::new("example.com.".to_string()),
+        ttl: 86400,
+        priority: None,
+        data: RData::SOA {
+            mname: Domain::new("ns1.example.com.".to_string()),
+            rname: Domain::new("dns\\.admin.example.com.".to_string()),
+            serial: 117102022,
+            refresh: 14400,
+            retry: 3600,
+            expire: 604800,
+            minimum: 86400,
+        },
+    }
+}
+
+#[test]
+fn test_soa_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("example.com".to_string()),
+        QueryType::SOA,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(response.data.response_values, Some(vec![example_com_soa()]));
+}
+
+#[test]
+fn test_negative_answers_carry_soa() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // NXDOMAIN: o nome nao existe na zona
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("nope.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NXDomain));
+    assert_eq!(response.data.response_values, None);
+    assert_eq!(response.data.authorities_values, Some(vec![example_com_soa()]));
+
+    // NODATA: o nome existe mas nao tem records do tipo pedido
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::MX,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(response.data.response_values, None);
+    assert_eq!(response.data.authorities_values, Some(vec![example_com_soa()]));
+}
+
+#[test]
+fn test_wildcard_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // host.wild.example.com nao existe e e respondido pelo *.wild.example.com
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("host.wild.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    let response_values = response.data.response_values.unwrap();
+    assert_eq!(response_values.len(), 1);
+    assert_eq!(
+        response_values[0].domain_name,
+        Domain::new("host.wild.example.com".to_string())
+    );
+    assert_eq!(response_values[0].data, RData::A(Ipv4Addr::new(10, 3, 3, 9)));
+}
+
+#[test]
+fn test_cname_chain_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // alias.example.com -> web.example.com -> www.example.com, seguido pelo servidor na zona
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("alias.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(
+        response.data.query_info.name,
+        Domain::new("alias.example.com".to_string())
+    );
+    let response_values = response.data.response_values.unwrap();
+    assert_eq!(response_values.len(), 3);
+    assert_eq!(
+        response_values[0].data,
+        RData::CNAME(Domain::new("web.example.com".to_string()))
+    );
+    assert_eq!(
+        response_values[1].data,
+        RData::CNAME(Domain::new("www.example.com".to_string()))
+    );
+    assert_eq!(
+        response_values[2].domain_name,
+        Domain::new("www.example.com".to_string())
+    );
+    assert_eq!(response_values[2].data, RData::A(Ipv4Addr::new(10, 3, 3, 1)));
+}
+
+#[test]
+fn test_cname_loop_query() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // loop1 e loop2 apontam um para o outro: a cadeia para no primeiro nome repetido
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("loop1.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    let response_values = response.data.response_values.unwrap();
+    assert_eq!(response_values.len(), 2);
+    assert!(response_values
+        .iter()
+        .all(|entry| matches!(entry.data, RData::CNAME(_))));
+}
+
+#[test]
+fn test_truncated_response_tcp_fallback() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // A resposta com os 8 TXT de huge.example.com nao cabe nos 1232 bytes anunciados no OPT,
+    // por isso vem truncada por UDP e o resolver repete a pergunta por TCP
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("huge.example.com".to_string()),
+        QueryType::TXT,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, server_list, true).unwrap();
+
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert!(!response.header.flags.contains(Flags::TRUNCATED));
+    assert_eq!(response.header.number_of_values, Some(8));
+    assert_eq!(response.get_edns(), None);
+    assert_eq!(response.data.response_values.unwrap().len(), 8);
+}
+
+#[test]
+fn test_resolver_standard_truncation_retries_tcp() {
+    // Servidor de teste com UDP e TCP na mesma porta: por UDP so responde com o bit TC
+    let (udp_server, tcp_server) = loop {
+        let tcp_server = TcpListener::bind("127.0.0.1:0").unwrap();
+        if let Ok(udp_server) = UdpSocket::bind(tcp_server.local_addr().unwrap()) {
+            break (udp_server, tcp_server);
+        }
+    };
+    let server_addr = udp_server.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut buf = [0u8; 1232];
+        let (size, resolver_addr) = udp_server.recv_from(&mut buf).unwrap();
+        let (query, format) = dns_recv::decode_pdu(&buf[..size]).ok().unwrap();
+        assert_eq!(format, dns_wire::PduFormat::Standard);
+        let mut truncated = query.to_owned();
+        truncated.take_edns();
+        truncated.header.flags = Flags::AUTHORITATIVE.with(Flags::TRUNCATED).unwrap();
+        truncated.header.response_code = Some(ResponseCode::NoError);
+        udp_server
+            .send_to(&dns_wire::encode(&truncated).unwrap(), resolver_addr)
+            .unwrap();
+
+        // A pergunta repetida por TCP recebe a resposta completa
+        let (mut stream, _peer) = tcp_server.accept().unwrap();
+        let (query, format) =
+            dns_recv::decode_pdu(&dns_recv::read_tcp_pdu(&mut stream).unwrap()).ok().unwrap();
+        assert_eq!(format, dns_wire::PduFormat::Standard);
+        let mut answer = query.to_owned();
+        answer.take_edns();
+        answer.header.flags = Flags::AUTHORITATIVE;
+        answer.header.response_code = Some(ResponseCode::NoError);
+        answer.data.response_values = Some(vec![DNSEntry::new(
+            Domain::new("big.example.com.".to_string()),
+            300,
+            RData::TXT(vec![b"over tcp".to_vec()]),
+        )]);
+        answer.header.number_of_values = Some(1);
+        dns_send::write_tcp_pdu(&mut stream, &dns_wire::encode(&answer).unwrap()).unwrap();
+    });
+
+    let mut query = client::query_builder(
+        Domain::new("big.example.com".to_string()),
+        QueryType::TXT,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, vec![server_addr], false).unwrap();
+    assert!(!response.header.flags.contains(Flags::TRUNCATED));
+    assert_eq!(
+        response.data.response_values.unwrap()[0].data,
+        RData::TXT(vec![b"over tcp".to_vec()])
+    );
+    server.join().unwrap();
+}
+
+#[test]
+fn test_standard_dns_truncation_and_tcp() {
+    let server_addr = spawn_server(&example_com_config());
+
+    let query = client::query_builder(
+        Domain::new("big.example.com".to_string()),
+        QueryType::TXT,
+        Flags::QUERY,
+    );
+    let query_bytes = dns_wire::encode(&query).unwrap();
+
+    // Por UDP a resposta vem so com o cabecalho, a pergunta e o bit TC
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    socket.send_to(&query_bytes, server_addr).unwrap();
+    let mut buf = [0u8; 512];
+    let (size, _src_addr) = socket.recv_from(&mut buf).unwrap();
+    let response = dns_wire::decode(&buf[..size]).unwrap();
+    assert!(response.header.flags.contains(Flags::TRUNCATED));
+    assert_eq!(response.data.response_values, None);
+
+    // Por TCP, na mesma porta, cada mensagem vai precedida do tamanho em 2 bytes
+    let mut stream = TcpStream::connect(server_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    stream
+        .write_all(&(query_bytes.len() as u16).to_be_bytes())
+        .unwrap();
+    stream.write_all(&query_bytes).unwrap();
+    let mut len_buf = [0u8; 2];
+    stream.read_exact(&mut len_buf).unwrap();
+    let mut response_bytes = vec![0u8; u16::from_be_bytes(len_buf).into()];
+    stream.read_exact(&mut response_bytes).unwrap();
+    assert!(response_bytes.len() > 512);
+
+    let response = dns_wire::decode(&response_bytes).unwrap();
+    assert_eq!(response.header.message_id, query.header.message_id);
+    assert!(!response.header.flags.contains(Flags::TRUNCATED));
+    assert_eq!(response.data.response_values.unwrap().len(), 4);
+}
+
+#[test]
+fn test_edns_udp_payload_size() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // Com um OPT de 1232 bytes a resposta com os 4 TXT de big.example.com ja cabe por UDP
+    let mut query = client::query_builder(
+        Domain::new("big.example.com".to_string()),
+        QueryType::TXT,
+        Flags::QUERY,
+    );
+    query.set_edns(Edns::new(1232));
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    socket
+        .send_to(&dns_wire::encode(&query).unwrap(), server_addr)
+        .unwrap();
+    let mut buf = [0u8; 1232];
+    let (size, _src_addr) = socket.recv_from(&mut buf).unwrap();
+    assert!(size > 512);
+    let mut response = dns_wire::decode(&buf[..size]).unwrap();
+    assert!(!response.header.flags.contains(Flags::TRUNCATED));
+    assert_eq!(response.take_edns(), Some(Edns::new(1232)));
+    assert_eq!(response.data.response_values.unwrap().len(), 4);
+}
+
+#[test]
+fn test_edns_bad_version() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // So a versao 0 do EDNS e suportada, outras versoes recebem BADVERS com o OPT do servidor
+    let mut query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let mut edns = Edns::new(1232);
+    edns.version = 1;
+    query.set_edns(edns);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    socket
+        .send_to(&dns_wire::encode(&query).unwrap(), server_addr)
+        .unwrap();
+    let mut buf = [0u8; 512];
+    let (size, _src_addr) = socket.recv_from(&mut buf).unwrap();
+    let response = dns_wire::decode(&buf[..size]).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::BadVersion));
+    assert_eq!(response.get_edns().map(|edns| edns.version), Some(0));
+    assert_eq!(response.data.response_values, None);
+}
+
+#[test]
+fn test_resolver_udp_standard_format() {
+    // Servidor antigo: recusa a pergunta RFC 1035 com FORMERR e so responde a perguntas bincode
+    let legacy_server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let legacy_addr = legacy_server.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut buf = [0u8; 1232];
+        let (size, resolver_addr) = legacy_server.recv_from(&mut buf).unwrap();
+        let (mut query, format) = dns_recv::decode_pdu(&buf[..size]).ok().unwrap();
+        assert_eq!(format, dns_wire::PduFormat::Standard);
+        // O OPT anunciado pelo resolver vai no datagrama
+        assert_eq!(query.take_edns().map(|edns| edns.udp_payload_size), Some(1232));
+        let mut formerr = query.to_owned();
+        formerr.header.flags = Flags::empty();
+        formerr.header.response_code = Some(ResponseCode::Malformed);
+        legacy_server
+            .send_to(&dns_wire::encode(&formerr).unwrap(), resolver_addr)
+            .unwrap();
+
+        let (size, resolver_addr) = legacy_server.recv_from(&mut buf).unwrap();
+        let (query, format) = dns_recv::decode_pdu(&buf[..size]).ok().unwrap();
+        assert_eq!(format, dns_wire::PduFormat::Legacy);
+        let mut answer = query.to_owned();
+        answer.header.flags = Flags::AUTHORITATIVE;
+        answer.header.response_code = Some(ResponseCode::NoError);
+        answer.data.response_values = Some(vec![DNSEntry::new(
+            Domain::new("www.example.com.".to_string()),
+            300,
+            RData::A(Ipv4Addr::new(10, 3, 3, 1)),
+        )]);
+        answer.header.number_of_values = Some(1);
+        let pdu = dns_send::encode_pdu(&answer, dns_wire::PduFormat::Legacy).unwrap();
+        legacy_server.send_to(&pdu, resolver_addr).unwrap();
+    });
+
+    let mut query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let response = resolver(&mut query, vec![legacy_addr], false).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(
+        response.data.response_values.unwrap()[0].data,
+        RData::A(Ipv4Addr::new(10, 3, 3, 1))
+    );
+    server.join().unwrap();
+}
+
+#[test]
+fn test_tcp_pipelined_queries() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // Mais perguntas do que as que o servidor trata ao mesmo tempo numa ligacao, enviadas de
+    // seguida antes de ler qualquer resposta
+    let queries: Vec<DNSMessage> = (0..40)
+        .map(|id| {
+            let query_type = if id % 2 == 0 {
+                QueryType::A
+            } else {
+                QueryType::TXT
+            };
+            let mut query = client::query_builder(
+                Domain::new("www.example.com".to_string()),
+                query_type,
+                Flags::QUERY,
+            );
+            query.header.message_id = id;
+            query
+        })
+        .collect();
+    let mut stream = TcpStream::connect(server_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    let mut pdus = Vec::new();
+    for query in queries.iter() {
+        let query_bytes = dns_wire::encode(query).unwrap();
+        pdus.extend_from_slice(&(query_bytes.len() as u16).to_be_bytes());
+        pdus.extend_from_slice(&query_bytes);
+    }
+    stream.write_all(&pdus).unwrap();
+
+    // As respostas podem vir por qualquer ordem e sao associadas as perguntas pelo id
+    let mut responses = Vec::new();
+    for _ in 0..queries.len() {
+        let mut len_buf = [0u8; 2];
+        stream.read_exact(&mut len_buf).unwrap();
+        let mut response_bytes = vec![0u8; u16::from_be_bytes(len_buf).into()];
+        stream.read_exact(&mut response_bytes).unwrap();
+        responses.push(dns_wire::decode(&response_bytes).unwrap());
+    }
+    for query in queries.iter() {
+        let response = responses
+            .iter()
+            .find(|response| response.header.message_id == query.header.message_id)
+            .unwrap();
+        assert_eq!(
+            response.data.query_info.type_of_value,
+            query.data.query_info.type_of_value
+        );
+        assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    }
+}
+
+#[test]
+fn test_resolver_tcp_transport() {
+    let server_addr = spawn_server(&example_com_config());
+
+    // Por TCP a resposta com os 8 TXT de huge.example.com vem logo completa
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut query = client::query_builder(
+        Domain::new("huge.example.com".to_string()),
+        QueryType::TXT,
+        Flags::QUERY,
+    );
+    let response = resolver_over(&mut query, server_list, true, Transport::Tcp).unwrap();
+
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert!(!response.header.flags.contains(Flags::TRUNCATED));
+    assert_eq!(response.data.response_values.unwrap().len(), 8);
+}
+
+// Gera um certificado auto-assinado para 127.0.0.1 e devolve os caminhos do certificado e da chave
+fn self_signed_certificate(name: &str) -> (String, String) {
+    let dir = std::env::temp_dir().join("rustcc-tests");
+    fs::create_dir_all(&dir).unwrap();
+    let certified_key = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+    let cert_path = dir.join(format!("{name}-cert.pem"));
+    let key_path = dir.join(format!("{name}-key.pem"));
+    fs::write(&cert_path, certified_key.cert.pem()).unwrap();
+    fs::write(&key_path, certified_key.key_pair.serialize_pem()).unwrap();
+    (
+        cert_path.to_string_lossy().to_string(),
+        key_path.to_string_lossy().to_string(),
+    )
+}
+
+#[test]
+fn test_dns_over_tls() {
+    let (cert_path, key_path) = self_signed_certificate("dot-server");
+    let (other_cert_path, _) = self_signed_certificate("dot-other");
+
+    // A configuracao de teste com o certificado, a chave e a porta do DNS sobre TLS
+    let mut config = example_com_config();
+    config.push_str(&format!("all TC {cert_path}\nall TK {key_path}\nall TP 0\n"));
+    let tls_addr = spawn_server_addrs(&config).tls.unwrap();
+
+    let server_list: Vec<SocketAddr> = vec![tls_addr];
+    let mut query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let connector = TlsConnector::from_ca_file(&cert_path).unwrap();
+    let response =
+        resolver_over(&mut query, server_list.clone(), true, Transport::Tls(connector)).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(
+        response.data.response_values.unwrap()[0].data,
+        RData::A(Ipv4Addr::new(10, 3, 3, 1))
+    );
+
+    // Um cliente que nao confia no certificado do servidor nao chega a enviar a pergunta
+    let untrusted = TlsConnector::from_ca_file(&other_cert_path).unwrap();
+    assert!(resolver_over(&mut query, server_list, true, Transport::Tls(untrusted)).is_err());
+}
+
+// Le uma resposta HTTP e devolve o estado, os cabecalhos (em minusculas) e o corpo
+fn read_http_response(reader: &mut impl BufRead) -> (String, Vec<(String, String)>, Vec<u8>) {
+    let mut status = String::new();
+    reader.read_line(&mut status).unwrap();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let content_length: usize = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map(|(_, value)| value.parse().unwrap())
+        .unwrap();
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+    (status.trim_end().to_string(), headers, body)
+}
+
+#[test]
+fn test_dns_over_https() {
+    let mut config = example_com_config();
+    config.push_str("all HP 0\n");
+    let http_addr = spawn_server_addrs(&config).http.unwrap();
+
+    let query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let query_bytes = dns_wire::encode(&query).unwrap();
+    let stream = TcpStream::connect(http_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    let mut reader = BufReader::new(stream);
+
+    // GET com a pergunta em base64url no parametro dns
+    let get_request = format!(
+        "GET /dns-query?dns={} HTTP/1.1\r\nHost: 127.0.0.1\r\nAccept: application/dns-message\r\n\r\n",
+        URL_SAFE_NO_PAD.encode(&query_bytes)
+    );
+    reader.get_mut().write_all(get_request.as_bytes()).unwrap();
+    let (status, headers, body) = read_http_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(headers.contains(&(
+        "content-type".to_string(),
+        "application/dns-message".to_string()
+    )));
+    let response = dns_wire::decode(&body).unwrap();
+    assert_eq!(response.header.message_id, query.header.message_id);
+    assert_eq!(
+        response.data.response_values.unwrap()[0].data,
+        RData::A(Ipv4Addr::new(10, 3, 3, 1))
+    );
+
+    // POST na mesma ligacao, com a pergunta no corpo
+    let mut post_request = format!(
+        "POST /dns-query HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+        query_bytes.len()
+    )
+    .into_bytes();
+    post_request.extend_from_slice(&query_bytes);
+    reader.get_mut().write_all(&post_request).unwrap();
+    let (status, _headers, body) = read_http_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let response = dns_wire::decode(&body).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+
+    // Um corpo que nao e uma mensagem DNS e recusado e a ligacao fechada
+    let bad_request = "POST /dns-query HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi";
+    reader.get_mut().write_all(bad_request.as_bytes()).unwrap();
+    let (status, _headers, body) = read_http_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 415 Unsupported Media Type");
+    assert!(body.is_empty());
+
+    // Uma pergunta no formato bincode tambem e recusada
+    let legacy_bytes = dns_send::encode_pdu(&query, dns_wire::PduFormat::Legacy).unwrap();
+    let stream = TcpStream::connect(http_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut legacy_request = format!(
+        "POST /dns-query HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+        legacy_bytes.len()
+    )
+    .into_bytes();
+    legacy_request.extend_from_slice(&legacy_bytes);
+    reader.get_mut().write_all(&legacy_request).unwrap();
+    let (status, _headers, _body) = read_http_response(&mut reader);
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+}
+
+#[test]
+fn test_tsig_signed_queries() {
+    // example.com so responde a perguntas assinadas com a chave query-key
+    let mut config = example_com_config();
+    config.push_str("query-key KY c2VncmVkbyBkYXMgcGVyZ3VudGFz\nexample.com KQ query-key\n");
+    let server_addr = spawn_server(&config);
+
+    let query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let query_key = TsigKey::new(
+        Domain::new("query-key".to_string()),
+        b"segredo das perguntas".to_vec(),
+    );
+
+    // Sem assinatura a pergunta e recusada
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    socket
+        .send_to(&dns_wire::encode(&query).unwrap(), server_addr)
+        .unwrap();
+    let mut buf = [0u8; 512];
+    let (size, _src_addr) = socket.recv_from(&mut buf).unwrap();
+    let response = dns_wire::decode(&buf[..size]).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::Refused));
+    assert_eq!(response.data.response_values, None);
+
+    // Assinada com a chave certa recebe a resposta, assinada com o MAC da pergunta
+    let mut stream = TcpStream::connect(server_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    let mut query_bytes = dns_wire::encode(&query).unwrap();
+    let request_mac = dns_tsig::sign_request(&mut query_bytes, &query_key, dns_tsig::now()).unwrap();
+    dns_send::write_tcp_pdu(&mut stream, &query_bytes).unwrap();
+    let response_bytes = dns_recv::read_tcp_pdu(&mut stream).unwrap();
+    let (message, context) = dns_tsig::verify_message(
+        &response_bytes,
+        std::slice::from_ref(&query_key),
+        Some(&request_mac),
+        dns_tsig::now(),
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(context.get_error(), None);
+    let response = dns_wire::decode(&message).unwrap();
+    assert_eq!(response.header.message_id, query.header.message_id);
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(response.data.response_values.map(|values| values.len()), Some(1));
+
+    // Uma chave que o servidor nao conhece recebe NOTAUTH com o erro BADKEY no TSIG
+    let other_key = TsigKey::new(
+        Domain::new("other-key".to_string()),
+        b"segredo das perguntas".to_vec(),
+    );
+    let mut query_bytes = dns_wire::encode(&query).unwrap();
+    dns_tsig::sign_request(&mut query_bytes, &other_key, dns_tsig::now()).unwrap();
+    dns_send::write_tcp_pdu(&mut stream, &query_bytes).unwrap();
+    let response_bytes = dns_recv::read_tcp_pdu(&mut stream).unwrap();
+    let (message, record) = dns_wire::split_tsig(&response_bytes).unwrap().unwrap();
+    assert_eq!(record.error, 17);
+    assert!(record.mac.is_empty());
+    let response = dns_wire::decode(&message).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NotAuth));
+
+    // Um TSIG que nao e o ultimo record torna a pergunta invalida e a resposta e um FORMERR
+    let mut query_bytes = dns_wire::encode(&query).unwrap();
+    let unsigned_len = query_bytes.len();
+    dns_tsig::sign_request(&mut query_bytes, &query_key, dns_tsig::now()).unwrap();
+    let tsig_record = query_bytes[unsigned_len..].to_vec();
+    query_bytes.extend_from_slice(&tsig_record);
+    query_bytes[11] += 1;
+    dns_send::write_tcp_pdu(&mut stream, &query_bytes).unwrap();
+    assert_eq!(
+        dns_recv::read_tcp_pdu(&mut stream).unwrap(),
+        dns_wire::encode_format_error(query.header.message_id)
+    );
+
+    // O resolver assina as perguntas com a chave e so aceita respostas assinadas por ela
+    let server_list: Vec<SocketAddr> = vec![server_addr];
+    let mut options = ResolverOptions {
+        transport: Transport::Tcp,
+        randomize_case: false,
+        tsig_key: Some(query_key),
+        key_cache: KeyCache::new(),
+    };
+    let mut query = query.to_owned();
+    let response =
+        resolver_with(&mut query, server_list.to_owned(), false, options.to_owned()).unwrap();
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(response.data.response_values.map(|values| values.len()), Some(1));
+    options.tsig_key = Some(other_key);
+    assert!(resolver_with(&mut query, server_list, false, options).is_err());
+}
+
+#[test]
+fn test_dnssec_signed_zone() {
+    // example.com e assinada com chaves criadas no arranque do servidor
+    let test_dir = std::env::temp_dir().join("rustcc-tests").join("dnssec");
+    fs::create_dir_all(&test_dir).unwrap();
+    let mut config = example_com_config();
+    for (entry, file) in [("KS", "ksk.pk8"), ("ZS", "zsk.pk8")] {
+        let key_path = test_dir.join(file);
+        let _ = fs::remove_file(&key_path);
+        config.push_str(&format!("example.com {} {}\n", entry, key_path.display()));
+    }
+    let server_addr = spawn_server(&config);
+
+    let mut stream = TcpStream::connect(server_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    let mut ask = |name: &str, query_type: QueryType, dnssec_ok: bool| {
+        let mut query =
+            client::query_builder(Domain::new(name.to_string()), query_type, Flags::QUERY);
+        let mut edns = Edns::new(1232);
+        edns.dnssec_ok = dnssec_ok;
+        query.set_edns(edns);
+        dns_send::write_tcp_pdu(&mut stream, &dns_wire::encode(&query).unwrap()).unwrap();
+        dns_wire::decode(&dns_recv::read_tcp_pdu(&mut stream).unwrap()).unwrap()
+    };
+    let count = |entries: &Option<Vec<DNSEntry>>, type_name: &str| {
+        entries
+            .iter()
+            .flatten()
+            .filter(|entry| entry.data.get_str() == type_name)
+            .count()
+    };
+
+    // Sem o bit DO a resposta nao traz assinaturas
+    let response = ask("www.example.com", QueryType::A, false);
+    assert_eq!(count(&response.data.response_values, "RRSIG"), 0);
+
+    // Com o bit DO o A e o NS da zona vem com os seus RRSIG
+    let response = ask("www.example.com", QueryType::A, true);
+    assert_eq!(response.get_edns().map(|edns| edns.dnssec_ok), Some(true));
+    assert_eq!(count(&response.data.response_values, "A"), 1);
+    assert_eq!(count(&response.data.response_values, "RRSIG"), 1);
+    assert_eq!(count(&response.data.authorities_values, "RRSIG"), 1);
+
+    let response = ask("example.com", QueryType::DNSKEY, true);
+    assert_eq!(count(&response.data.response_values, "DNSKEY"), 2);
+    assert_eq!(count(&response.data.response_values, "RRSIG"), 1);
+
+    // O NXDOMAIN e provado pelos NSEC que cobrem o nome e o wildcard
+    let response = ask("nothing.example.com", QueryType::A, true);
+    assert_eq!(response.header.response_code, Some(ResponseCode::NXDomain));
+    let nsec_count = count(&response.data.authorities_values, "NSEC");
+    assert!(nsec_count >= 1);
+    assert_eq!(
+        count(&response.data.authorities_values, "RRSIG"),
+        nsec_count + 1
+    );
+}
+
+#[test]
+fn test_dnssec_validation() {
+    let test_dir = std::env::temp_dir()
+        .join("rustcc-tests")
+        .join("dnssec-validation");
+    fs::create_dir_all(&test_dir).unwrap();
+    let ksk_path = test_dir.join("ksk.pk8");
+    let mut config = example_com_config();
+    for (entry, file) in [("KS", "ksk.pk8"), ("ZS", "zsk.pk8")] {
+        let key_path = test_dir.join(file);
+        let _ = fs::remove_file(&key_path);
+        config.push_str(&format!("example.com {} {}\n", entry, key_path.display()));
+    }
+    let server_addr = spawn_server(&config);
+
+    // A ancora e o DS da KSK criada pelo servidor, no formato da base de dados
+    let anchor_for = |ksk: &ZoneKey, file: &str| {
+        let ds = dns_dnssec::ds_for(&Domain::new("example.com.".to_string()), &ksk.get_dnskey())
+            .unwrap();
+        let anchor_path = test_dir.join(file);
+        fs::write(&anchor_path, format!("example.com. DS {} 3600\n", ds.get_value())).unwrap();
+        TrustAnchor::from_file(&anchor_path.to_string_lossy()).unwrap()
+    };
+    let ksk = ZoneKey::load_or_generate(&ksk_path.to_string_lossy(), ZONE_KEY_FLAG | SEP_FLAG)
+        .unwrap();
+    let anchor = anchor_for(&ksk, "anchor.db");
+    let server_list = vec![server_addr];
+    let resolve = |name: &str, query_type: QueryType, anchor: &TrustAnchor| {
+        let mut query =
+            client::query_builder(Domain::new(name.to_string()), query_type, Flags::QUERY);
+        validating_resolver(
+            &mut query,
+            server_list.to_owned(),
+            false,
+            Transport::Tcp.into(),
+            anchor,
+        )
+        .unwrap()
+    };
+
+    let response = resolve("www.example.com", QueryType::A, &anchor);
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert!(response.header.flags.contains(Flags::AUTHENTICATED));
+
+    let response = resolve("nothing.example.com", QueryType::A, &anchor);
+    assert_eq!(response.header.response_code, Some(ResponseCode::NXDomain));
+    assert!(response.header.flags.contains(Flags::AUTHENTICATED));
+
+    // Sem validacao a resposta nao leva a flag AD
+    let mut query = client::query_builder(
+        Domain::new("www.example.com".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    let response =
+        resolver_over(&mut query, server_list.to_owned(), false, Transport::Tcp).unwrap();
+    assert!(!response.header.flags.contains(Flags::AUTHENTICATED));
+
+    // Uma ancora que nao corresponde as chaves da zona da SERVFAIL
+    let other_ksk =
+        ZoneKey::from_pkcs8(&dns_dnssec::generate_pkcs8().unwrap(), ZONE_KEY_FLAG | SEP_FLAG)
+            .unwrap();
+    let response = resolve(
+        "www.example.com",
+        QueryType::A,
+        &anchor_for(&other_ksk, "wrong-anchor.db"),
+    );
+    assert_eq!(
+        response.header.response_code,
+        Some(ResponseCode::ServerFailure)
+    );
+    assert!(!response.header.flags.contains(Flags::AUTHENTICATED));
+    assert_eq!(response.data.response_values, None);
+}
+
+#[test]
+fn test_resolver_failure_servfail() {
+    // O unico servidor de topo nao responde, por isso os nomes fora das zonas nao tem resposta
+    let dir = std::env::temp_dir().join("rustcc-tests");
+    fs::create_dir_all(&dir).unwrap();
+    let root_path = dir.join(format!("{}-unreachable-root.db", std::process::id()));
+    fs::write(&root_path, "127.0.0.1:1\n").unwrap();
+    let config = example_com_config().replace(
+        "root ST etc/rootservers.db",
+        &format!("root ST {}", root_path.display()),
+    );
+    let server_addr = spawn_server(&config);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::new(10, 0))).unwrap();
+    let ask = |name: &str| {
+        let query =
+            client::query_builder(Domain::new(name.to_string()), QueryType::A, Flags::QUERY);
+        socket
+            .send_to(&dns_wire::encode(&query).unwrap(), server_addr)
+            .unwrap();
+        let mut buf = [0u8; 512];
+        let (size, _src_addr) = socket.recv_from(&mut buf).unwrap();
+        let response = dns_wire::decode(&buf[..size]).unwrap();
+        assert_eq!(response.header.message_id, query.header.message_id);
+        response
+    };
+
+    let response = ask("www.example.org");
+    assert_eq!(response.header.response_code, Some(ResponseCode::ServerFailure));
+    assert_eq!(response.data.response_values, None);
+
+    // A falha nao deixa a base de dados bloqueada: as zonas continuam a ser respondidas
+    let response = ask("www.example.com");
+    assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+    assert_eq!(
+        response.data.response_values.unwrap()[0].data,
+        RData::A(Ipv4Addr::new(10, 3, 3, 1))
+    );
+}
+
+#[test]
+fn test_case_randomization() {
+    let server_addr = spawn_server(&example_com_config());
+    let server_list = vec![server_addr];
+
+    // O servidor encontra o nome com qualquer mistura de maiusculas e repete-o tal como veio
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+    let query = client::query_builder(
+        Domain::new("wWw.ExAmPlE.cOm".to_string()),
+        QueryType::A,
+        Flags::QUERY,
+    );
+    dns_send::send(
+        query.to_owned(),
+        &socket,
+        server_list[0].to_string(),
+        dns_send::UDP_PAYLOAD_LIMIT,
+    )
+    .unwrap();
+    let (response, _) = dns_recv::recv(&socket).ok().unwrap();
+    assert_eq!(
+        response.data.query_info.name.getparts(),
+        query.data.query_info.name.getparts()
+    );
+    assert_eq!(response.header.number_of_values, Some(1));
+
+    // Com 0x20 o resolver aceita a resposta e devolve o nome da pergunta original, tambem por TCP
+    for transport in [Transport::Udp, Transport::Udp, Transport::Tcp, Transport::Tcp] {
+        let options = ResolverOptions {
+            transport,
+            randomize_case: true,
+            tsig_key: None,
+            key_cache: KeyCache::new(),
+        };
+        let mut query = client::query_builder(
+            Domain::new("www.example.com".to_string()),
+            QueryType::A,
+            Flags::QUERY,
+        );
+        let response =
+            resolver_with(&mut query, server_list.to_owned(), false, options.to_owned()).unwrap();
+        assert_eq!(response.header.response_code, Some(ResponseCode::NoError));
+        assert_eq!(
+            response.data.query_info.name,
+            Domain::new("www.example.com".to_string())
+        );
+        assert_eq!(response.header.number_of_values, Some(1));
+    }
+}